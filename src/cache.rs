@@ -0,0 +1,208 @@
+use crate::nomad::NomadClient;
+use anyhow::Result;
+use log::{trace, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The directory listings are cached in: `$XDG_CACHE_HOME/nquery`, falling back to
+/// `~/.cache/nquery`, or the temporary directory if there's no home directory.
+pub fn default_dir() -> PathBuf {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(env::temp_dir)
+        .join("nquery")
+}
+
+/// A client that answers requests for the job listing from files on disk while they're fresh, so
+/// back-to-back queries don't each list every job in the cluster. Every other request goes
+/// straight to the wrapped client.
+///
+/// What a listing holds depends on the ACL token it was fetched with, so listings are only shared
+/// between queries made with the same token, or with none.
+pub struct ListingCache<'a> {
+    client: &'a mut dyn NomadClient,
+    dir: PathBuf,
+    /// The address of the cluster, which keys the cache along with the listing's parameters
+    address: String,
+    /// The ACL token listings are fetched with, which also keys the cache
+    token: Option<String>,
+    ttl: Duration,
+}
+
+impl<'a> ListingCache<'a> {
+    /// Cache the listings fetched through a client.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The client used when there's no fresh listing cached
+    /// * `dir` - The directory to keep cached listings in
+    /// * `address` - The address of the cluster the client queries
+    /// * `token` - The ACL token the client queries with, if any
+    /// * `ttl` - How long a cached listing is reused for
+    pub fn new(
+        client: &'a mut dyn NomadClient,
+        dir: PathBuf,
+        address: &str,
+        token: Option<&str>,
+        ttl: Duration,
+    ) -> Self {
+        ListingCache {
+            client,
+            dir,
+            address: address.to_string(),
+            token: token.map(str::to_string),
+            ttl,
+        }
+    }
+
+    /// The file a listing is cached in. The resource carries the prefix and namespace, so each
+    /// combination of them with the address and token gets its own file. Only a hash of the token
+    /// ends up in the name.
+    fn path(&self, resource: &str) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        (&self.address, &self.token, resource).hash(&mut hasher);
+        self.dir
+            .join(format!("listing-{:016x}.json", hasher.finish()))
+    }
+
+    /// Read a cached listing, if it was written within the TTL.
+    fn read_fresh(&self, path: &Path) -> Option<String> {
+        let age = fs::metadata(path).ok()?.modified().ok()?.elapsed().ok()?;
+        if age >= self.ttl {
+            return None;
+        }
+        fs::read_to_string(path).ok()
+    }
+}
+
+impl NomadClient for ListingCache<'_> {
+    fn get(&mut self, resource: &str) -> Result<ureq::Response> {
+        if !resource.starts_with("jobs?") {
+            return self.client.get(resource);
+        }
+        let path = self.path(resource);
+        if let Some(body) = self.read_fresh(&path) {
+            trace!("Using the listing cached in {}", path.display());
+            return Ok(ureq::Response::new(200, "OK", &body));
+        }
+        let response = self.client.get(resource)?;
+        if response.status() != 200 {
+            return Ok(response);
+        }
+        let body = response.into_string()?;
+        if let Err(err) = fs::create_dir_all(&self.dir).and_then(|_| write_private(&path, &body)) {
+            warn!("Could not cache the listing in {}: {}", path.display(), err);
+        }
+        Ok(ureq::Response::new(200, "OK", &body))
+    }
+}
+
+/// Write a file only its owner can read, since the cache may be in the shared temporary directory
+/// and listings can reveal jobs the token is allowed to see.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files, so tighten one left by an earlier version too
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nomad::{self, test::TestClient};
+    use std::process;
+
+    #[test]
+    fn test_reuses_fresh_listing() {
+        let dir = env::temp_dir().join(format!("nquery-test-cache-{}", process::id()));
+        let mut client = TestClient::new(200, "OK", nomad::test::JOB_LISTING);
+        let address = "http://127.0.0.1:4646";
+        let ttl = Duration::from_secs(60);
+        let first = nomad::get_jobs(
+            &mut ListingCache::new(&mut client, dir.clone(), address, None, ttl),
+            "",
+            None,
+        );
+        assert_eq!(client.path, Some(String::from("jobs?prefix=")));
+        client.path = None;
+        let second = nomad::get_jobs(
+            &mut ListingCache::new(&mut client, dir.clone(), address, None, ttl),
+            "",
+            None,
+        );
+        // Served from the cache without touching the cluster
+        assert_eq!(client.path, None);
+        let other_address = nomad::get_jobs(
+            &mut ListingCache::new(&mut client, dir.clone(), "http://nomad:4646", None, ttl),
+            "",
+            None,
+        );
+        assert_eq!(client.path, Some(String::from("jobs?prefix=")));
+        client.path = None;
+        let expired = nomad::get_jobs(
+            &mut ListingCache::new(&mut client, dir.clone(), address, None, Duration::ZERO),
+            "",
+            None,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(client.path, Some(String::from("jobs?prefix=")));
+        assert_eq!(first.unwrap()[0].ID, "example");
+        assert_eq!(second.unwrap()[0].ID, "example");
+        assert!(other_address.is_ok());
+        assert!(expired.is_ok());
+    }
+
+    #[test]
+    fn test_keyed_by_token() {
+        let dir = env::temp_dir().join(format!("nquery-test-cache-token-{}", process::id()));
+        let mut client = TestClient::new(200, "OK", nomad::test::JOB_LISTING);
+        let address = "http://127.0.0.1:4646";
+        let ttl = Duration::from_secs(60);
+        let mut list = |token| {
+            client.path = None;
+            let mut cache = ListingCache::new(&mut client, dir.clone(), address, token, ttl);
+            nomad::get_jobs(&mut cache, "", None).unwrap();
+            client.path.is_some()
+        };
+        let fetched = [
+            list(Some("management")),
+            list(Some("management")),
+            list(Some("readonly")),
+            list(None),
+            list(None),
+        ];
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        #[cfg(unix)]
+        let modes: Vec<u32> = files
+            .iter()
+            .map(|path| {
+                use std::os::unix::fs::PermissionsExt;
+                fs::metadata(path).unwrap().permissions().mode() & 0o777
+            })
+            .collect();
+        let names: String = files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        // A listing fetched with one token is never served to a query with another, or none
+        assert_eq!(fetched, [true, false, true, true, false]);
+        assert!(!names.contains("management"));
+        #[cfg(unix)]
+        assert_eq!(modes, [0o600; 3]);
+    }
+}
@@ -0,0 +1,130 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// The exit code used when a query is cut short by Ctrl-C, following the shell's 128 + SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+/// The exit code used when a query is cut short by its deadline, following `timeout(1)`
+pub const DEADLINE_EXIT_CODE: i32 = 124;
+
+/// Set once SIGINT has been received while the handler is installed
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Catch Ctrl-C rather than letting it kill the process, so the jobs fetched so far can still be
+/// written out. Any request in flight is allowed to finish.
+pub fn install_handler() {
+    #[cfg(unix)]
+    unsafe {
+        let handler: extern "C" fn(libc::c_int) = on_interrupt;
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+/// Let Ctrl-C kill the process again, returning whether it was pressed while being caught.
+pub fn restore_default() -> bool {
+    #[cfg(unix)]
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Stop taking items once Ctrl-C has been pressed.
+///
+/// # Arguments
+///
+/// * `items` - The items to take, each typically costing a request
+pub fn until_interrupted<'a, I: Iterator + 'a>(items: I) -> impl Iterator<Item = I::Item> + 'a {
+    until_set(&INTERRUPTED, items)
+}
+
+/// Stop taking items once a deadline has passed, noting that it did in `exceeded`. The deadline is
+/// checked as each item is taken. Work on an item is expected to be bounded by the deadline itself,
+/// so if it has passed by the time the items run out, the last one was cut short and that's noted
+/// too.
+///
+/// # Arguments
+///
+/// * `items` - The items to take, each typically costing a request
+/// * `deadline` - When to stop, or `None` to take every item
+/// * `exceeded` - Set if items were left untaken because the deadline passed
+pub fn until_deadline<'a, I: Iterator + 'a>(
+    mut items: I,
+    deadline: Option<Instant>,
+    exceeded: &'a Cell<bool>,
+) -> impl Iterator<Item = I::Item> + 'a {
+    std::iter::from_fn(move || {
+        let item = items.next();
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            exceeded.set(true);
+            None
+        } else {
+            item
+        }
+    })
+}
+
+/// Stop taking items once a flag is set. The flag is checked before each item is taken, so an item
+/// that was already being produced when it was set is still returned.
+fn until_set<'a, I: Iterator + 'a>(
+    flag: &'a AtomicBool,
+    mut items: I,
+) -> impl Iterator<Item = I::Item> + 'a {
+    std::iter::from_fn(move || {
+        if flag.load(Ordering::SeqCst) {
+            None
+        } else {
+            items.next()
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_until_set() {
+        let flag = AtomicBool::new(false);
+        // Interrupted while the second item is being fetched
+        let items = (0..5).inspect(|&n| {
+            if n == 1 {
+                flag.store(true, Ordering::SeqCst);
+            }
+        });
+        let taken: Vec<_> = until_set(&flag, items).collect();
+        assert_eq!(taken, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_until_deadline() {
+        let exceeded = Cell::new(false);
+        let deadline = Instant::now() + Duration::from_millis(100);
+        // Each item takes 40ms to process, so the deadline passes a few items in
+        let taken: Vec<_> = until_deadline(0..10, Some(deadline), &exceeded)
+            .inspect(|_| std::thread::sleep(Duration::from_millis(40)))
+            .collect();
+        assert!(!taken.is_empty() && taken.len() < 10, "took {:?}", taken);
+        assert!(exceeded.get());
+
+        let exceeded = Cell::new(false);
+        assert_eq!(until_deadline(0..3, None, &exceeded).count(), 3);
+        assert!(!exceeded.get());
+
+        // The last item's work runs past the deadline
+        let exceeded = Cell::new(false);
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let taken: Vec<_> = until_deadline(0..1, Some(deadline), &exceeded)
+            .inspect(|_| std::thread::sleep(Duration::from_millis(80)))
+            .collect();
+        assert_eq!(taken, vec![0]);
+        assert!(exceeded.get());
+    }
+}
@@ -1,25 +1,100 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 extern crate jsonpath_lib as jsonpath;
+use glob::{MatchOptions, Pattern};
 use log::trace;
-use std::collections::HashMap;
-use std::{env, process};
+use output::{OutputFormat, OutputMode};
+use serde::Deserialize;
+use serde_json::json;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fs, process};
 use structopt::StructOpt;
 
+mod cache;
+mod interrupt;
 mod nomad;
+mod output;
+mod pager;
+mod report;
+mod schedule;
+mod suggest;
+mod tls;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "nquery", about = "Query and explore jobs on a Nomad cluster")]
 /// Query a Nomad cluster for jobs matching the provided parameters. The output can then be piped
 /// to tools, such as jq.
 ///
-/// nquery utilizes the `NOMAD_ADDR` environment variable to locate the Nomad cluster. If one is
-/// not defined, it defaults to localhost:4646 (the Nomad default).
+/// nquery utilizes the `NOMAD_ADDR` environment variable to locate the Nomad cluster, unless
+/// `--address` or `--address-file` is given. If none are defined, it defaults to localhost:4646
+/// (the Nomad default).
 struct Opt {
+    /// The address of the Nomad cluster, an http:// or https:// URL, overriding `NOMAD_ADDR`. May
+    /// contain `${VAR}` references to other environment variables.
+    #[structopt(long)]
+    address: Option<String>,
+
+    /// Read the address of the Nomad cluster from this file, overriding `NOMAD_ADDR`. --address
+    /// takes precedence
+    #[structopt(long, parse(from_os_str))]
+    address_file: Option<PathBuf>,
+
+    /// The ACL token to send with each request, overriding NOMAD_TOKEN
+    #[structopt(long)]
+    token: Option<String>,
+
+    /// Look up the ACL token before querying, and warn on stderr if it expires within
+    /// --token-expiry-warning
+    #[structopt(long)]
+    check_token: bool,
+
+    /// With --check-token, how soon the token must expire to be warned about, e.g. 1d or 2h
+    #[structopt(long, default_value = "1d", parse(try_from_str = humantime::parse_duration))]
+    token_expiry_warning: Duration,
+
+    /// Also print to stderr the nquery command that reproduces this query, with the cluster
+    /// address and option defaults resolved and any token redacted
+    #[structopt(long)]
+    print_command: bool,
+
+    /// When a job doesn't match nquery's model of it, also print the raw JSON around where decoding
+    /// failed, to help fix the mismatch
+    #[structopt(long)]
+    sample_errors: bool,
+
+    /// Reuse the job listing fetched by an earlier query for this many seconds, caching it on disk.
+    /// Speeds up running several queries in a row, at the cost of missing recent changes
+    #[structopt(long)]
+    cache_ttl: Option<u64>,
+
+    /// Fetch the job listing from the cluster even if --cache-ttl is given
+    #[structopt(long)]
+    no_cache: bool,
+
+    /// Query jobs in this namespace rather than the default one, or in every namespace with '*'
+    #[structopt(long)]
+    namespace: Option<String>,
+
     /// Return jobs with this status
     #[structopt(long)]
     status: Option<String>,
 
+    /// Leave out jobs with this status. Can be repeated to leave out several statuses
+    #[structopt(long, number_of_values = 1)]
+    status_not: Vec<String>,
+
+    /// Leave out dead jobs, i.e. --status-not dead
+    #[structopt(long)]
+    no_dead: bool,
+
+    /// Leave out jobs that have finished successfully: dead jobs whose allocations all completed
+    /// without any failing or being lost. Dead jobs that failed or were stopped are kept
+    #[structopt(long)]
+    no_complete: bool,
+
     /// Return periodic jobs
     #[structopt(long, conflicts_with = "no_periodic")]
     periodic: bool,
@@ -40,153 +115,5004 @@ struct Opt {
     #[structopt(long)]
     pretty: bool,
 
+    /// With --pretty, wrap string values onto continuation lines marked with an arrow so no line
+    /// is wider than this, for reading long templates or payloads in a terminal. Ignored when
+    /// stdout isn't a terminal, since the wrapped output is no longer valid JSON
+    #[structopt(long, requires = "pretty")]
+    wrap_strings: Option<usize>,
+
     /// Return jobs of this type
-    #[structopt(long = "type")]
+    #[structopt(name = "type", long = "type")]
     job_type: Option<String>,
 
+    /// Return jobs whose ID matches this glob pattern, e.g. '*-canary' or 'web-?'
+    #[structopt(long, parse(try_from_str = Pattern::new))]
+    glob: Option<Pattern>,
+
+    /// Return jobs that may run in this datacenter, including those that may run in any
+    #[structopt(long)]
+    datacenter: Option<String>,
+
+    /// Return jobs that may run in any datacenter, i.e. whose datacenters include '*'
+    #[structopt(long)]
+    any_datacenter: bool,
+
+    /// Return jobs whose status description contains this text, ignoring case, e.g. 'missing
+    /// drivers'
+    #[structopt(long)]
+    status_description_contains: Option<String>,
+
+    /// Return service jobs that should be running, but have no running allocations in any task
+    /// group. Batch and system jobs are never returned, since they're often legitimately idle
+    #[structopt(long)]
+    no_running: bool,
+
+    /// Return dispatched jobs that have finished with this outcome: complete if every allocation
+    /// completed, or failed if any failed or was lost
+    #[structopt(long, possible_values = &["complete", "failed"])]
+    dispatch_status: Option<nomad::DispatchStatus>,
+
+    /// Return jobs dispatched from a parameterized job
+    #[structopt(long)]
+    dispatched: bool,
+
+    /// Return only jobs that changed after the index saved in this file by --save-index, whether
+    /// their specification or their status. Every job is returned if the file doesn't exist yet
+    #[structopt(long, parse(from_os_str))]
+    since_index_file: Option<PathBuf>,
+
+    /// Save the highest modify index of the jobs written to this file, for a later
+    /// --since-index-file to return only the jobs changed since this query. It's only saved once
+    /// the jobs have been written, and held back below any job that was cut by --head or --tail
+    /// or failed to fetch, so a later query doesn't skip changes this one never reported. Reports
+    /// and other outputs that don't list the jobs leave it alone
+    #[structopt(long, parse(from_os_str))]
+    save_index: Option<PathBuf>,
+
+    /// Treat the job name as the whole job ID, ignoring case, rather than a prefix
+    #[structopt(long)]
+    exact: bool,
+
+    /// Return jobs reserving at least this much ephemeral disk (in MB) across all task groups
+    #[structopt(long)]
+    min_disk: Option<u64>,
+
+    /// Return jobs reserving at most this much ephemeral disk (in MB) across all task groups
+    #[structopt(long)]
+    max_disk: Option<u64>,
+
+    /// Return jobs wanting at least this many allocations running across all task groups
+    #[structopt(long)]
+    min_count: Option<u64>,
+
+    /// Return jobs wanting at most this many allocations running across all task groups
+    #[structopt(long)]
+    max_count: Option<u64>,
+
+    /// Return jobs whose specification has been updated at least this many times, i.e. whose
+    /// version is at least this
+    #[structopt(long)]
+    min_version: Option<u64>,
+
+    /// Return jobs whose version is at most this, e.g. 0 for jobs that have never been updated
+    #[structopt(long)]
+    max_version: Option<u64>,
+
+    /// Sort the matching jobs by this field, in ascending order. --head and --tail apply after
+    /// sorting
+    #[structopt(long, possible_values = &["ID", "SubmitTime", "Version"])]
+    sort: Option<SortKey>,
+
+    /// Return jobs with a task group allowing at least this many restart attempts
+    #[structopt(long)]
+    min_restart_attempts: Option<u64>,
+
+    /// Return jobs with a task group allowing at most this many restart attempts
+    #[structopt(long)]
+    max_restart_attempts: Option<u64>,
+
+    /// Return jobs exposing a port with this label, whether dynamic or reserved
+    #[structopt(long)]
+    port: Option<String>,
+
+    /// Return jobs with this metadata set, e.g. 'owner=ops'. Can be repeated to require several
+    #[structopt(long, number_of_values = 1)]
+    meta: Vec<FieldEq>,
+
+    /// Where to look for --meta: job, group, task, or any to match metadata set at any level
+    #[structopt(long, default_value = "job", possible_values = &["job", "group", "task", "any"])]
+    meta_scope: nomad::MetaScope,
+
+    /// Return jobs with a constraint on this attribute, e.g. 'attr.kernel.name' or
+    /// '${meta.rack}', whether placed on the job, a task group or a task
+    #[structopt(long)]
+    has_constraint: Option<String>,
+
+    /// Return jobs with a service health check of this type
+    #[structopt(long, possible_values = &["tcp", "http", "grpc", "script"])]
+    check_type: Option<String>,
+
+    /// Return jobs with a task that downloads artifacts
+    #[structopt(long)]
+    uses_artifacts: bool,
+
+    /// Return jobs with a task setting this environment variable. The variables themselves can
+    /// be output with e.g. --fields 'TaskGroups[*].Tasks[*].Env'
+    #[structopt(long)]
+    env_has: Option<String>,
+
+    /// Return jobs with a task group or task that waits after deregistering its services before
+    /// being killed
+    #[structopt(long)]
+    has_shutdown_delay: bool,
+
+    /// Return jobs spreading their allocations across node attribute values, at the job or task
+    /// group level
+    #[structopt(long)]
+    has_spread: bool,
+
+    /// Return jobs with an affinity for particular nodes, at the job or task group level
+    #[structopt(long)]
+    has_affinity: bool,
+
+    /// Return jobs with a task requesting this device, named as the job requests it, e.g.
+    /// 'nvidia/gpu'
+    #[structopt(long)]
+    uses_device: Option<String>,
+
+    /// Return jobs registering a Consul service with this tag. Can be repeated to match any of
+    /// several tags
+    #[structopt(long, number_of_values = 1)]
+    service_tag: Vec<String>,
+
+    /// Return periodic jobs launched on this cron schedule, e.g. '0 2 * * *'. Jobs whose periodic
+    /// spec isn't a cron expression never match
+    #[structopt(long, conflicts_with = "no-periodic")]
+    cron_matches: Option<String>,
+
+    /// Return multiregion jobs federated across exactly this many regions (0 for single-region jobs)
+    #[structopt(long)]
+    region_count: Option<usize>,
+
+    /// Return jobs whose top-level field equals a value, e.g. 'Priority=50' or 'Stop=false'. Can
+    /// be given multiple times, and every condition must hold.
+    #[structopt(long, number_of_values = 1)]
+    field_eq: Vec<FieldEq>,
+
+    /// Load filters from a TOML or JSON file, keyed by flag name (e.g. `status = "running"`). Flags
+    /// given on the command line take precedence.
+    #[structopt(long, parse(from_os_str))]
+    filter_file: Option<PathBuf>,
+
+    /// Return jobs with an allocation on this node, given by name, ID or ID prefix. Fetches each
+    /// matching job's allocations.
+    #[structopt(long)]
+    on_node: Option<String>,
+
+    /// Give up on connecting to the agent after this many seconds
+    #[structopt(long)]
+    connect_timeout: Option<u64>,
+
+    /// Give up on a request when the agent has sent nothing for this many seconds
+    #[structopt(long)]
+    read_timeout: Option<u64>,
+
+    /// Shorthand for setting both --connect-timeout and --read-timeout, which take precedence
+    #[structopt(long)]
+    timeout: Option<u64>,
+
+    /// Give up on fetching an individual job after this many seconds, skipping it, or failing
+    /// under --strict
+    #[structopt(long)]
+    job_timeout: Option<u64>,
+
+    /// Stop once this many seconds have passed since nquery started, write out the jobs fetched so
+    /// far, and exit with code 124. Every request, including the listing, is given up on at the
+    /// deadline, whatever --timeout allows
+    #[structopt(long)]
+    deadline: Option<u64>,
+
+    /// Follow at most this many redirects from the agent before failing, or 0 to not follow any
+    #[structopt(long, default_value = "5")]
+    max_redirects: u32,
+
+    /// Allow any server to answer, rather than only the leader. Reduces load on the leader, but
+    /// results may be slightly out of date
+    #[structopt(long)]
+    stale: bool,
+
+    /// If no jobs in the listing match, fetch it again up to this many times, waiting a couple of
+    /// seconds in between. Useful right after submitting a job. Retries are always answered by
+    /// the leader, even with --stale
+    #[structopt(long, default_value = "0")]
+    retry_on_empty: u32,
+
+    /// Skip jobs that fail to fetch, appending an entry listing them and why under `_errors`
+    #[structopt(long)]
+    report_errors: bool,
+
+    /// Skip jobs that fail to fetch, writing the list of them and why to this file
+    #[structopt(long, parse(from_os_str))]
+    error_file: Option<PathBuf>,
+
+    /// Count the matched jobs by how long ago they were submitted, instead of listing them
+    #[structopt(long)]
+    submit_histogram: bool,
+
+    /// Comma-separated upper bounds of the submission age buckets
+    #[structopt(long, default_value = "1h,1d,1w")]
+    histogram_buckets: report::AgeBuckets,
+
+    /// Attach each job's latest deployment, including its task group health, under a
+    /// `Deployment` key
+    #[structopt(long)]
+    with_deployment: bool,
+
+    /// Return jobs whose latest deployment is running but has unhealthy allocations past its
+    /// progress deadline
+    #[structopt(long)]
+    stuck_deployments: bool,
+
+    /// Print the matched job exactly as Nomad returned it, without any processing. The query must
+    /// match a single job
+    #[structopt(long)]
+    raw: bool,
+
+    /// Print only the number of matching jobs. Only filters on the job listing apply, since no
+    /// jobs are fetched, so filters on the full job need --force-full
+    #[structopt(long, conflicts_with_all = &["ids-only", "tree"])]
+    count: bool,
+
+    /// Print only the ID of each matching job, one per line, as plain text. No jobs are fetched, so
+    /// filters on the full job need --force-full
+    #[structopt(long, conflicts_with = "tree")]
+    ids_only: bool,
+
+    /// Print the matching jobs as an indented tree, with dispatched and periodic child jobs under
+    /// their parents. No jobs are fetched, so filters on the full job need --force-full
+    #[structopt(long)]
+    tree: bool,
+
+    /// Print a line summarizing each matching job, like 'example  service  running  dc1
+    /// prio=50', with the fields given by --fields if any. No jobs are fetched, so filters on the
+    /// full job need --force-full
+    #[structopt(long, conflicts_with_all = &["count", "ids-only", "tree"])]
+    oneline: bool,
+
+    /// With --count, --ids-only, --tree, --oneline or --output count-table, fetch every job anyway
+    /// so that filters on the full job, --on-node and --stuck-deployments apply too
+    #[structopt(long)]
+    force_full: bool,
+
+    /// Return only the first N matching jobs, stopping as soon as they've been found
+    #[structopt(long, conflicts_with = "tail")]
+    head: Option<usize>,
+
+    /// Return only the last N matching jobs
+    #[structopt(long, conflicts_with = "head")]
+    tail: Option<usize>,
+
+    /// Page output that doesn't fit in the terminal with this command, rather than `$PAGER` or
+    /// `less -R`
+    #[structopt(long)]
+    pager: Option<String>,
+
+    /// Never page output. Output is only paged when stdout is a terminal.
+    #[structopt(long, conflicts_with = "pager")]
+    no_pager: bool,
+
+    /// Interpret each of --fields as a JSON Pointer (RFC 6901), e.g. `/TaskGroups/0/Name`, rather
+    /// than as jsonpath
+    #[structopt(long)]
+    pointer: bool,
+
+    /// Fail if a field given to --fields isn't present in the first matching job, suggesting the
+    /// closest field that is, or if a job's fetch times out under --job-timeout. Not checked for
+    /// json-stream output.
+    #[structopt(long)]
+    strict: bool,
+
+    /// Write each job to its own file in this directory, named after its ID, and list the files
+    /// written instead of the jobs
+    #[structopt(long, parse(from_os_str))]
+    output_dir: Option<PathBuf>,
+
+    /// Compare the matching jobs with a previous run's output, saved as JSON or JSON lines, and
+    /// list the jobs added, removed and changed since instead of the jobs
+    #[structopt(long, parse(from_os_str))]
+    diff_against: Option<PathBuf>,
+
+    /// With --diff-against, exit with an error if anything has changed
+    #[structopt(long, requires = "diff-against")]
+    fail_on_diff: bool,
+
+    /// When exactly one job matches, print it on its own rather than in a single-item array
+    #[structopt(long)]
+    unwrap_single: bool,
+
+    /// Don't ask for confirmation before a query making a large number of requests. Confirmation
+    /// is only asked for on a terminal.
+    #[structopt(long)]
+    yes: bool,
+
+    /// Output format: json, json-stream for one fetch-annotated object per line,
+    /// ndjson-with-header for one object per line preceded by a line describing the fields, html
+    /// for a standalone report, env for a shell variable assignment per field (requires --fields),
+    /// template for a line per job rendered from --template, table for an aligned text table,
+    /// yaml-stream for a YAML document per job, csv for values separated by --delimiter, or
+    /// count-table for a table counting the jobs by --count-by
+    #[structopt(
+        long,
+        default_value = "json",
+        possible_values = &[
+            "json",
+            "json-stream",
+            "ndjson-with-header",
+            "html",
+            "env",
+            "template",
+            "table",
+            "yaml-stream",
+            "csv",
+            "count-table",
+        ]
+    )]
+    output: OutputMode,
+
+    /// With --output count-table, the one or two listing fields to count jobs by, e.g. Status for
+    /// a count of each status, or Status,Type for a row per status and a column per type. No jobs
+    /// are fetched, so filters on the full job need --force-full
+    #[structopt(long, default_value = "Status,Type")]
+    count_by: report::CountBy,
+
+    /// Also send the output to syslog: a message with how many jobs matched, then one per job.
+    /// Use --fields to choose what's logged for each job
+    #[structopt(long)]
+    syslog: bool,
+
+    /// The syslog facility to log under, e.g. daemon or local0
+    #[structopt(long, default_value = "user", parse(try_from_str = parse_facility))]
+    syslog_facility: syslog::Facility,
+
+    /// The tag to log under, identifying the monitor sending the messages
+    #[structopt(long, default_value = "nquery")]
+    syslog_tag: String,
+
+    /// The character separating values with --output csv, e.g. ';' for spreadsheets in locales
+    /// using decimal commas, or 'tab' for TSV
+    #[structopt(long, default_value = ",", parse(try_from_str = parse_delimiter))]
+    delimiter: char,
+
+    /// Output what this jsonpath expression selects from the matching jobs, rather than the jobs
+    /// themselves. With --output table, an expression selecting objects gives a row for each
+    #[structopt(long)]
+    jsonpath: Option<String>,
+
+    /// With --output template, the line written for each job. '{Field}' is replaced by the job's
+    /// field, '{duration Field}' formats a field holding nanoseconds as a duration, e.g. '5s', and
+    /// '{timestamp Field}' formats one holding nanoseconds since the epoch as a timestamp
+    #[structopt(long, required_if("output", "template"))]
+    template: Option<String>,
+
+    /// With --output template, write a shell script: start with '#!/bin/sh', and shell-quote
+    /// every value filled in, e.g. to review and then run --template 'nomad job stop {ID}'
+    #[structopt(long, requires = "template")]
+    shebang: bool,
+
+    /// With json-stream output, buffer up to this many bytes of output rather than writing each
+    /// job as soon as it's fetched. Speeds up streaming many jobs into a file or pipe
+    #[structopt(long, default_value = "0")]
+    buffer_size: usize,
+
+    /// Attach each job's entry from the job listing, including its summary counts, under a
+    /// `Listing` key
+    #[structopt(long)]
+    merge_listing: bool,
+
+    /// Only output the fields nquery explicitly models, dropping everything else Nomad returns
+    #[structopt(long)]
+    strip_extra: bool,
+
+    /// Order each job's fields canonically, with the fields nquery models first and everything
+    /// else sorted alphabetically, so output is stable and easy to diff
+    #[structopt(long)]
+    canonical_order: bool,
+
+    /// Drop fields that are null, empty strings, empty arrays or empty objects from each job,
+    /// along with any objects left empty by dropping them
+    #[structopt(long)]
+    compact_nulls: bool,
+
+    /// With --compact-nulls, keep fields that are empty strings
+    #[structopt(long, requires = "compact-nulls")]
+    keep_empty_strings: bool,
+
+    /// Check that each matching job can be decoded by nquery, instead of listing the jobs. Lists the
+    /// jobs that can't, with why, and exits with an error if there are any.
+    #[structopt(long)]
+    validate: bool,
+
+    /// List the schedule of each matching periodic job, including when it will next be launched,
+    /// instead of the jobs
+    #[structopt(long, conflicts_with = "no-periodic")]
+    periodic_summary: bool,
+
+    /// List the distinct images run by the matching jobs' tasks instead of the jobs
+    #[structopt(long)]
+    images: bool,
+
+    /// With --images, include how many of the matching jobs run each image
+    #[structopt(long, requires = "images")]
+    image_counts: bool,
+
+    /// List the services each matching job registers, with their port labels and tags, instead of
+    /// the jobs
+    #[structopt(long)]
+    services: bool,
+
+    /// List how each matching job's tasks are stopped (kill signal and timeout) and the lifecycle
+    /// hook each runs at, instead of the jobs
+    #[structopt(long)]
+    lifecycle_summary: bool,
+
+    /// Count each matching job's allocations by client status (running, complete, failed, lost)
+    /// instead of listing the jobs. Fetches each matching job's allocations
+    #[structopt(long)]
+    alloc_counts: bool,
+
+    /// List the matching jobs that can't be scheduled because their latest evaluation is blocked,
+    /// along with why, instead of listing the jobs. Only pending jobs and jobs with queued
+    /// allocations are checked, by fetching their evaluations
+    #[structopt(long)]
+    blocked: bool,
+
+    /// Summarize how each matching job's latest deployment is rolling out (complete, healthy,
+    /// unhealthy or running) instead of listing the jobs. Fetches each matching job's deployment
+    #[structopt(long)]
+    deployment: bool,
+
+    /// List every field path present in the matching jobs, in the form --fields accepts, instead
+    /// of the jobs
+    #[structopt(long)]
+    describe_fields: bool,
+
+    /// With --describe-fields, only list fields down to this depth, where 1 is top-level fields
+    #[structopt(long, requires = "describe-fields")]
+    describe_depth: Option<usize>,
+
+    /// List the namespaces in the cluster instead of jobs
+    #[structopt(long)]
+    namespaces: bool,
+
+    /// List the servers in the cluster and whether each is alive, instead of jobs
+    #[structopt(long)]
+    members: bool,
+
+    /// Show which server is answering, its version, and the cluster's current leader, instead of
+    /// jobs
+    #[structopt(long)]
+    cluster_info: bool,
+
+    /// Print the JSON Schema of the job listing and job fields nquery models, instead of jobs.
+    /// Fields outside the schema are still output, but can't be relied on
+    #[structopt(long)]
+    schema: bool,
+
     /// Include only these fields in the ouput
     #[structopt(short, long, number_of_values = 1)]
     fields: Vec<String>,
 
+    /// Rename fields in the output using a file mapping each field, as given to --fields, to the
+    /// key it should appear under, one 'field=name' per line
+    #[structopt(long, parse(from_os_str))]
+    rename_file: Option<PathBuf>,
+
     /// A prefix that the job name must match
     #[structopt(default_value = "")]
     job_name: String,
 }
 
-/// Get all jobs matching the supplied criteria
-///
-/// # Arguments
-///
-/// * `name_filter` - A string prefix that all job IDs must match
-/// * `status_filter` - If specified, all jobs must have a status equal to this
-/// * `job_type_filter` - If specified, all jobs must be of this type
-/// * `periodic_filter` - If specified, all jobs must be either periodic or not periodic
-/// * `parameterized_filter` - If specified, all jobs must be either parameterized or
-/// non-parameterized.
-fn get_jobs(
-    name_filter: &str,
-    status_filter: Option<String>,
-    job_type_filter: Option<String>,
-    periodic_filter: Option<bool>,
-    parameterized_filter: Option<bool>,
-) -> Result<Vec<nomad::Job>> {
-    let mut client = nomad::get_client();
-    let job_listing = nomad::get_jobs(&mut client, name_filter)?;
-    Ok(job_listing
-        .into_iter()
-        .filter(|job| match periodic_filter {
-            Some(is_periodic) => is_periodic == job.Periodic.unwrap_or(false),
-            None => true,
-        })
-        .filter(|job| match parameterized_filter {
-            Some(is_parameterized) => is_parameterized == job.ParameterizedJob.unwrap_or(false),
-            None => true,
-        })
-        .filter(|job| {
-            job.ID
-                .to_lowercase()
-                .starts_with(&name_filter.to_lowercase())
-        })
-        .filter(|job| match &status_filter {
-            Some(status) => job.Status.eq_ignore_ascii_case(&status),
-            None => true,
-        })
-        .filter(|job| match &job_type_filter {
-            Some(job_type) => job.Type.eq_ignore_ascii_case(&job_type),
-            None => true,
-        })
-        .map(|job| nomad::get_job(&mut client, &job.ID).unwrap())
-        .map(|job| {
-            trace!("Individual Job: {:#?}", job);
-            job
-        })
-        .collect())
+/// Shorthand names accepted by `--fields`, and the selectors they expand to
+const FIELD_ALIASES: &[(&str, &str)] = &[
+    ("EphemeralDiskMB", "TaskGroups[*].EphemeralDisk.SizeMB"),
+    ("Regions", "Multiregion.Regions[*].Name"),
+    ("DynamicPorts", "TaskGroups..DynamicPorts[*]"),
+    ("ReservedPorts", "TaskGroups..ReservedPorts[*]"),
+];
+
+/// The listing fields on each line of `--oneline` output, unless `--fields` is given
+const ONELINE_FIELDS: &[&str] = &["ID", "Type", "Status", "Datacenters", "Priority"];
+
+/// Short labels for fields shown as `label=value` in `--oneline` output. Other fields are
+/// labelled with their name in lowercase.
+const ONELINE_LABELS: &[(&str, &str)] = &[("Priority", "prio"), ("JobModifyIndex", "index")];
+
+/// A reusable set of filters loaded from a TOML or JSON file. Keys are named after the flags
+/// they stand in for, with `prefix` in place of the job name argument.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct FilterFile {
+    prefix: Option<String>,
+    status: Option<String>,
+    #[serde(rename = "type")]
+    job_type: Option<String>,
+    periodic: Option<bool>,
+    parameterized: Option<bool>,
+    glob: Option<String>,
+    min_disk: Option<u64>,
+    max_disk: Option<u64>,
+    min_count: Option<u64>,
+    max_count: Option<u64>,
+    min_restart_attempts: Option<u64>,
+    max_restart_attempts: Option<u64>,
+    region_count: Option<usize>,
+    field_eq: Vec<String>,
 }
 
-/// Build a ternary value from a combination of boolean values.
-///
-/// # Arguments
-///
-/// * `flag_tuple` - A tuple of boolean values, the first being the positive, and the second being
-/// the negative
-fn handle_negative_flags(flag_tuple: (bool, bool)) -> Option<bool> {
-    match flag_tuple {
-        (false, false) => None,
-        (true, false) => Some(true),
-        (false, true) => Some(false),
-        (_, _) => panic!("This shouldn't happen"),
+impl FilterFile {
+    /// Load filters from a file, parsed as JSON if it has a `.json` extension and TOML otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to load
+    fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| anyhow!("could not read {}: {}", path.display(), err))?;
+        let filters = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&contents)?
+        } else {
+            toml::from_str(&contents)?
+        };
+        Ok(filters)
     }
-}
 
-/// Run the thing!
-fn main() {
-    let _ = env_logger::Builder::new()
-        .parse_filters(&env::var("NQUERY_LOG").unwrap_or_default())
-        .try_init();
-    if cfg!(debug_assertions) {
-        color_backtrace::install();
+    /// Fill in any criteria not already set on the command line.
+    ///
+    /// # Arguments
+    ///
+    /// * `listing_filter` - The listing criteria given as flags
+    /// * `job_filter` - The job criteria given as flags
+    fn fill(self, listing_filter: &mut ListingFilter, job_filter: &mut JobFilter) -> Result<()> {
+        if listing_filter.name.is_empty() {
+            listing_filter.name = self.prefix.unwrap_or_default();
+        }
+        listing_filter.status = listing_filter.status.take().or(self.status);
+        listing_filter.job_type = listing_filter.job_type.take().or(self.job_type);
+        listing_filter.periodic = listing_filter.periodic.or(self.periodic);
+        listing_filter.parameterized = listing_filter.parameterized.or(self.parameterized);
+        if listing_filter.glob.is_none() {
+            listing_filter.glob = self.glob.as_deref().map(Pattern::new).transpose()?;
+        }
+        job_filter.min_disk = job_filter.min_disk.or(self.min_disk);
+        job_filter.max_disk = job_filter.max_disk.or(self.max_disk);
+        job_filter.min_count = job_filter.min_count.or(self.min_count);
+        job_filter.max_count = job_filter.max_count.or(self.max_count);
+        job_filter.min_restart_attempts = job_filter
+            .min_restart_attempts
+            .or(self.min_restart_attempts);
+        job_filter.max_restart_attempts = job_filter
+            .max_restart_attempts
+            .or(self.max_restart_attempts);
+        job_filter.region_count = job_filter.region_count.or(self.region_count);
+        if job_filter.field_eq.is_empty() {
+            job_filter.field_eq = self
+                .field_eq
+                .iter()
+                .map(|field_eq| field_eq.parse())
+                .collect::<Result<_>>()?;
+        }
+        Ok(())
     }
-    let cmd = Opt::from_args();
-    let periodic = handle_negative_flags((cmd.periodic, cmd.no_periodic));
-    let parameterized = handle_negative_flags((cmd.parameterized, cmd.no_parameterized));
-    let jobs: Vec<nomad::Job> = match get_jobs(
-        &cmd.job_name,
-        cmd.status,
-        cmd.job_type,
-        periodic,
-        parameterized,
-    ) {
-        Ok(found_jobs) => found_jobs,
-        Err(err) => {
-            eprintln!("{}", err.to_string());
-            process::exit(1);
+}
+
+/// How long to wait before fetching the listing again with `--retry-on-empty`
+const RETRY_ON_EMPTY_DELAY: Duration = Duration::from_secs(2);
+
+/// Queries expected to make more requests than this ask for confirmation first
+const CONFIRM_REQUESTS_ABOVE: usize = 500;
+
+/// Criteria that can be checked against the job listing, before any full jobs are fetched
+#[derive(Debug, Default)]
+struct ListingFilter {
+    /// A string prefix that all job IDs must match
+    name: String,
+    /// Whether job IDs must match `name` exactly, rather than only start with it
+    exact: bool,
+    /// If specified, all jobs must have a status equal to this
+    status: Option<String>,
+    /// Statuses no job may have
+    status_not: Vec<String>,
+    /// Whether jobs that finished successfully are left out
+    no_complete: bool,
+    /// If specified, all jobs must be of this type
+    job_type: Option<String>,
+    /// If specified, all jobs must be either periodic or not periodic
+    periodic: Option<bool>,
+    /// If specified, all jobs must be either parameterized or non-parameterized
+    parameterized: Option<bool>,
+    /// If specified, all job IDs must match this glob pattern
+    glob: Option<Pattern>,
+    /// If specified, all jobs must be able to run in this datacenter
+    datacenter: Option<String>,
+    /// Whether all jobs must be able to run in any datacenter
+    any_datacenter: bool,
+    /// If specified, text all jobs' status descriptions must contain
+    status_description_contains: Option<String>,
+    /// Whether all jobs must be live service jobs without any running allocations
+    no_running: bool,
+    /// If specified, how all jobs must have turned out, having been dispatched
+    dispatch_status: Option<nomad::DispatchStatus>,
+    /// If specified, the index all jobs must have changed after
+    modified_since: Option<u64>,
+    /// If specified, the namespace to list jobs in, or `*` for every namespace
+    namespace: Option<String>,
+}
+
+impl ListingFilter {
+    /// Check whether a listed job satisfies every criterion in the filter
+    fn matches(&self, job: &nomad::JobListing) -> bool {
+        match self.periodic {
+            Some(is_periodic) if is_periodic != job.Periodic.unwrap_or(false) => return false,
+            _ => {}
         }
-    };
-    let mut flattened = serde_json::to_value(&jobs).unwrap();
-    if !(&cmd.fields).is_empty() {
-        let mut paths: HashMap<String, String> = cmd
-            .fields
+        match self.parameterized {
+            Some(is_parameterized) if is_parameterized != job.ParameterizedJob.unwrap_or(false) => {
+                return false
+            }
+            _ => {}
+        }
+        if self.exact {
+            if !job.ID.eq_ignore_ascii_case(&self.name) {
+                return false;
+            }
+        } else if !job.ID.to_lowercase().starts_with(&self.name.to_lowercase()) {
+            return false;
+        }
+        let glob_options = MatchOptions {
+            case_sensitive: false,
+            ..Default::default()
+        };
+        match &self.glob {
+            Some(glob) if !glob.matches_with(&job.ID, glob_options) => return false,
+            _ => {}
+        }
+        match &self.status {
+            Some(status) if !job.Status.eq_ignore_ascii_case(status) => return false,
+            _ => {}
+        }
+        if self
+            .status_not
             .iter()
-            .map(|f| (String::from(f), format!("$.{}", f)))
-            .collect();
-        paths.insert("ID".into(), "$.ID".into());
-        let mut full_jobs: Vec<serde_json::Value> = Vec::new();
-        for job in jobs {
-            let mut job_view: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
-            for (path, selector) in &paths {
-                let job_json = serde_json::to_value(&job).unwrap();
-                let matches: Vec<&serde_json::Value> =
-                    jsonpath::select(&job_json, selector).unwrap();
-                for matched in matches {
-                    trace!("Match: {}, {}", path, matched);
-                    job_view.insert(path.to_string(), matched.to_owned());
-                }
+            .any(|status| job.Status.eq_ignore_ascii_case(status))
+        {
+            return false;
+        }
+        if self.no_complete && job.is_complete() {
+            return false;
+        }
+        match &self.job_type {
+            Some(job_type) if !job.Type.eq_ignore_ascii_case(job_type) => return false,
+            _ => {}
+        }
+        match &self.datacenter {
+            Some(datacenter) if !job.runs_in(datacenter) => return false,
+            _ => {}
+        }
+        if self.any_datacenter && !job.runs_anywhere() {
+            return false;
+        }
+        match &self.status_description_contains {
+            Some(text)
+                if !job
+                    .StatusDescription
+                    .to_lowercase()
+                    .contains(&text.to_lowercase()) =>
+            {
+                return false
             }
-            full_jobs.push(serde_json::to_value(job_view).unwrap());
+            _ => {}
         }
-        flattened = serde_json::to_value(full_jobs).unwrap();
-    }
-    if cmd.pretty {
-        println!("{}", serde_json::to_string_pretty(&flattened).unwrap());
-    } else {
-        println!("{}", serde_json::to_string(&flattened).unwrap());
+        if self.no_running {
+            let is_live_service = job.Type == "service" && job.Status != "dead";
+            if !is_live_service || job.running_count() != Some(0) {
+                return false;
+            }
+        }
+        if self.dispatch_status.is_some() && job.dispatch_status() != self.dispatch_status {
+            return false;
+        }
+        match self.modified_since {
+            Some(index) if job.ModifyIndex <= index => return false,
+            _ => {}
+        }
+        true
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Criteria that can only be checked once the full job definition has been fetched
+#[derive(Debug, Default, PartialEq)]
+struct JobFilter {
+    /// Bounds on the total ephemeral disk, in MB, reserved across all task groups
+    min_disk: Option<u64>,
+    max_disk: Option<u64>,
+    /// Bounds on the total number of allocations wanted across all task groups
+    min_count: Option<u64>,
+    max_count: Option<u64>,
+    /// Bounds on the job's version
+    min_version: Option<u64>,
+    max_version: Option<u64>,
+    /// Bounds that at least one task group's restart attempts must fall within
+    min_restart_attempts: Option<u64>,
+    max_restart_attempts: Option<u64>,
+    /// If specified, the number of regions a job must be federated across
+    region_count: Option<usize>,
+    /// If specified, the label of a port the job must expose
+    port: Option<String>,
+    /// Metadata the job must have set, at the level given by `meta_scope`
+    meta: Vec<FieldEq>,
+    meta_scope: nomad::MetaScope,
+    /// If specified, an attribute the job must have a constraint on
+    has_constraint: Option<String>,
+    /// If specified, the cron expression a periodic job must be launched on
+    cron_matches: Option<String>,
+    /// If specified, the type of health check one of the job's services must have
+    check_type: Option<String>,
+    /// Whether the job must have been dispatched from a parameterized job
+    dispatched: bool,
+    /// Whether one of the job's tasks must download artifacts
+    uses_artifacts: bool,
+    /// If specified, an environment variable one of the job's tasks must set
+    env_has: Option<String>,
+    /// Whether one of the job's task groups or tasks must have a shutdown delay
+    has_shutdown_delay: bool,
+    /// Whether the job or one of its task groups must define a spread
+    has_spread: bool,
+    /// Whether the job or one of its task groups must define an affinity
+    has_affinity: bool,
+    /// If specified, a device one of the job's tasks must request
+    uses_device: Option<String>,
+    /// Service tags, at least one of which the job must register a service with
+    service_tags: Vec<String>,
+    /// Top-level fields that must equal the given values
+    field_eq: Vec<FieldEq>,
+}
 
-    #[test]
-    fn test_handle_negative_flags_valid() {
+impl JobFilter {
+    /// Check whether the filter has no criteria, so every job satisfies it
+    fn is_empty(&self) -> bool {
+        *self
+            == JobFilter {
+                meta_scope: self.meta_scope,
+                ..Default::default()
+            }
+    }
+
+    /// Check whether a job satisfies every criterion in the filter
+    fn matches(&self, job: &nomad::Job) -> bool {
+        if !within_bounds(job.ephemeral_disk_mb(), self.min_disk, self.max_disk) {
+            return false;
+        }
+        if !within_bounds(job.desired_count(), self.min_count, self.max_count) {
+            return false;
+        }
+        if !within_bounds(job.Version, self.min_version, self.max_version) {
+            return false;
+        }
+        if (self.min_restart_attempts.is_some() || self.max_restart_attempts.is_some())
+            && !job.restart_attempts().into_iter().any(|attempts| {
+                within_bounds(
+                    attempts,
+                    self.min_restart_attempts,
+                    self.max_restart_attempts,
+                )
+            })
+        {
+            return false;
+        }
+        if self
+            .region_count
+            .is_some_and(|count| job.region_count() != count)
+        {
+            return false;
+        }
+        if let Some(label) = &self.port {
+            if !job.ports().iter().any(|port| port.Label == *label) {
+                return false;
+            }
+        }
+        if !self.meta.is_empty() {
+            let meta = job.meta(self.meta_scope);
+            let has_all = self.meta.iter().all(|expected| {
+                meta.iter()
+                    .any(|meta| meta.get(&expected.key) == Some(&expected.value))
+            });
+            if !has_all {
+                return false;
+            }
+        }
+        if let Some(attribute) = &self.has_constraint {
+            if !job
+                .constraints()
+                .iter()
+                .any(|constraint| constraint.references(attribute))
+            {
+                return false;
+            }
+        }
+        if let Some(expr) = &self.cron_matches {
+            let on_schedule = job
+                .Periodic
+                .as_ref()
+                .is_some_and(|periodic| schedule::cron_matches(periodic, expr));
+            if !on_schedule {
+                return false;
+            }
+        }
+        match &self.check_type {
+            Some(check_type) if !job.has_check_type(check_type) => return false,
+            _ => {}
+        }
+        if self.dispatched && !job.Dispatched {
+            return false;
+        }
+        if self.uses_artifacts && !job.uses_artifacts() {
+            return false;
+        }
+        if self.has_shutdown_delay && !job.has_shutdown_delay() {
+            return false;
+        }
+        if self.has_spread && !job.has_spread() {
+            return false;
+        }
+        if self.has_affinity && !job.has_affinity() {
+            return false;
+        }
+        match &self.env_has {
+            Some(key) if !job.sets_env(key) => return false,
+            _ => {}
+        }
+        match &self.uses_device {
+            Some(device) if !job.uses_device(device) => return false,
+            _ => {}
+        }
+        if !self.service_tags.is_empty() {
+            let tags = job.service_tags();
+            if !self
+                .service_tags
+                .iter()
+                .any(|tag| tags.contains(&tag.as_str()))
+            {
+                return false;
+            }
+        }
+        if !self.field_eq.is_empty() {
+            let job_json = serde_json::to_value(job).unwrap();
+            if !self
+                .field_eq
+                .iter()
+                .all(|field_eq| field_eq.matches(&job_json))
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// A condition that a job's top-level field, modeled or not, equals a value
+#[derive(Debug, PartialEq)]
+struct FieldEq {
+    key: String,
+    value: String,
+}
+
+impl FieldEq {
+    /// Check whether a job has the field, with a value equal to the expected one once it's been
+    /// coerced to the field's JSON type.
+    ///
+    /// # Arguments
+    ///
+    /// * `job_json` - The serialized job
+    fn matches(&self, job_json: &serde_json::Value) -> bool {
+        match job_json.get(&self.key) {
+            Some(serde_json::Value::String(actual)) => *actual == self.value,
+            Some(serde_json::Value::Number(actual)) => match self.value.parse::<f64>() {
+                Ok(expected) => actual.as_f64() == Some(expected),
+                Err(_) => false,
+            },
+            Some(serde_json::Value::Bool(actual)) => self.value.parse() == Ok(*actual),
+            Some(serde_json::Value::Null) => self.value == "null",
+            // Arrays and objects have to be given as JSON
+            Some(actual) => serde_json::from_str::<serde_json::Value>(&self.value)
+                .is_ok_and(|expected| *actual == expected),
+            None => false,
+        }
+    }
+}
+
+impl FromStr for FieldEq {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(FieldEq {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(anyhow!("expected key=value, got {}", s)),
+        }
+    }
+}
+
+/// A job matching the query, along with everything fetched about it
+#[derive(Debug)]
+struct MatchedJob {
+    /// The job's entry in the job listing
+    listing: nomad::JobListing,
+    /// The full job definition
+    job: nomad::Job,
+    /// Further data fetched about the job, added to its output under each key
+    attached: serde_json::Map<String, serde_json::Value>,
+}
+
+impl MatchedJob {
+    fn new(listing: nomad::JobListing, job: nomad::Job) -> Self {
+        MatchedJob {
+            listing,
+            job,
+            attached: serde_json::Map::new(),
+        }
+    }
+}
+
+/// A field the matched jobs can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    Id,
+    SubmitTime,
+    Version,
+}
+
+impl FromStr for SortKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ID" => Ok(SortKey::Id),
+            "SubmitTime" => Ok(SortKey::SubmitTime),
+            "Version" => Ok(SortKey::Version),
+            _ => Err(anyhow!("can't sort by {}", s)),
+        }
+    }
+}
+
+/// Sort matched jobs in ascending order of a field, keeping jobs that tie in their listing order.
+///
+/// # Arguments
+///
+/// * `matched` - The jobs to sort
+/// * `key` - The field to sort them by
+fn sort_jobs(matched: &mut [MatchedJob], key: SortKey) {
+    match key {
+        SortKey::Id => matched.sort_by(|a, b| a.listing.ID.cmp(&b.listing.ID)),
+        SortKey::SubmitTime => matched.sort_by_key(|matched| matched.listing.SubmitTime),
+        SortKey::Version => matched.sort_by_key(|matched| matched.job.Version),
+    }
+}
+
+/// Options controlling how each matched job is presented in the output
+#[derive(Debug, Default)]
+struct JobView {
+    /// If non-empty, the fields (or aliases) to project each job onto
+    fields: Vec<String>,
+    /// Attach the job's entry from the job listing under a `Listing` key
+    merge_listing: bool,
+    /// Leave out any fields that nquery doesn't explicitly model
+    strip_extra: bool,
+    /// Put the fields nquery models first, followed by the rest alphabetically
+    canonical_order: bool,
+    /// Interpret `fields` as JSON Pointers rather than jsonpath
+    pointer: bool,
+    /// The keys to output fields under, by the field they were selected with
+    renames: Vec<(String, String)>,
+    /// Drop fields with empty values
+    compact_nulls: bool,
+    /// Count empty strings as values worth keeping, when dropping empty fields
+    keep_empty_strings: bool,
+}
+
+impl JobView {
+    /// Serialize a job for output.
+    ///
+    /// # Arguments
+    ///
+    /// * `matched` - The job, along with everything fetched about it
+    fn render(&self, matched: &MatchedJob) -> serde_json::Value {
+        let job_json = self.serialize(matched);
+        let job_view = if self.fields.is_empty() {
+            job_json
+        } else {
+            project_fields(&job_json, &self.fields, self.pointer)
+        };
+        let job_view = rename_keys(job_view, &self.renames);
+        if self.compact_nulls {
+            drop_empty_fields(job_view, self.keep_empty_strings)
+        } else {
+            job_view
+        }
+    }
+
+    /// Project something identified by name rather than by ID, such as a namespace or a server,
+    /// onto the requested fields. Its name is always kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The serialized namespace or server
+    fn render_named(&self, value: serde_json::Value) -> serde_json::Value {
+        if self.fields.is_empty() {
+            return value;
+        }
+        let mut projected = project_fields(&value, &self.fields, self.pointer);
+        projected["Name"] = value["Name"].clone();
+        projected
+    }
+
+    /// Serialize a job, with everything fetched about it, before any fields are projected.
+    ///
+    /// # Arguments
+    ///
+    /// * `matched` - The job, along with everything fetched about it
+    fn serialize(&self, matched: &MatchedJob) -> serde_json::Value {
+        let mut job_json = if self.strip_extra {
+            matched.job.to_modeled_value().unwrap()
+        } else if self.canonical_order {
+            matched.job.to_canonical_value().unwrap()
+        } else {
+            serde_json::to_value(&matched.job).unwrap()
+        };
+        if self.merge_listing {
+            job_json["Listing"] = serde_json::to_value(&matched.listing).unwrap();
+        }
+        for (key, value) in &matched.attached {
+            job_json[key] = value.clone();
+        }
+        job_json
+    }
+}
+
+/// Check whether a value falls within an optional, inclusive, range.
+///
+/// # Arguments
+///
+/// * `value` - The value being checked
+/// * `min` - If specified, the value must be greater than or equal to this
+/// * `max` - If specified, the value must be less than or equal to this
+fn within_bounds(value: u64, min: Option<u64>, max: Option<u64>) -> bool {
+    min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+}
+
+/// Expand a `--fields` entry into the jsonpath selector used to look it up.
+///
+/// # Arguments
+///
+/// * `field` - A field name, dotted path, or one of the `FIELD_ALIASES`
+fn field_selector(field: &str) -> String {
+    let path = FIELD_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == field)
+        .map_or(field, |(_, path)| path);
+    format!("$.{}", path)
+}
+
+/// Check whether a jsonpath selector can match more than one value, i.e. it uses a wildcard,
+/// recursive descent, a filter, a slice or a union.
+///
+/// # Arguments
+///
+/// * `selector` - The jsonpath selector
+fn selects_many(selector: &str) -> bool {
+    if selector.contains('*') || selector.contains("..") {
+        return true;
+    }
+    selector.split('[').skip(1).any(|bracket| {
+        let inner = bracket.split(']').next().unwrap_or_default();
+        !inner.starts_with(['\'', '"']) && (inner.starts_with('?') || inner.contains([':', ',']))
+    })
+}
+
+/// Get the listing entries of all jobs matching the listing-level criteria
+///
+/// # Arguments
+///
+/// * `client` - The client used to query the cluster
+/// * `filter` - Criteria that every listed job must satisfy
+fn get_listing(
+    client: &mut dyn nomad::NomadClient,
+    filter: &ListingFilter,
+) -> Result<Vec<nomad::JobListing>> {
+    let started = Instant::now();
+    let job_listing = nomad::get_jobs(client, &filter.name, filter.namespace.as_deref())?;
+    trace!("Listing fetched in {:?}", started.elapsed());
+    let started = Instant::now();
+    let listing = job_listing
+        .into_iter()
+        .filter(|job| filter.matches(job))
+        .collect();
+    trace!("Listing filtered in {:?}", started.elapsed());
+    Ok(listing)
+}
+
+/// Fetch the job listing, fetching it again if no jobs match in case they've only just been
+/// submitted.
+///
+/// # Arguments
+///
+/// * `client` - The client used for the first fetch
+/// * `retry_client` - The client used for any retries, which should read from the leader
+/// * `filter` - Criteria applied to the listing
+/// * `retries` - The most times to fetch the listing again
+/// * `delay` - How long to wait before each retry
+/// * `deadline` - If specified, when to stop waiting, however long the delay
+fn get_listing_retrying(
+    client: &mut dyn nomad::NomadClient,
+    retry_client: &mut dyn nomad::NomadClient,
+    filter: &ListingFilter,
+    retries: u32,
+    delay: Duration,
+    deadline: Option<Instant>,
+) -> Result<Vec<nomad::JobListing>> {
+    let mut listing = get_listing(client, filter)?;
+    for attempt in 1..=retries {
+        if !listing.is_empty() {
+            break;
+        }
+        trace!("No jobs matched, retrying ({}/{})", attempt, retries);
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        std::thread::sleep(remaining.map_or(delay, |remaining| remaining.min(delay)));
+        listing = get_listing(retry_client, filter)?;
+    }
+    Ok(listing)
+}
+
+/// The outcome of fetching the full definitions of the listed jobs
+#[derive(Debug, Default)]
+struct FetchedJobs {
+    /// Jobs matching the criteria
+    jobs: Vec<MatchedJob>,
+    /// The IDs of jobs that were skipped, along with why their fetch failed
+    errors: Vec<(String, anyhow::Error)>,
+    /// The modify indices of the jobs that were skipped
+    skipped_indices: Vec<u64>,
+    /// How many listing entries were fetched, or failed to be, before stopping
+    attempted: usize,
+    /// Where the time fetching the jobs went
+    timings: FetchTimings,
+}
+
+/// How long each phase of fetching the listed jobs took, for tracing slow queries
+#[derive(Debug, Default)]
+struct FetchTimings {
+    /// The time taken to fetch and filter every job
+    total: Duration,
+    /// The time spent applying the job criteria
+    filtering: Duration,
+    /// The ID of the job that took longest to fetch, and how long it took
+    slowest: Option<(String, Duration)>,
+}
+
+impl FetchTimings {
+    /// Record how long fetching a job took, keeping it if it's the slowest so far
+    fn record_fetch(&mut self, id: &str, duration: Duration) {
+        if self
+            .slowest
+            .as_ref()
+            .is_none_or(|(_, slowest)| duration > *slowest)
+        {
+            self.slowest = Some((id.to_string(), duration));
+        }
+    }
+}
+
+/// Fetch the full definition of each listed job, keeping those matching the supplied criteria.
+///
+/// Jobs whose fetch is cut short by the deadline are always skipped and recorded in the errors, as
+/// are those whose fetch times out unless `strict` is set. Any other failure is returned as an
+/// error, unless `keep_going` is set, in which case it is recorded as well.
+///
+/// # Arguments
+///
+/// * `client` - The client used to fetch each job
+/// * `listing` - The listing entries of the jobs to fetch
+/// * `job_filter` - Criteria applied to the full job definitions
+/// * `keep_going` - Whether to skip jobs that fail to fetch, rather than failing entirely
+/// * `strict` - Whether a fetch timing out fails entirely, even with `keep_going`
+/// * `limit` - If specified, stop fetching once this many jobs have matched
+fn fetch_jobs(
+    client: &mut dyn nomad::NomadClient,
+    listing: impl IntoIterator<Item = nomad::JobListing>,
+    job_filter: &JobFilter,
+    keep_going: bool,
+    strict: bool,
+    limit: Option<usize>,
+) -> Result<FetchedJobs> {
+    let mut fetched = FetchedJobs::default();
+    let started = Instant::now();
+    for entry in listing {
+        if limit.is_some_and(|limit| fetched.jobs.len() >= limit) {
+            break;
+        }
+        fetched.attempted += 1;
+        let fetch_started = Instant::now();
+        let result = nomad::get_job(client, &entry.ID, &entry.Namespace);
+        fetched
+            .timings
+            .record_fetch(&entry.ID, fetch_started.elapsed());
+        let job = match result {
+            Ok(job) => job,
+            Err(err) if strict && err.is::<nomad::Timeout>() => return Err(err),
+            Err(err)
+                if keep_going
+                    || err.is::<nomad::Timeout>()
+                    || err.is::<nomad::DeadlineExceeded>() =>
+            {
+                fetched.skipped_indices.push(entry.ModifyIndex);
+                fetched.errors.push((entry.ID, err));
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        trace!("Individual Job: {:#?}", job);
+        let filter_started = Instant::now();
+        let matches = job_filter.matches(&job);
+        fetched.timings.filtering += filter_started.elapsed();
+        if matches {
+            fetched.jobs.push(MatchedJob::new(entry, job));
+        }
+    }
+    fetched.timings.total = started.elapsed();
+    trace!(
+        "Jobs fetched in {:?}, {:?} of it filtering",
+        fetched.timings.total,
+        fetched.timings.filtering
+    );
+    if let Some((id, duration)) = &fetched.timings.slowest {
+        trace!("Slowest job to fetch was {} in {:?}", id, duration);
+    }
+    Ok(fetched)
+}
+
+/// Keep only the last `n` items.
+///
+/// # Arguments
+///
+/// * `items` - The items to take from
+/// * `n` - How many items to keep
+fn take_tail<T>(mut items: Vec<T>, n: usize) -> Vec<T> {
+    let skip = items.len().saturating_sub(n);
+    items.split_off(skip)
+}
+
+/// List job IDs one per line, for feeding into shell loops.
+///
+/// # Arguments
+///
+/// * `listing` - The jobs whose IDs to list
+fn job_ids(listing: &[&nomad::JobListing]) -> String {
+    listing.iter().map(|job| format!("{}\n", job.ID)).collect()
+}
+
+/// Summarize each job on a line of its own, for eyeballing a cluster. Text fields are shown as
+/// they are, lists joined with commas, and anything else as `label=value`. Fields a job doesn't
+/// have are left out.
+///
+/// # Arguments
+///
+/// * `listing` - The jobs to summarize
+/// * `fields` - The listing fields (or aliases) on each line
+fn oneline(listing: &[&nomad::JobListing], fields: &[String]) -> String {
+    let mut lines = String::new();
+    for job in listing {
+        let view = project_fields(&serde_json::to_value(job).unwrap(), fields, false);
+        let parts: Vec<String> = view
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(field, value)| match value {
+                serde_json::Value::Null => None,
+                serde_json::Value::String(text) if text.is_empty() => None,
+                serde_json::Value::String(text) => Some(text.clone()),
+                serde_json::Value::Array(items) => Some(
+                    items
+                        .iter()
+                        .map(|item| {
+                            item.as_str()
+                                .map_or_else(|| item.to_string(), str::to_string)
+                        })
+                        .collect::<Vec<_>>()
+                        .join(","),
+                ),
+                value => {
+                    let label = ONELINE_LABELS
+                        .iter()
+                        .find(|(name, _)| name == field)
+                        .map_or_else(|| field.to_lowercase(), |(_, label)| label.to_string());
+                    Some(format!("{}={}", label, value))
+                }
+            })
+            .collect();
+        lines.push_str(&parts.join("  "));
+        lines.push('\n');
+    }
+    lines
+}
+
+/// Plain text outputs built only from the job listing. Rendering one can't fetch anything, so
+/// they never pay for fetching every full job unless asked to with `--force-full`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ListingOutput<'a> {
+    /// The number of jobs
+    Count,
+    /// Each job's ID
+    Ids,
+    /// The jobs nested under their parents
+    Tree,
+    /// A line summarizing each job, with these fields
+    Oneline(&'a [String]),
+    /// A table counting the jobs by these fields
+    CountTable(&'a [String]),
+}
+
+impl<'a> ListingOutput<'a> {
+    /// Pick the listing output requested, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - Whether `--count` was given
+    /// * `ids_only` - Whether `--ids-only` was given
+    /// * `tree` - Whether `--tree` was given
+    /// * `oneline` - The fields on each line, if `--oneline` was given
+    /// * `count_by` - The fields to count by, if `--output count-table` was given
+    fn from_flags(
+        count: bool,
+        ids_only: bool,
+        tree: bool,
+        oneline: Option<&'a [String]>,
+        count_by: Option<&'a [String]>,
+    ) -> Option<Self> {
+        if count {
+            Some(ListingOutput::Count)
+        } else if ids_only {
+            Some(ListingOutput::Ids)
+        } else if tree {
+            Some(ListingOutput::Tree)
+        } else if let Some(fields) = oneline {
+            Some(ListingOutput::Oneline(fields))
+        } else {
+            count_by.map(ListingOutput::CountTable)
+        }
+    }
+
+    /// The flag the output was requested with, for messages.
+    fn flag(self) -> &'static str {
+        match self {
+            ListingOutput::Count => "--count",
+            ListingOutput::Ids => "--ids-only",
+            ListingOutput::Tree => "--tree",
+            ListingOutput::Oneline(_) => "--oneline",
+            ListingOutput::CountTable(_) => "--output count-table",
+        }
+    }
+
+    /// Render the output for a set of listed jobs.
+    ///
+    /// # Arguments
+    ///
+    /// * `listing` - The jobs to render
+    fn render(self, listing: &[&nomad::JobListing]) -> String {
+        match self {
+            ListingOutput::Count => format!("{}\n", listing.len()),
+            ListingOutput::Ids => job_ids(listing),
+            ListingOutput::Tree => report::job_tree(listing),
+            ListingOutput::Oneline(fields) => oneline(listing, fields),
+            ListingOutput::CountTable(by) => {
+                let mut table = Vec::new();
+                output::Table
+                    .write(&report::count_table(listing, by), &mut table)
+                    .unwrap();
+                String::from_utf8(table).unwrap()
+            }
+        }
+    }
+}
+
+/// Check whether the listing output requested, if any, can be rendered from the job listing alone
+/// instead of after fetching every job. Criteria that are only checked on fetched jobs would
+/// silently match everything, so they're an error unless `--force-full` asks for the fetch.
+///
+/// # Arguments
+///
+/// * `output` - The listing output requested, if any
+/// * `force_full` - Whether `--force-full` was given
+/// * `job_filter` - The criteria checked on each full job
+/// * `on_node` - Whether `--on-node` was given
+/// * `stuck_deployments` - Whether `--stuck-deployments` was given
+fn renders_from_listing(
+    output: Option<ListingOutput>,
+    force_full: bool,
+    job_filter: &JobFilter,
+    on_node: bool,
+    stuck_deployments: bool,
+) -> Result<bool> {
+    let output = match output {
+        Some(output) if !force_full => output,
+        _ => return Ok(false),
+    };
+    if !job_filter.is_empty() || on_node || stuck_deployments {
+        return Err(anyhow!(
+            "{} only sees the job listing, so filters on the full job, --on-node and \
+             --stuck-deployments need --force-full to fetch every job",
+            output.flag()
+        ));
+    }
+    Ok(true)
+}
+
+/// Fetch the latest deployment of each job, attaching it under a `Deployment` key. Jobs whose
+/// deployment wasn't fetched before the deadline are dropped.
+///
+/// # Arguments
+///
+/// * `client` - The client used to fetch each deployment
+/// * `jobs` - The jobs whose deployments to fetch
+/// * `stuck_only` - Whether to keep only jobs whose deployment is stuck
+/// * `now` - The time to compare deployment progress deadlines against
+fn attach_deployments(
+    client: &mut dyn nomad::NomadClient,
+    jobs: impl IntoIterator<Item = MatchedJob>,
+    stuck_only: bool,
+    now: SystemTime,
+) -> Result<Vec<MatchedJob>> {
+    let mut with_deployments = Vec::new();
+    for mut matched in jobs {
+        let deployment =
+            match nomad::get_deployment(client, &matched.listing.ID, &matched.listing.Namespace) {
+                Ok(deployment) => deployment,
+                Err(err) if err.is::<nomad::DeadlineExceeded>() => continue,
+                Err(err) => return Err(err),
+            };
+        let is_stuck = deployment
+            .as_ref()
+            .is_some_and(|deployment| deployment.is_stuck(now));
+        if stuck_only && !is_stuck {
+            continue;
+        }
+        matched.attached.insert(
+            "Deployment".into(),
+            serde_json::to_value(&deployment).unwrap(),
+        );
+        with_deployments.push(matched);
+    }
+    Ok(with_deployments)
+}
+
+/// Keep only the jobs with an allocation placed on a node. Jobs whose allocations weren't fetched
+/// before the deadline are dropped, since they can't be checked.
+///
+/// # Arguments
+///
+/// * `client` - The client used to fetch each job's allocations
+/// * `jobs` - The jobs to check
+/// * `node` - The node's name, ID, or an ID prefix
+fn filter_on_node(
+    client: &mut dyn nomad::NomadClient,
+    jobs: impl IntoIterator<Item = MatchedJob>,
+    node: &str,
+) -> Result<Vec<MatchedJob>> {
+    let mut on_node = Vec::new();
+    for matched in jobs {
+        let allocations =
+            match nomad::get_allocations(client, &matched.listing.ID, &matched.listing.Namespace) {
+                Ok(allocations) => allocations,
+                Err(err) if err.is::<nomad::DeadlineExceeded>() => continue,
+                Err(err) => return Err(err),
+            };
+        if allocations
+            .iter()
+            .any(|allocation| allocation.is_on_node(node))
+        {
+            on_node.push(matched);
+        }
+    }
+    Ok(on_node)
+}
+
+/// Count each job's allocations by client status. Jobs whose allocations weren't fetched before
+/// the deadline are left out, so the counts fetched so far can still be written.
+///
+/// # Arguments
+///
+/// * `client` - The client used to fetch each job's allocations
+/// * `jobs` - The jobs whose allocations to count
+fn alloc_counts<'a>(
+    client: &mut dyn nomad::NomadClient,
+    jobs: impl IntoIterator<Item = &'a MatchedJob>,
+) -> Result<serde_json::Value> {
+    let mut counts = Vec::new();
+    for matched in jobs {
+        let allocations =
+            match nomad::get_allocations(client, &matched.listing.ID, &matched.listing.Namespace) {
+                Ok(allocations) => allocations,
+                Err(err) if err.is::<nomad::DeadlineExceeded>() => continue,
+                Err(err) => return Err(err),
+            };
+        counts.push(report::allocation_counts(&matched.listing.ID, &allocations));
+    }
+    Ok(json!(counts))
+}
+
+/// Summarize the rollout of each job's latest deployment, for a fleet-wide view during a release.
+/// Jobs whose deployment wasn't fetched before the deadline are left out.
+///
+/// # Arguments
+///
+/// * `client` - The client used to fetch each job's deployment
+/// * `listing` - The jobs to summarize
+fn deployment_statuses<'a>(
+    client: &mut dyn nomad::NomadClient,
+    listing: impl IntoIterator<Item = &'a nomad::JobListing>,
+) -> Result<serde_json::Value> {
+    let mut statuses = Vec::new();
+    for entry in listing {
+        let deployment = match nomad::get_deployment(client, &entry.ID, &entry.Namespace) {
+            Ok(deployment) => deployment,
+            Err(err) if err.is::<nomad::DeadlineExceeded>() => continue,
+            Err(err) => return Err(err),
+        };
+        statuses.push(report::deployment_status(&entry.ID, deployment.as_ref()));
+    }
+    Ok(json!(statuses))
+}
+
+/// Find the jobs whose latest evaluation is blocked, describing why each can't be placed. Only jobs
+/// the listing shows waiting on placements have their evaluations fetched, and those not fetched
+/// before the deadline are left out.
+///
+/// # Arguments
+///
+/// * `client` - The client used to fetch each job's evaluations
+/// * `listing` - The jobs to check
+fn blocked_jobs<'a>(
+    client: &mut dyn nomad::NomadClient,
+    listing: impl IntoIterator<Item = &'a nomad::JobListing>,
+) -> Result<serde_json::Value> {
+    let mut blocked = Vec::new();
+    let waiting = listing
+        .into_iter()
+        .filter(|entry| entry.Status == "pending" || entry.queued_count().unwrap_or(0) > 0);
+    for entry in waiting {
+        let evaluations = match nomad::get_evaluations(client, &entry.ID, &entry.Namespace) {
+            Ok(evaluations) => evaluations,
+            Err(err) if err.is::<nomad::DeadlineExceeded>() => continue,
+            Err(err) => return Err(err),
+        };
+        blocked.extend(report::blocked_evaluation(&entry.ID, &evaluations));
+    }
+    Ok(json!(blocked))
+}
+
+/// Fetch each listed job and check that it survives a round trip through nquery's typed model.
+///
+/// Returns the IDs of the jobs that couldn't be fetched or didn't survive, along with why. Running
+/// out of time isn't a failure of the job, so jobs not fetched before the deadline are left out.
+///
+/// # Arguments
+///
+/// * `client` - The client used to fetch each job
+/// * `listing` - The jobs to check
+fn validate_jobs(
+    client: &mut dyn nomad::NomadClient,
+    listing: impl IntoIterator<Item = nomad::JobListing>,
+) -> Vec<(String, anyhow::Error)> {
+    let mut failures = Vec::new();
+    for entry in listing {
+        let checked = nomad::get_job_raw(client, &entry.ID, &entry.Namespace)
+            .and_then(|raw| nomad::check_round_trip(&raw));
+        match checked {
+            Err(err) if err.is::<nomad::DeadlineExceeded>() => {}
+            Err(err) => failures.push((entry.ID, err)),
+            Ok(()) => {}
+        }
+    }
+    failures
+}
+
+/// Describe the jobs that failed to fetch, as reported by `--report-errors`.
+///
+/// # Arguments
+///
+/// * `errors` - The IDs of the jobs that failed, along with why
+fn error_report(errors: &[(String, anyhow::Error)]) -> serde_json::Value {
+    errors
+        .iter()
+        .map(|(id, err)| json!({"ID": id, "error": err.to_string()}))
+        .collect()
+}
+
+/// Fetch a single job, annotated with when it was fetched and how long the request took.
+///
+/// Returns `None` if the job was fetched but doesn't satisfy the filter. Failed fetches produce a
+/// record with an `error` field in place of `job`.
+///
+/// # Arguments
+///
+/// * `client` - The client used to query the cluster
+/// * `listing` - The listing entry of the job to fetch
+/// * `job_filter` - Criteria applied to the full job definition
+/// * `view` - How the fetched job is presented
+fn fetch_job_record(
+    client: &mut dyn nomad::NomadClient,
+    listing: nomad::JobListing,
+    job_filter: &JobFilter,
+    view: &JobView,
+) -> Option<serde_json::Value> {
+    let fetched_at = SystemTime::now();
+    let started = Instant::now();
+    let result = nomad::get_job(client, &listing.ID, &listing.Namespace);
+    let duration_ms = started.elapsed().as_millis() as u64;
+    let mut record = json!({
+        "id": listing.ID,
+        "fetched_at": humantime::format_rfc3339_millis(fetched_at).to_string(),
+        "duration_ms": duration_ms,
+    });
+    match result {
+        Ok(job) if !job_filter.matches(&job) => return None,
+        Ok(job) => record["job"] = view.render(&MatchedJob::new(listing, job)),
+        Err(err) => record["error"] = json!(err.to_string()),
+    }
+    Some(record)
+}
+
+/// Check that every requested field is present in a job, suggesting the closest valid field for
+/// any that aren't.
+///
+/// # Arguments
+///
+/// * `job_json` - The serialized job, before any fields are projected
+/// * `fields` - The fields (or aliases) requested
+/// * `pointer` - Whether the fields are JSON Pointers, for which no suggestions are made
+fn check_fields(job_json: &serde_json::Value, fields: &[String], pointer: bool) -> Result<()> {
+    let paths = suggest::field_paths(job_json);
+    let candidates = paths
+        .iter()
+        .map(String::as_str)
+        .chain(FIELD_ALIASES.iter().map(|(alias, _)| *alias));
+    let unknown: Vec<String> = fields
+        .iter()
+        .filter(|field| select_field(job_json, field, pointer).is_empty())
+        .map(|field| match suggest::closest(field, candidates.clone()) {
+            Some(suggestion) if !pointer => format!("{} (did you mean {}?)", field, suggestion),
+            _ => field.clone(),
+        })
+        .collect();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("unknown fields: {}", unknown.join(", ")))
+    }
+}
+
+/// Build a view of a job containing only the requested fields, along with its ID.
+///
+/// Fields appear in the order they were requested, after the ID unless it was requested
+/// explicitly. A field requested more than once is only included at its first position. Fields
+/// that can match several values, such as `TaskGroups[*].Name`, are always shown as an array of
+/// them, even when only one matched, so the shape doesn't depend on the job.
+///
+/// # Arguments
+///
+/// * `job_json` - The serialized job
+/// * `fields` - The fields (or aliases) to include in the view
+fn project_fields(
+    job_json: &serde_json::Value,
+    fields: &[String],
+    pointer: bool,
+) -> serde_json::Value {
+    let mut job_view: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
+    if !fields.iter().any(|f| f == "ID") {
+        if let Some(id) = job_json.get("ID") {
+            job_view.insert("ID".into(), id.clone());
+        }
+    }
+    for (i, field) in fields.iter().enumerate() {
+        if fields[..i].contains(field) {
+            continue;
+        }
+        let matches = select_field(job_json, field, pointer);
+        for matched in &matches {
+            trace!("Match: {}, {}", field, matched);
+        }
+        // A selector matching several values (e.g. across task groups) keeps them all
+        match matches.as_slice() {
+            [] => {}
+            [matched] if pointer || !selects_many(&field_selector(field)) => {
+                job_view.insert(field.to_string(), (*matched).to_owned());
+            }
+            _ => {
+                let all = matches.into_iter().cloned().collect();
+                job_view.insert(field.to_string(), serde_json::Value::Array(all));
+            }
+        }
+    }
+    serde_json::Value::Object(job_view)
+}
+
+/// Parse a rename file, made up of `field=name` lines. Blank lines and lines starting with `#` are
+/// ignored.
+///
+/// # Arguments
+///
+/// * `contents` - The contents of the rename file
+fn parse_renames(contents: &str) -> Result<Vec<(String, String)>> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| match line.rsplit_once('=') {
+            Some((field, name)) if !field.trim().is_empty() && !name.trim().is_empty() => {
+                Ok((field.trim().to_string(), name.trim().to_string()))
+            }
+            _ => Err(anyhow!(
+                "line {}: expected field=name, got '{}'",
+                number,
+                line
+            )),
+        })
+        .collect()
+}
+
+/// Load the renames given with `--rename-file`.
+///
+/// # Arguments
+///
+/// * `path` - The rename file
+fn load_renames(path: &Path) -> Result<Vec<(String, String)>> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| anyhow!("could not read {}: {}", path.display(), err))?;
+    parse_renames(&contents)
+}
+
+/// Parse the jobs from a previous run's output, written as a JSON array, a single job, or JSON
+/// lines.
+///
+/// # Arguments
+///
+/// * `contents` - The previous output
+fn parse_previous(contents: &str) -> Result<Vec<serde_json::Value>> {
+    match serde_json::from_str(contents) {
+        Ok(serde_json::Value::Array(jobs)) => Ok(jobs),
+        Ok(job) => Ok(vec![job]),
+        Err(_) => contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect(),
+    }
+}
+
+/// Rename the top-level keys of a view, keeping them in place.
+///
+/// # Arguments
+///
+/// * `view` - The job's view
+/// * `renames` - The new name for each key to rename
+fn rename_keys(view: serde_json::Value, renames: &[(String, String)]) -> serde_json::Value {
+    match view {
+        serde_json::Value::Object(map) if !renames.is_empty() => map
+            .into_iter()
+            .map(|(key, value)| {
+                let name = renames
+                    .iter()
+                    .find(|(field, _)| *field == key)
+                    .map_or(key, |(_, name)| name.clone());
+                (name, value)
+            })
+            .collect(),
+        view => view,
+    }
+}
+
+/// Recursively drop the fields of a view that hold null, an empty string, an empty array or an
+/// empty object. Objects and arrays are compacted first, so one left empty is dropped too. Array
+/// entries are kept, so positions within arrays don't shift.
+///
+/// # Arguments
+///
+/// * `view` - The job's view
+/// * `keep_empty_strings` - Whether fields holding empty strings are kept
+fn drop_empty_fields(view: serde_json::Value, keep_empty_strings: bool) -> serde_json::Value {
+    use serde_json::Value;
+    let is_empty = |value: &Value| match value {
+        Value::Null => true,
+        Value::String(text) => text.is_empty() && !keep_empty_strings,
+        Value::Array(items) => items.is_empty(),
+        Value::Object(map) => map.is_empty(),
+        _ => false,
+    };
+    match view {
+        Value::Object(map) => map
+            .into_iter()
+            .map(|(key, value)| (key, drop_empty_fields(value, keep_empty_strings)))
+            .filter(|(_, value)| !is_empty(value))
+            .collect(),
+        Value::Array(items) => items
+            .into_iter()
+            .map(|item| drop_empty_fields(item, keep_empty_strings))
+            .collect(),
+        view => view,
+    }
+}
+
+/// Look up the values a `--fields` entry selects from a job.
+///
+/// # Arguments
+///
+/// * `job_json` - The serialized job
+/// * `field` - A field name, dotted path, alias, or, with `pointer`, a JSON Pointer
+/// * `pointer` - Whether the field is a JSON Pointer (RFC 6901) rather than jsonpath
+fn select_field<'a>(
+    job_json: &'a serde_json::Value,
+    field: &str,
+    pointer: bool,
+) -> Vec<&'a serde_json::Value> {
+    if pointer {
+        job_json.pointer(field).into_iter().collect()
+    } else {
+        jsonpath::select(job_json, &field_selector(field)).unwrap_or_default()
+    }
+}
+
+/// Estimate how many requests fetching the matched jobs will make.
+///
+/// # Arguments
+///
+/// * `listed` - How many jobs matched the listing-level criteria
+/// * `limit` - If specified, the most jobs that will be fetched
+/// * `requests_per_job` - How many requests are made for each job fetched
+fn estimate_requests(listed: usize, limit: Option<usize>, requests_per_job: usize) -> usize {
+    limit.map_or(listed, |limit| limit.min(listed)) * requests_per_job
+}
+
+/// Ask for confirmation before making a large number of requests against the cluster.
+///
+/// # Arguments
+///
+/// * `estimated_requests` - How many requests the query is expected to make
+/// * `assume_yes` - Whether to go ahead without asking
+/// * `input` - Where the answer is read from
+/// * `prompt` - Where the question is written to
+fn confirm_fan_out(
+    estimated_requests: usize,
+    assume_yes: bool,
+    input: &mut dyn BufRead,
+    prompt: &mut dyn Write,
+) -> io::Result<bool> {
+    if assume_yes || estimated_requests <= CONFIRM_REQUESTS_ABOVE {
+        return Ok(true);
+    }
+    write!(
+        prompt,
+        "This query will make about {} requests to the cluster. Continue? [y/N] ",
+        estimated_requests
+    )?;
+    prompt.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Read the address of the Nomad cluster from a file, ignoring surrounding whitespace.
+///
+/// # Arguments
+///
+/// * `path` - The file holding the address
+fn read_address_file(path: &Path) -> Result<String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| anyhow!("could not read {}: {}", path.display(), err))?;
+    let address = contents.trim();
+    nomad::parse_address(address)
+        .map(|_| address.to_string())
+        .map_err(|err| anyhow!("{} doesn't hold a valid address: {}", path.display(), err))
+}
+
+/// Parse a CSV delimiter: a single character, or `tab`.
+fn parse_delimiter(delimiter: &str) -> Result<char> {
+    let mut chars = delimiter.chars();
+    match (chars.next(), chars.next()) {
+        _ if delimiter == "tab" || delimiter == "\\t" => Ok('\t'),
+        (Some(c), None) if c != '"' && c != '\n' && c != '\r' => Ok(c),
+        _ => Err(anyhow!(
+            "the delimiter must be a single character other than a quote or line break"
+        )),
+    }
+}
+
+/// Parse the name of a syslog facility, such as `user` or `local0`.
+fn parse_facility(name: &str) -> Result<syslog::Facility> {
+    syslog::Facility::from_str(name).map_err(|_| anyhow!("unknown syslog facility: {}", name))
+}
+
+/// Read the modify index saved by an earlier query, which is 0 if none has been saved yet so that
+/// every job counts as changed.
+///
+/// # Arguments
+///
+/// * `path` - The file the index was saved in
+fn read_index_file(path: &Path) -> Result<u64> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(anyhow!("could not read {}: {}", path.display(), err)),
+    };
+    contents
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("{} doesn't hold a modify index", path.display()))
+}
+
+/// The exit code for a query that failed: 124 if it ran out of time, like `timeout(1)`, or 1
+/// otherwise. A request the deadline cut short may have failed in other ways, e.g. reading a
+/// truncated body, so any failure once the deadline has passed counts.
+///
+/// # Arguments
+///
+/// * `err` - Why the query failed
+/// * `deadline` - The query's deadline, if it has one
+fn exit_code(err: &anyhow::Error, deadline: Option<Instant>) -> i32 {
+    if err.is::<nomad::DeadlineExceeded>()
+        || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    {
+        interrupt::DEADLINE_EXIT_CODE
+    } else {
+        1
+    }
+}
+
+/// The highest modify index among the jobs written, for a later query to pick up from. Jobs that
+/// should have been reported but weren't, e.g. because their fetch failed or `--head` cut them,
+/// hold the index back to just below theirs, so the later query returns them again. The index
+/// never goes backwards, so a query that wrote nothing keeps the one it started from.
+///
+/// `ModifyIndex` is used rather than `JobModifyIndex`, so jobs whose status changed count as
+/// changed, not just those whose specification did.
+///
+/// # Arguments
+///
+/// * `candidates` - The modify indices of the jobs the query should have reported
+/// * `written` - The modify indices of the jobs it did, which are among the candidates
+/// * `since` - The index the query started from, if any
+fn latest_index(candidates: &[u64], written: &[u64], since: Option<u64>) -> u64 {
+    let mut candidates = candidates.to_vec();
+    candidates.sort_unstable();
+    let mut written = written.to_vec();
+    written.sort_unstable();
+    // Both are sorted, so the first candidate not matched by a written job is the lowest unreported
+    let unreported = candidates
+        .iter()
+        .enumerate()
+        .find(|(i, index)| written.get(*i) != Some(index))
+        .map(|(_, index)| *index);
+    let latest = written.last().copied().unwrap_or(0);
+    let latest = match unreported {
+        Some(unreported) => latest.min(unreported.saturating_sub(1)),
+        None => latest,
+    };
+    latest.max(since.unwrap_or(0))
+}
+
+/// Save the modify index for a later `--since-index-file` query to pick up from.
+///
+/// # Arguments
+///
+/// * `path` - The file to save the index in
+/// * `index` - The index to save
+fn write_index_file(path: &Path, index: u64) -> Result<()> {
+    fs::write(path, format!("{}\n", index))
+        .map_err(|err| anyhow!("could not write {}: {}", path.display(), err))
+}
+
+/// A warning if the client's ACL token expires soon, or if it couldn't be looked up.
+///
+/// # Arguments
+///
+/// * `client` - The client whose token to look up
+/// * `threshold` - How soon the token must expire to be warned about
+/// * `now` - The time to measure the token's remaining lifetime from
+fn token_warning(
+    client: &mut dyn nomad::NomadClient,
+    threshold: Duration,
+    now: SystemTime,
+) -> Option<String> {
+    match nomad::get_token_self(client) {
+        Ok(token) => token.expires_within(threshold, now).map(|remaining| {
+            if remaining.is_zero() {
+                format!("Warning: the ACL token '{}' has expired", token.Name)
+            } else {
+                format!(
+                    "Warning: the ACL token '{}' expires in {}",
+                    token.Name,
+                    humantime::format_duration(Duration::from_secs(remaining.as_secs()))
+                )
+            }
+        }),
+        Err(err) => Some(format!("Warning: could not look up the ACL token: {}", err)),
+    }
+}
+
+/// Arguments whose values are secrets, which are redacted wherever the command line is echoed back
+const SECRET_ARGS: &[&str] = &["token"];
+
+/// The name of every option and flag nquery takes, which is also its long form. Keep this in step
+/// with `Opt`.
+const LONG_ARGS: &[&str] = &[
+    "address",
+    "address-file",
+    "token",
+    "check-token",
+    "token-expiry-warning",
+    "print-command",
+    "sample-errors",
+    "cache-ttl",
+    "no-cache",
+    "namespace",
+    "status",
+    "status-not",
+    "no-dead",
+    "no-complete",
+    "periodic",
+    "no-periodic",
+    "parameterized",
+    "no-parameterized",
+    "pretty",
+    "wrap-strings",
+    "type",
+    "glob",
+    "datacenter",
+    "any-datacenter",
+    "status-description-contains",
+    "no-running",
+    "dispatch-status",
+    "dispatched",
+    "since-index-file",
+    "save-index",
+    "exact",
+    "min-disk",
+    "max-disk",
+    "min-count",
+    "max-count",
+    "min-version",
+    "max-version",
+    "sort",
+    "min-restart-attempts",
+    "max-restart-attempts",
+    "port",
+    "meta",
+    "meta-scope",
+    "has-constraint",
+    "check-type",
+    "uses-artifacts",
+    "env-has",
+    "has-shutdown-delay",
+    "has-spread",
+    "has-affinity",
+    "uses-device",
+    "service-tag",
+    "cron-matches",
+    "region-count",
+    "field-eq",
+    "filter-file",
+    "on-node",
+    "connect-timeout",
+    "read-timeout",
+    "timeout",
+    "job-timeout",
+    "deadline",
+    "max-redirects",
+    "stale",
+    "retry-on-empty",
+    "report-errors",
+    "error-file",
+    "submit-histogram",
+    "histogram-buckets",
+    "with-deployment",
+    "stuck-deployments",
+    "raw",
+    "count",
+    "ids-only",
+    "tree",
+    "oneline",
+    "force-full",
+    "head",
+    "tail",
+    "pager",
+    "no-pager",
+    "pointer",
+    "strict",
+    "output-dir",
+    "diff-against",
+    "fail-on-diff",
+    "unwrap-single",
+    "yes",
+    "output",
+    "count-by",
+    "syslog",
+    "syslog-facility",
+    "syslog-tag",
+    "delimiter",
+    "jsonpath",
+    "template",
+    "shebang",
+    "buffer-size",
+    "merge-listing",
+    "strip-extra",
+    "canonical-order",
+    "compact-nulls",
+    "keep-empty-strings",
+    "validate",
+    "periodic-summary",
+    "images",
+    "image-counts",
+    "services",
+    "lifecycle-summary",
+    "alloc-counts",
+    "blocked",
+    "deployment",
+    "describe-fields",
+    "describe-depth",
+    "namespaces",
+    "members",
+    "cluster-info",
+    "schema",
+    "fields",
+    "rename-file",
+];
+
+/// Write an option and its value, quoted for the shell, redacting the value of a secret one.
+fn option_arg(name: &str, value: &str) -> String {
+    if SECRET_ARGS.contains(&name) {
+        format!("--{}=REDACTED", name)
+    } else {
+        format!("--{} {}", name, quote_arg(value))
+    }
+}
+
+/// The arguments of a query, rebuilt from how they were parsed rather than echoed. Those given on
+/// the command line come first, in the order they were given, followed by the defaults of options
+/// that weren't, so the query reads the same if the defaults change. Each option is written in its
+/// long form, with its value quoted for the shell, and the values of secret options are redacted.
+///
+/// # Arguments
+///
+/// * `matches` - The parsed command line
+/// * `skip` - The names of arguments to leave out
+fn given_args(matches: &structopt::clap::ArgMatches, skip: &[&str]) -> Vec<String> {
+    let mut given: Vec<(usize, String)> = Vec::new();
+    let mut defaults = Vec::new();
+    for &name in LONG_ARGS {
+        if skip.contains(&name) || !matches.is_present(name) {
+            continue;
+        }
+        let indices = matches.indices_of(name).into_iter().flatten();
+        // Flags have no values
+        let values = matches.values_of_lossy(name).unwrap_or_default();
+        if values.is_empty() {
+            given.extend(indices.map(|index| (index, format!("--{}", name))));
+        } else if matches.occurrences_of(name) == 0 {
+            defaults.extend(values.iter().map(|value| option_arg(name, value)));
+        } else {
+            given.extend(
+                indices
+                    .zip(values)
+                    .map(|(index, value)| (index, option_arg(name, &value))),
+            );
+        }
+    }
+    if matches.occurrences_of("job-name") > 0 {
+        let indices = matches.indices_of("job-name").into_iter().flatten();
+        let values = matches.values_of_lossy("job-name").unwrap_or_default();
+        given.extend(indices.zip(values.iter().map(|value| quote_arg(value))));
+    }
+    given.sort_by_key(|(index, _)| *index);
+    given
+        .into_iter()
+        .map(|(_, arg)| arg)
+        .chain(defaults)
+        .collect()
+}
+
+/// Rebuild the command that reproduces a query, pinning it to the cluster it ran against so it
+/// gives the same results wherever it's pasted. The values of secret options are redacted.
+///
+/// # Arguments
+///
+/// * `matches` - The command line nquery was run with, as parsed
+/// * `address` - The address the query ran against, however it was resolved
+fn reproducible_command(matches: &structopt::clap::ArgMatches, address: &str) -> String {
+    let mut command = vec![
+        String::from("nquery"),
+        String::from("--address"),
+        quote_arg(address),
+    ];
+    // The address is replaced by the one resolved
+    command.extend(given_args(
+        matches,
+        &["address", "address-file", "print-command"],
+    ));
+    command.join(" ")
+}
+
+/// Quote a command line argument for the shell, if it needs it.
+fn quote_arg(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@%+".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Look up an environment variable, treating one that isn't valid unicode as unset.
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+/// Expand `${VAR}` references in a string.
+///
+/// # Arguments
+///
+/// * `s` - The string to expand
+/// * `lookup` - Gets the value of a variable, or `None` if it isn't set
+fn expand_env(s: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated variable reference in {}", s))?;
+        let name = &rest[start + 2..start + end];
+        match lookup(name) {
+            Some(value) => expanded.push_str(&value),
+            None => return Err(anyhow!("environment variable {} is not set", name)),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Turn the rendered jobs into the output value: the job itself if there's exactly one, and an
+/// array otherwise.
+///
+/// # Arguments
+///
+/// * `rendered` - The rendered jobs
+fn unwrap_single(mut rendered: Vec<serde_json::Value>) -> serde_json::Value {
+    if rendered.len() == 1 {
+        rendered.pop().unwrap()
+    } else {
+        serde_json::Value::Array(rendered)
+    }
+}
+
+/// Build a ternary value from a combination of boolean values.
+///
+/// # Arguments
+///
+/// * `flag_tuple` - A tuple of boolean values, the first being the positive, and the second being
+///   the negative
+fn handle_negative_flags(flag_tuple: (bool, bool)) -> Option<bool> {
+    match flag_tuple {
+        (false, false) => None,
+        (true, false) => Some(true),
+        (false, true) => Some(false),
+        (_, _) => panic!("This shouldn't happen"),
+    }
+}
+
+/// Describe the cluster being queried: the agent answering requests and the current leader.
+///
+/// # Arguments
+///
+/// * `client` - The client used to query the cluster
+/// * `address` - The address the client was pointed at
+fn cluster_info(client: &mut dyn nomad::NomadClient, address: &str) -> Result<serde_json::Value> {
+    let agent = nomad::get_agent_self(client)?;
+    let leader = nomad::get_leader(client)?;
+    Ok(json!({
+        "Address": address,
+        "Server": agent.member.Name,
+        "ServerAddr": format!("{}:{}", agent.member.Addr, agent.member.Port),
+        "Region": agent.config.Region,
+        "Datacenter": agent.config.Datacenter,
+        "Version": agent.config.Version.to_string(),
+        "Leader": leader,
+    }))
+}
+
+/// Select the values matching a jsonpath expression from the output.
+///
+/// # Arguments
+///
+/// * `output` - The value that would otherwise be written
+/// * `expr` - The jsonpath expression, e.g. `$[*].TaskGroups[*].RestartPolicy`
+fn select_jsonpath(output: &serde_json::Value, expr: &str) -> Result<serde_json::Value> {
+    let selected = jsonpath::select(output, expr).map_err(|err| anyhow!("{:?}", err))?;
+    Ok(selected.into_iter().cloned().collect())
+}
+
+/// The raw JSON around where a job failed to decode, if that's why it couldn't be fetched.
+fn decode_sample(err: &anyhow::Error) -> Option<&str> {
+    err.downcast_ref::<nomad::DecodeError>()
+        .map(|err| err.snippet.as_str())
+}
+
+/// Serialize a value in the chosen format and write it to stdout, paging it if necessary
+///
+/// # Arguments
+///
+/// * `format` - The output format
+/// * `value` - The value to write
+/// * `pager` - The pager to send large output through, if any
+fn write_output(
+    format: &dyn OutputFormat,
+    value: &serde_json::Value,
+    pager: Option<&str>,
+) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    format.write(value, &mut buffer)?;
+    pager::write(&buffer, pager)
+}
+
+/// Serialize each value in the chosen format as it's produced, buffering up to `buffer_size` bytes
+/// of output between writes. Whatever is buffered is flushed at the end, or on failure.
+///
+/// # Arguments
+///
+/// * `format` - The output format
+/// * `values` - The values to write
+/// * `out` - Where to write them
+/// * `buffer_size` - How much output to buffer, or 0 to write each value straight through
+fn write_stream(
+    format: &dyn OutputFormat,
+    values: impl Iterator<Item = serde_json::Value>,
+    out: impl Write,
+    buffer_size: usize,
+) -> io::Result<()> {
+    let mut out = io::BufWriter::with_capacity(buffer_size, out);
+    for value in values {
+        if let Err(err) = format.write(&value, &mut out) {
+            let _ = out.flush();
+            return Err(err);
+        }
+        if buffer_size == 0 {
+            out.flush()?;
+        }
+    }
+    out.flush()
+}
+
+/// Run the thing!
+fn main() {
+    let _ = env_logger::Builder::new()
+        .parse_filters(&env::var("NQUERY_LOG").unwrap_or_default())
+        .try_init();
+    if cfg!(debug_assertions) {
+        color_backtrace::install();
+    }
+    let matches = Opt::clap().get_matches();
+    let cmd = Opt::from_clap(&matches);
+    let deadline = cmd
+        .deadline
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    let deadline_exceeded = std::cell::Cell::new(false);
+    let mut listing_filter = ListingFilter {
+        name: cmd.job_name,
+        exact: cmd.exact,
+        status: cmd.status,
+        status_not: cmd.status_not,
+        no_complete: cmd.no_complete,
+        job_type: cmd.job_type,
+        periodic: handle_negative_flags((cmd.periodic, cmd.no_periodic)),
+        parameterized: handle_negative_flags((cmd.parameterized, cmd.no_parameterized)),
+        glob: cmd.glob,
+        datacenter: cmd.datacenter,
+        any_datacenter: cmd.any_datacenter,
+        status_description_contains: cmd.status_description_contains,
+        no_running: cmd.no_running,
+        dispatch_status: cmd.dispatch_status,
+        modified_since: None,
+        namespace: cmd.namespace,
+    };
+    if cmd.no_dead {
+        listing_filter.status_not.push(String::from("dead"));
+    }
+    if let Some(path) = &cmd.since_index_file {
+        match read_index_file(path) {
+            Ok(index) => listing_filter.modified_since = Some(index),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+    let mut job_filter = JobFilter {
+        min_disk: cmd.min_disk,
+        max_disk: cmd.max_disk,
+        min_count: cmd.min_count,
+        max_count: cmd.max_count,
+        min_version: cmd.min_version,
+        max_version: cmd.max_version,
+        min_restart_attempts: cmd.min_restart_attempts,
+        max_restart_attempts: cmd.max_restart_attempts,
+        region_count: cmd.region_count,
+        port: cmd.port,
+        meta: cmd.meta,
+        meta_scope: cmd.meta_scope,
+        has_constraint: cmd.has_constraint,
+        cron_matches: cmd.cron_matches,
+        check_type: cmd.check_type,
+        dispatched: cmd.dispatched,
+        uses_artifacts: cmd.uses_artifacts,
+        has_shutdown_delay: cmd.has_shutdown_delay,
+        has_spread: cmd.has_spread,
+        has_affinity: cmd.has_affinity,
+        env_has: cmd.env_has,
+        uses_device: cmd.uses_device,
+        service_tags: cmd.service_tag,
+        field_eq: cmd.field_eq,
+    };
+    if let Some(path) = &cmd.filter_file {
+        if let Err(err) = FilterFile::load(path)
+            .and_then(|filters| filters.fill(&mut listing_filter, &mut job_filter))
+        {
+            eprintln!("Invalid filter file {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    }
+    if cmd.periodic_summary || job_filter.cron_matches.is_some() {
+        // Only periodic jobs have a schedule, so don't bother fetching the rest
+        listing_filter.periodic = Some(true);
+    }
+    let renames = match cmd.rename_file.as_deref().map(load_renames) {
+        Some(Ok(renames)) => renames,
+        Some(Err(err)) => {
+            eprintln!("Invalid rename file: {}", err);
+            process::exit(1);
+        }
+        None => Vec::new(),
+    };
+    let view = JobView {
+        fields: cmd.fields,
+        merge_listing: cmd.merge_listing,
+        strip_extra: cmd.strip_extra,
+        canonical_order: cmd.canonical_order,
+        pointer: cmd.pointer,
+        renames,
+        compact_nulls: cmd.compact_nulls,
+        keep_empty_strings: cmd.keep_empty_strings,
+    };
+    if cmd.output == OutputMode::Env && view.fields.is_empty() {
+        eprintln!("--output env needs the fields to write given with --fields");
+        process::exit(1);
+    }
+    let wrap_width = match cmd.wrap_strings {
+        Some(width) if cmd.output == OutputMode::Json && io::stdout().is_terminal() => Some(width),
+        _ => None,
+    };
+    let format: Box<dyn OutputFormat> = match wrap_width {
+        Some(width) => Box::new(output::WrappedJson { width }),
+        None => cmd.output.format(
+            cmd.pretty,
+            cmd.template.as_deref(),
+            cmd.delimiter,
+            cmd.shebang,
+            &given_args(&matches, &[]).join(" "),
+        ),
+    };
+    let pager = pager::pager_command(cmd.pager, cmd.no_pager);
+    if cmd.schema {
+        write_output(format.as_ref(), &nomad::model_schema(), pager.as_deref()).unwrap();
+        return;
+    }
+    let address = match cmd.address.as_deref().map(|address| {
+        expand_env(address, env_var)
+            .and_then(|address| nomad::parse_address(&address).map(|_| address))
+    }) {
+        Some(Ok(address)) => Some(address),
+        Some(Err(err)) => {
+            eprintln!("Invalid --address: {}", err);
+            process::exit(1);
+        }
+        None => match cmd.address_file.as_deref().map(read_address_file) {
+            Some(Ok(address)) => Some(address),
+            Some(Err(err)) => {
+                eprintln!("Invalid --address-file: {}", err);
+                process::exit(1);
+            }
+            None => None,
+        },
+    };
+    if address.is_none() {
+        if let Some(Err(err)) = env_var("NOMAD_ADDR").map(|address| nomad::parse_address(&address))
+        {
+            eprintln!("Invalid NOMAD_ADDR: {}", err);
+            process::exit(1);
+        }
+    }
+    let mut client = nomad::get_client(address)
+        .with_max_redirects(cmd.max_redirects)
+        .with_stale(cmd.stale);
+    if let Some(token) = cmd.token {
+        client = client.with_token(token);
+    }
+    match tls::TlsConfig::from_env(env_var) {
+        Ok(Some(tls)) => client = client.with_tls(tls),
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("Invalid TLS configuration: {}", err);
+            process::exit(1);
+        }
+    }
+    if let Some(seconds) = cmd.connect_timeout.or(cmd.timeout) {
+        client = client.with_connect_timeout(Duration::from_secs(seconds));
+    }
+    if let Some(seconds) = cmd.read_timeout.or(cmd.timeout) {
+        client = client.with_read_timeout(Duration::from_secs(seconds));
+    }
+    if let Some(deadline) = deadline {
+        client = client.with_deadline(deadline);
+    }
+    if cmd.check_token {
+        if let Some(warning) =
+            token_warning(&mut client, cmd.token_expiry_warning, SystemTime::now())
+        {
+            eprintln!("{}", warning);
+        }
+    }
+    if cmd.print_command {
+        eprintln!("{}", reproducible_command(&matches, client.address()));
+    }
+    let mut job_client = match cmd.job_timeout {
+        Some(seconds) => client.clone().with_timeout(Duration::from_secs(seconds)),
+        None => client.clone(),
+    };
+    if cmd.cluster_info {
+        let address = client.address().to_string();
+        let info = match cluster_info(&mut client, &address) {
+            Ok(info) => info,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(exit_code(&err, deadline));
+            }
+        };
+        write_output(format.as_ref(), &info, pager.as_deref()).unwrap();
+        return;
+    }
+    if cmd.namespaces {
+        let namespaces = match nomad::get_namespaces(&mut client) {
+            Ok(namespaces) => namespaces,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(exit_code(&err, deadline));
+            }
+        };
+        let output: serde_json::Value = namespaces
+            .iter()
+            .map(|namespace| view.render_named(serde_json::to_value(namespace).unwrap()))
+            .collect();
+        write_output(format.as_ref(), &output, pager.as_deref()).unwrap();
+        return;
+    }
+    if cmd.members {
+        let members = match nomad::get_members(&mut client) {
+            Ok(members) => members,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(exit_code(&err, deadline));
+            }
+        };
+        let output: serde_json::Value = members
+            .iter()
+            .map(|member| view.render_named(serde_json::to_value(member).unwrap()))
+            .collect();
+        write_output(format.as_ref(), &output, pager.as_deref()).unwrap();
+        return;
+    }
+    let mut retry_client = client.clone().with_stale(false);
+    let cache_ttl = if cmd.no_cache { None } else { cmd.cache_ttl };
+    let cluster_address = client.address().to_string();
+    let cluster_token = client.token().map(str::to_string);
+    let mut listing_client = client.clone();
+    let mut listing_cache;
+    let listing_client: &mut dyn nomad::NomadClient = match cache_ttl {
+        Some(seconds) => {
+            listing_cache = cache::ListingCache::new(
+                &mut listing_client,
+                cache::default_dir(),
+                &cluster_address,
+                cluster_token.as_deref(),
+                Duration::from_secs(seconds),
+            );
+            &mut listing_cache
+        }
+        None => &mut listing_client,
+    };
+    let listing = match get_listing_retrying(
+        listing_client,
+        &mut retry_client,
+        &listing_filter,
+        cmd.retry_on_empty,
+        RETRY_ON_EMPTY_DELAY,
+        deadline,
+    ) {
+        Ok(listing) => listing,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(exit_code(&err, deadline));
+        }
+    };
+    // The index is only saved once the jobs are written, so a query that fails or is cut short
+    // leaves it alone and the next one returns what this one missed
+    let (index_path, since_index) = (cmd.save_index.clone(), listing_filter.modified_since);
+    let save_index = move |candidates: &[u64], written: &[u64]| {
+        if let Some(path) = &index_path {
+            let index = latest_index(candidates, written, since_index);
+            if let Err(err) = write_index_file(path, index) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    };
+    if cmd.raw {
+        let raw = match listing.as_slice() {
+            [job] => nomad::get_job_raw(&mut job_client, &job.ID, &job.Namespace),
+            _ => Err(anyhow!(
+                "--raw requires exactly one job to match, but {} did",
+                listing.len()
+            )),
+        };
+        match raw {
+            Ok(body) => pager::write(format!("{}\n", body).as_bytes(), pager.as_deref()).unwrap(),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(exit_code(&err, deadline));
+            }
+        }
+        return;
+    }
+    if cmd.submit_histogram {
+        let histogram =
+            report::submit_histogram(&listing, &cmd.histogram_buckets.0, SystemTime::now());
+        write_output(format.as_ref(), &histogram, pager.as_deref()).unwrap();
+        return;
+    }
+    let count_by = if cmd.output == OutputMode::CountTable {
+        Some(cmd.count_by.0.as_slice())
+    } else {
+        None
+    };
+    let oneline_fields: Vec<String> = if view.fields.is_empty() {
+        ONELINE_FIELDS
+            .iter()
+            .map(|field| field.to_string())
+            .collect()
+    } else {
+        view.fields.clone()
+    };
+    let oneline_fields = if cmd.oneline {
+        Some(oneline_fields.as_slice())
+    } else {
+        None
+    };
+    let listing_output =
+        ListingOutput::from_flags(cmd.count, cmd.ids_only, cmd.tree, oneline_fields, count_by);
+    let from_listing = match renders_from_listing(
+        listing_output,
+        cmd.force_full,
+        &job_filter,
+        cmd.on_node.is_some(),
+        cmd.stuck_deployments,
+    ) {
+        Ok(from_listing) => from_listing,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    if let Some(listing_output) = listing_output.filter(|_| from_listing) {
+        let candidates: Vec<u64> = listing.iter().map(|job| job.ModifyIndex).collect();
+        let mut listing: Vec<&nomad::JobListing> = listing.iter().collect();
+        if let Some(head) = cmd.head {
+            listing.truncate(head);
+        }
+        if let Some(tail) = cmd.tail {
+            listing = take_tail(listing, tail);
+        }
+        let rendered = listing_output.render(&listing);
+        pager::write(rendered.as_bytes(), pager.as_deref()).unwrap();
+        let written: Vec<u64> = listing.iter().map(|job| job.ModifyIndex).collect();
+        save_index(&candidates, &written);
+        return;
+    }
+    if cmd.blocked {
+        let jobs = interrupt::until_deadline(listing.iter(), deadline, &deadline_exceeded);
+        match blocked_jobs(&mut job_client, jobs) {
+            Ok(blocked) => write_output(format.as_ref(), &blocked, pager.as_deref()).unwrap(),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(exit_code(&err, deadline));
+            }
+        }
+        if deadline_exceeded.get() {
+            process::exit(interrupt::DEADLINE_EXIT_CODE);
+        }
+        return;
+    }
+    if cmd.deployment {
+        interrupt::install_handler();
+        let jobs = interrupt::until_deadline(
+            interrupt::until_interrupted(listing.iter()),
+            deadline,
+            &deadline_exceeded,
+        );
+        let statuses = deployment_statuses(&mut job_client, jobs);
+        let interrupted = interrupt::restore_default();
+        match statuses {
+            Ok(statuses) => write_output(format.as_ref(), &statuses, pager.as_deref()).unwrap(),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(exit_code(&err, deadline));
+            }
+        }
+        if interrupted {
+            process::exit(interrupt::EXIT_CODE);
+        }
+        if deadline_exceeded.get() {
+            process::exit(interrupt::DEADLINE_EXIT_CODE);
+        }
+        return;
+    }
+    // Jobs may still be dropped or reordered after fetching, so only stop early if none will be
+    let fetch_limit = if cmd.stuck_deployments || cmd.on_node.is_some() || cmd.sort.is_some() {
+        None
+    } else {
+        cmd.head
+    };
+    let fetch_deployments = cmd.with_deployment || cmd.stuck_deployments;
+    let requests_per_job =
+        1 + usize::from(fetch_deployments) + usize::from(cmd.on_node.is_some() || cmd.alloc_counts);
+    let estimated_requests = estimate_requests(listing.len(), fetch_limit, requests_per_job);
+    let interactive = io::stdin().is_terminal() && io::stderr().is_terminal();
+    match confirm_fan_out(
+        estimated_requests,
+        cmd.yes || !interactive,
+        &mut io::stdin().lock(),
+        &mut io::stderr(),
+    ) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+    if cmd.validate {
+        let jobs = interrupt::until_deadline(listing.into_iter(), deadline, &deadline_exceeded);
+        let failures = validate_jobs(&mut job_client, jobs);
+        write_output(format.as_ref(), &error_report(&failures), pager.as_deref()).unwrap();
+        if deadline_exceeded.get() {
+            process::exit(interrupt::DEADLINE_EXIT_CODE);
+        }
+        if !failures.is_empty() {
+            process::exit(1);
+        }
+        return;
+    }
+    if cmd.output == OutputMode::JsonStream {
+        if cmd.tail.is_some() {
+            eprintln!("--tail needs the full result, so can't be used with json-stream output");
+            process::exit(1);
+        }
+        interrupt::install_handler();
+        let listed: Vec<u64> = listing.iter().map(|job| job.ModifyIndex).collect();
+        let (mut attempted, mut candidates, mut written) = (0, Vec::new(), Vec::new());
+        let records = interrupt::until_deadline(
+            interrupt::until_interrupted(listing.into_iter()),
+            deadline,
+            &deadline_exceeded,
+        )
+        .filter_map(|job| {
+            let index = job.ModifyIndex;
+            attempted += 1;
+            let record = fetch_job_record(&mut job_client, job, &job_filter, &view)?;
+            candidates.push(index);
+            if record.get("error").is_none() {
+                written.push(index);
+            }
+            Some(record)
+        })
+        .take(cmd.head.unwrap_or(usize::MAX));
+        write_stream(
+            format.as_ref(),
+            records,
+            io::stdout().lock(),
+            cmd.buffer_size,
+        )
+        .unwrap();
+        if interrupt::restore_default() {
+            process::exit(interrupt::EXIT_CODE);
+        }
+        if deadline_exceeded.get() {
+            process::exit(interrupt::DEADLINE_EXIT_CODE);
+        }
+        candidates.extend(&listed[attempted..]);
+        save_index(&candidates, &written);
+        return;
+    }
+    let report_errors = cmd.report_errors || cmd.error_file.is_some();
+    interrupt::install_handler();
+    let listed: Vec<u64> = listing.iter().map(|job| job.ModifyIndex).collect();
+    let fetched = match fetch_jobs(
+        &mut job_client,
+        interrupt::until_deadline(
+            interrupt::until_interrupted(listing.into_iter()),
+            deadline,
+            &deadline_exceeded,
+        ),
+        &job_filter,
+        report_errors,
+        cmd.strict,
+        fetch_limit,
+    ) {
+        Ok(fetched) => fetched,
+        Err(err) => {
+            eprintln!("{}", err);
+            match decode_sample(&err) {
+                Some(sample) if cmd.sample_errors => eprintln!("Decoding failed near: {}", sample),
+                _ => {}
+            }
+            process::exit(exit_code(&err, deadline));
+        }
+    };
+    if cmd.sample_errors {
+        for (id, err) in &fetched.errors {
+            if let Some(sample) = decode_sample(err) {
+                eprintln!("Decoding {} failed near: {}", id, sample);
+            }
+        }
+    }
+    let interrupted = interrupt::restore_default();
+    if interrupted {
+        eprintln!(
+            "Interrupted, only writing the {} matching jobs fetched so far",
+            fetched.jobs.len()
+        );
+    } else if deadline_exceeded.get() {
+        eprintln!(
+            "Deadline exceeded, only writing the {} matching jobs fetched so far",
+            fetched.jobs.len()
+        );
+    }
+    let mut matched = fetched.jobs;
+    if let Some(node) = &cmd.on_node {
+        let jobs = interrupt::until_deadline(matched.into_iter(), deadline, &deadline_exceeded);
+        matched = match filter_on_node(&mut job_client, jobs, node) {
+            Ok(matched) => matched,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(exit_code(&err, deadline));
+            }
+        };
+    }
+    if fetch_deployments {
+        matched = match attach_deployments(
+            &mut job_client,
+            interrupt::until_deadline(matched.into_iter(), deadline, &deadline_exceeded),
+            cmd.stuck_deployments,
+            SystemTime::now(),
+        ) {
+            Ok(matched) => matched,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(exit_code(&err, deadline));
+            }
+        };
+    }
+    if let Some(key) = cmd.sort {
+        sort_jobs(&mut matched, key);
+    }
+    // Jobs that failed to fetch, weren't fetched or are cut below all hold the saved index back
+    let candidates: Vec<u64> = (fetched.skipped_indices.iter())
+        .chain(&listed[fetched.attempted..])
+        .chain(matched.iter().map(|matched| &matched.listing.ModifyIndex))
+        .copied()
+        .collect();
+    if let Some(head) = cmd.head {
+        matched.truncate(head);
+    }
+    if let Some(tail) = cmd.tail {
+        matched = take_tail(matched, tail);
+    }
+    let written: Vec<u64> = matched
+        .iter()
+        .map(|matched| matched.listing.ModifyIndex)
+        .collect();
+    if let Some(listing_output) = listing_output {
+        let listing: Vec<&nomad::JobListing> =
+            matched.iter().map(|matched| &matched.listing).collect();
+        pager::write(listing_output.render(&listing).as_bytes(), pager.as_deref()).unwrap();
+        if !interrupted && !deadline_exceeded.get() {
+            save_index(&candidates, &written);
+        }
+        return;
+    }
+    if cmd.periodic_summary {
+        let summary = report::periodic_summary(
+            matched.iter().map(|matched| &matched.job),
+            SystemTime::now(),
+        );
+        write_output(format.as_ref(), &summary, pager.as_deref()).unwrap();
+        return;
+    }
+    if cmd.describe_fields {
+        let mut paths = std::collections::BTreeSet::new();
+        for matched in &matched {
+            paths.extend(suggest::field_paths_to_depth(
+                &view.serialize(matched),
+                cmd.describe_depth,
+            ));
+        }
+        write_output(format.as_ref(), &json!(paths), pager.as_deref()).unwrap();
+        return;
+    }
+    if cmd.services {
+        let services = report::service_summary(matched.iter().map(|matched| &matched.job));
+        write_output(format.as_ref(), &services, pager.as_deref()).unwrap();
+        return;
+    }
+    if cmd.lifecycle_summary {
+        let summary = report::lifecycle_summary(matched.iter().map(|matched| &matched.job));
+        write_output(format.as_ref(), &summary, pager.as_deref()).unwrap();
+        return;
+    }
+    if cmd.alloc_counts {
+        interrupt::install_handler();
+        let jobs = interrupt::until_deadline(
+            interrupt::until_interrupted(matched.iter()),
+            deadline,
+            &deadline_exceeded,
+        );
+        let counts = match alloc_counts(&mut job_client, jobs) {
+            Ok(counts) => counts,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(exit_code(&err, deadline));
+            }
+        };
+        let interrupted = interrupt::restore_default();
+        write_output(format.as_ref(), &counts, pager.as_deref()).unwrap();
+        if interrupted {
+            process::exit(interrupt::EXIT_CODE);
+        }
+        if deadline_exceeded.get() {
+            process::exit(interrupt::DEADLINE_EXIT_CODE);
+        }
+        return;
+    }
+    if cmd.images {
+        let images =
+            report::image_summary(matched.iter().map(|matched| &matched.job), cmd.image_counts);
+        write_output(format.as_ref(), &images, pager.as_deref()).unwrap();
+        return;
+    }
+    match matched.first() {
+        Some(first) if cmd.strict => {
+            if let Err(err) = check_fields(&view.serialize(first), &view.fields, view.pointer) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        _ => {}
+    }
+    let mut rendered: Vec<serde_json::Value> =
+        matched.iter().map(|matched| view.render(matched)).collect();
+    if let Some(path) = &cmd.diff_against {
+        let previous = fs::read_to_string(path)
+            .map_err(|err| anyhow!("could not read {}: {}", path.display(), err))
+            .and_then(|contents| parse_previous(&contents));
+        let previous = match previous {
+            Ok(previous) => previous,
+            Err(err) => {
+                eprintln!("Invalid --diff-against: {}", err);
+                process::exit(1);
+            }
+        };
+        for (id, err) in &fetched.errors {
+            eprintln!("Skipping {}: {}", id, err);
+        }
+        let diff = report::diff_jobs(&previous, &rendered);
+        write_output(format.as_ref(), &diff, pager.as_deref()).unwrap();
+        if cmd.fail_on_diff && report::has_differences(&diff) {
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(dir) = &cmd.output_dir {
+        let ids = matched.iter().map(|matched| matched.listing.ID.as_str());
+        let extension = cmd.output.extension();
+        match output::write_files(dir, ids.zip(&rendered), format.as_ref(), extension) {
+            // The jobs are in their files, so list where they went instead
+            Ok(written) => {
+                rendered = written
+                    .iter()
+                    .map(|path| json!(path.to_string_lossy()))
+                    .collect()
+            }
+            Err(err) => {
+                eprintln!("Could not write to {}: {}", dir.display(), err);
+                process::exit(1);
+            }
+        }
+    }
+    let mut unwrap = cmd.unwrap_single;
+    if let Some(path) = &cmd.error_file {
+        let report = serde_json::to_string_pretty(&error_report(&fetched.errors)).unwrap();
+        if let Err(err) = fs::write(path, report) {
+            eprintln!("Could not write {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    } else if report_errors {
+        // The errors entry sits alongside the jobs, so the result has to stay an array
+        unwrap = false;
+        rendered.push(json!({ "_errors": error_report(&fetched.errors) }));
+    } else {
+        for (id, err) in &fetched.errors {
+            eprintln!("Skipping {}: {}", id, err);
+        }
+    }
+    let output = if unwrap {
+        unwrap_single(rendered)
+    } else {
+        serde_json::Value::Array(rendered)
+    };
+    let output = match cmd
+        .jsonpath
+        .as_deref()
+        .map(|expr| select_jsonpath(&output, expr))
+    {
+        Some(Ok(selected)) => selected,
+        Some(Err(err)) => {
+            eprintln!("Invalid --jsonpath: {}", err);
+            process::exit(1);
+        }
+        None => output,
+    };
+    if let Err(err) = write_output(format.as_ref(), &output, pager.as_deref()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    if !interrupted && !deadline_exceeded.get() {
+        save_index(&candidates, &written);
+    }
+    if cmd.syslog {
+        let formatter = syslog::Formatter3164 {
+            facility: cmd.syslog_facility,
+            hostname: None,
+            process: cmd.syslog_tag,
+            pid: process::id(),
+        };
+        let logged = syslog::unix(formatter)
+            .map_err(|err| anyhow!("could not connect to syslog: {}", err))
+            .and_then(|mut logger| {
+                output::send_to_syslog(&mut logger, &output::syslog_messages(&output))
+            });
+        if let Err(err) = logged {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+    if interrupted {
+        process::exit(interrupt::EXIT_CODE);
+    }
+    if deadline_exceeded.get() {
+        process::exit(interrupt::DEADLINE_EXIT_CODE);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nomad::test::{Reply, RoutedClient};
+
+    #[test]
+    fn test_handle_negative_flags_valid() {
         assert_eq!(Some(true), handle_negative_flags((true, false)));
         assert_eq!(None, handle_negative_flags((false, false)));
         assert_eq!(Some(false), handle_negative_flags((false, true)));
     }
 
     #[test]
-    #[should_panic]
-    fn handle_negative_flags_invalid() {
-        handle_negative_flags((true, true));
+    #[should_panic]
+    fn handle_negative_flags_invalid() {
+        handle_negative_flags((true, true));
+    }
+
+    #[test]
+    fn test_filter_file() {
+        let filters: FilterFile = toml::from_str(
+            r#"
+            prefix = "web"
+            status = "running"
+            type = "service"
+            periodic = false
+            glob = "*-canary"
+            min-disk = 100
+            field-eq = ["Priority=50", "Region=global"]
+            "#,
+        )
+        .unwrap();
+        // Flags given on the command line win over the file
+        let mut listing_filter = ListingFilter {
+            status: Some(String::from("pending")),
+            ..Default::default()
+        };
+        let mut job_filter = JobFilter {
+            max_disk: Some(500),
+            ..Default::default()
+        };
+        filters.fill(&mut listing_filter, &mut job_filter).unwrap();
+        assert_eq!(listing_filter.name, "web");
+        assert_eq!(listing_filter.status, Some(String::from("pending")));
+        assert_eq!(listing_filter.job_type, Some(String::from("service")));
+        assert_eq!(listing_filter.periodic, Some(false));
+        assert_eq!(listing_filter.parameterized, None);
+        assert_eq!(listing_filter.glob, Some(Pattern::new("*-canary").unwrap()));
+        assert_eq!(job_filter.min_disk, Some(100));
+        assert_eq!(job_filter.max_disk, Some(500));
+        assert_eq!(
+            job_filter.field_eq,
+            vec![
+                "Priority=50".parse().unwrap(),
+                "Region=global".parse::<FieldEq>().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_previous() {
+        let jobs = vec![json!({"ID": "example"}), json!({"ID": "other"})];
+        assert_eq!(
+            parse_previous(r#"[{"ID": "example"}, {"ID": "other"}]"#).unwrap(),
+            jobs
+        );
+        assert_eq!(
+            parse_previous("{\"ID\":\"example\"}\n{\"ID\":\"other\"}\n").unwrap(),
+            jobs
+        );
+        assert_eq!(
+            parse_previous(r#"{"ID": "example"}"#).unwrap(),
+            vec![json!({"ID": "example"})]
+        );
+        assert!(parse_previous("not json").is_err());
+    }
+
+    #[test]
+    fn test_read_address_file() {
+        let path = env::temp_dir().join(format!("nquery-test-address-{}", process::id()));
+        fs::write(&path, "  https://nomad.example.com:4646\n").unwrap();
+        let address = read_address_file(&path);
+        fs::write(&path, "nomad.example.com\n").unwrap();
+        let invalid = read_address_file(&path);
+        fs::write(&path, "http://nomad example.com\n").unwrap();
+        let whitespace = read_address_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(address.unwrap(), "https://nomad.example.com:4646");
+        assert!(invalid
+            .unwrap_err()
+            .to_string()
+            .contains("doesn't hold a valid address: 'nomad.example.com' is not a URL"));
+        assert!(whitespace.is_err());
+        assert!(read_address_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_parse_delimiter() {
+        assert_eq!(parse_delimiter(";").unwrap(), ';');
+        assert_eq!(parse_delimiter("tab").unwrap(), '\t');
+        assert_eq!(parse_delimiter("\t").unwrap(), '\t');
+        assert!(parse_delimiter(";;").is_err());
+        assert!(parse_delimiter("\"").is_err());
+        assert!(parse_delimiter("").is_err());
+    }
+
+    #[test]
+    fn test_index_file() {
+        let path = env::temp_dir().join(format!("nquery-test-index-{}", process::id()));
+        assert_eq!(read_index_file(&path).unwrap(), 0);
+        let listing = [listing()];
+        write_index_file(&path, latest_index(&[410], &[410], Some(400))).unwrap();
+        let saved = read_index_file(&path);
+        fs::write(&path, "latest\n").unwrap();
+        let invalid = read_index_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.unwrap(), 410);
+        assert!(invalid.is_err());
+
+        let filter = |index| ListingFilter {
+            modified_since: Some(index),
+            ..Default::default()
+        };
+        // The status changed after the specification did, which still counts
+        assert!(!filter(410).matches(&listing[0]));
+        assert!(filter(403).matches(&listing[0]));
+    }
+
+    #[test]
+    fn test_latest_index() {
+        assert_eq!(latest_index(&[410, 405], &[410, 405], None), 410);
+        // Nothing changed since, so the saved index is kept rather than reset
+        assert_eq!(latest_index(&[], &[], Some(410)), 410);
+        // A job cut by --head or that failed to fetch is returned again by the next query
+        assert_eq!(latest_index(&[420, 410, 405], &[420, 405], None), 409);
+        assert_eq!(latest_index(&[420, 410, 405], &[410, 405], Some(400)), 410);
+        // Even if nothing at all could be written
+        assert_eq!(latest_index(&[420, 410], &[], Some(400)), 400);
+        // Jobs sharing an index are told apart
+        assert_eq!(latest_index(&[410, 410], &[410], None), 409);
+    }
+
+    #[test]
+    fn test_token_warning() {
+        let now = humantime::parse_rfc3339("2020-11-03T11:30:00Z").unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let warning = |expiration_time: &str, threshold| {
+            let mut client = RoutedClient::new(vec![(
+                "acl/token/self",
+                Reply::Body(200, nomad::test::acl_token(expiration_time)),
+            )]);
+            token_warning(&mut client, threshold, now)
+        };
+        assert_eq!(
+            warning("2020-11-03T12:00:00.5Z", day),
+            Some(String::from(
+                "Warning: the ACL token 'deploy' expires in 30m"
+            ))
+        );
+        assert_eq!(
+            warning("2020-11-03T12:00:00Z", Duration::from_secs(60)),
+            None
+        );
+        assert_eq!(
+            warning("2020-11-03T11:00:00Z", day),
+            Some(String::from("Warning: the ACL token 'deploy' has expired"))
+        );
+        let mut client = RoutedClient::new(vec![]);
+        assert!(token_warning(&mut client, day, now)
+            .unwrap()
+            .starts_with("Warning: could not look up the ACL token"));
+    }
+
+    /// The defaults `given_args` writes out after the arguments given
+    const DEFAULT_ARGS: &str = "--token-expiry-warning 1d --meta-scope job --max-redirects 5 \
+                                --retry-on-empty 0 --histogram-buckets 1h,1d,1w --output json \
+                                --count-by Status,Type --syslog-facility user --syslog-tag nquery \
+                                --delimiter , --buffer-size 0";
+
+    #[test]
+    fn test_long_args() {
+        // Every option and flag in the help is listed, so none is left out of rebuilt commands
+        let mut help = Vec::new();
+        Opt::clap().write_long_help(&mut help).unwrap();
+        let help = String::from_utf8(help).unwrap();
+        let mut longs: Vec<&str> = help
+            .lines()
+            .map(str::trim_start)
+            .map(|line| line.strip_prefix("-f, ").unwrap_or(line))
+            .filter_map(|line| line.strip_prefix("--"))
+            .map(|line| line.split(' ').next().unwrap())
+            .filter(|long| !["help", "version"].contains(long))
+            .collect();
+        longs.sort_unstable();
+        longs.dedup();
+        let mut listed = LONG_ARGS.to_vec();
+        listed.sort_unstable();
+        assert_eq!(longs, listed);
+    }
+
+    #[test]
+    fn test_reproducible_command() {
+        let reproduce = |args: &[&str]| {
+            let matches = Opt::clap()
+                .get_matches_from_safe(std::iter::once(&"nquery").chain(args))
+                .unwrap();
+            reproducible_command(&matches, "https://nomad.example.com:4646")
+        };
+        assert_eq!(
+            reproduce(&[
+                "--address-file",
+                "/etc/nomad-addr",
+                "--periodic",
+                "--fields",
+                "ID",
+                "-f",
+                "Type",
+                "--field-eq",
+                "Meta.owner=data team",
+                "--print-command",
+                "--token=secret",
+                "web",
+            ]),
+            format!(
+                "nquery --address https://nomad.example.com:4646 --periodic --fields ID \
+                 --fields Type --field-eq 'Meta.owner=data team' --token=REDACTED web {}",
+                DEFAULT_ARGS
+            )
+        );
+        // Flags merely named after tokens aren't secrets, and don't swallow what follows
+        assert_eq!(
+            reproduce(&[
+                "--print-command",
+                "--check-token",
+                "--token-expiry-warning",
+                "2h",
+                "--token",
+                "secret",
+                "web",
+            ]),
+            format!(
+                "nquery --address https://nomad.example.com:4646 --check-token \
+                 --token-expiry-warning 2h --token=REDACTED web {}",
+                DEFAULT_ARGS.replace("--token-expiry-warning 1d ", "")
+            )
+        );
+        assert_eq!(
+            reproduce(&["--check-token", "--type=batch", "--output", "csv"]),
+            format!(
+                "nquery --address https://nomad.example.com:4646 --check-token --type batch \
+                 --output csv {}",
+                DEFAULT_ARGS.replace("--output json ", "")
+            )
+        );
+    }
+
+    #[test]
+    fn test_html_query_redacts_token() {
+        let matches = Opt::clap()
+            .get_matches_from_safe(["nquery", "--token", "s3cr3t", "--output", "html", "web"])
+            .unwrap();
+        let cmd = Opt::from_clap(&matches);
+        let format = cmd.output.format(
+            false,
+            None,
+            ',',
+            false,
+            &given_args(&matches, &[]).join(" "),
+        );
+        let mut html = Vec::new();
+        format.write(&json!([{"ID": "web"}]), &mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(!html.contains("s3cr3t"), "{}", html);
+        let query = format!(
+            "<code>--token=REDACTED --output html web {}</code>",
+            DEFAULT_ARGS.replace("--output json ", "")
+        );
+        assert!(html.contains(&query), "{}", html);
+    }
+
+    #[test]
+    fn test_rename_file() {
+        let path = env::temp_dir().join(format!("nquery-test-renames-{}", process::id()));
+        fs::write(
+            &path,
+            "# Friendly column headers\nTaskGroups[*].Name = Groups\n\nID=Job\n",
+        )
+        .unwrap();
+        let renames = load_renames(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let view = JobView {
+            fields: vec!["TaskGroups[*].Name".into(), "Status".into()],
+            renames,
+            ..Default::default()
+        };
+        let rendered = view.render(&MatchedJob::new(listing(), full_job()));
+        let keys: Vec<&String> = rendered.as_object().unwrap().keys().collect();
+        assert_eq!(keys, vec!["Job", "Groups", "Status"]);
+        assert_eq!(rendered["Groups"], json!(["cache"]));
+    }
+
+    #[test]
+    fn test_parse_renames_invalid() {
+        let err = parse_renames("ID=Job\nStatus\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+        assert!(parse_renames("=Job").is_err());
+    }
+
+    #[test]
+    fn test_filter_file_json() {
+        let filters: FilterFile =
+            serde_json::from_str(r#"{"status": "dead", "region-count": 2}"#).unwrap();
+        let mut listing_filter = ListingFilter::default();
+        let mut job_filter = JobFilter::default();
+        filters.fill(&mut listing_filter, &mut job_filter).unwrap();
+        assert_eq!(listing_filter.status, Some(String::from("dead")));
+        assert_eq!(job_filter.region_count, Some(2));
+    }
+
+    #[test]
+    fn test_filter_file_invalid() {
+        assert!(toml::from_str::<FilterFile>("stauts = \"running\"").is_err());
+        let filters: FilterFile = toml::from_str("field-eq = [\"Priority\"]").unwrap();
+        assert!(filters
+            .fill(&mut ListingFilter::default(), &mut JobFilter::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_within_bounds() {
+        assert!(within_bounds(300, None, None));
+        assert!(within_bounds(300, Some(300), Some(300)));
+        assert!(!within_bounds(300, Some(301), None));
+        assert!(!within_bounds(300, None, Some(299)));
+    }
+
+    #[test]
+    fn test_field_selector_alias() {
+        assert_eq!(field_selector("Meta.owner"), "$.Meta.owner");
+        assert_eq!(
+            field_selector("EphemeralDiskMB"),
+            "$.TaskGroups[*].EphemeralDisk.SizeMB"
+        );
+    }
+
+    #[test]
+    fn test_selects_many() {
+        assert!(!selects_many("$.Meta.owner"));
+        assert!(!selects_many("$.TaskGroups[0].Name"));
+        assert!(!selects_many("$.Meta['a,b']"));
+        assert!(selects_many("$.TaskGroups[*].Name"));
+        assert!(selects_many("$..Name"));
+        assert!(selects_many("$.TaskGroups[0:2].Name"));
+        assert!(selects_many("$.TaskGroups[0,1].Name"));
+        assert!(selects_many("$.TaskGroups[?(@.Count > 1)].Name"));
+
+        // With a single task group, a wildcard still yields an array
+        let job = serde_json::to_value(full_job()).unwrap();
+        let fields = vec![String::from("TaskGroups[*].Name"), String::from("Name")];
+        assert_eq!(
+            project_fields(&job, &fields, false),
+            json!({"ID": "example", "TaskGroups[*].Name": ["cache"], "Name": "example"})
+        );
+    }
+
+    #[test]
+    fn test_listing_filter_exact() {
+        let filter = ListingFilter {
+            name: String::from("web"),
+            exact: true,
+            ..Default::default()
+        };
+        let listing = listing_with_ids(&["web", "web-staging", "WEB", "api"]);
+        let matched: Vec<&str> = listing
+            .iter()
+            .filter(|job| filter.matches(job))
+            .map(|job| job.ID.as_str())
+            .collect();
+        assert_eq!(matched, vec!["web", "WEB"]);
+    }
+
+    #[test]
+    fn test_listing_filter_datacenter() {
+        let mut listing = listing_with_ids(&["anywhere", "west", "east"]);
+        listing[0].Datacenters = Some(vec![String::from("*")]);
+        listing[1].Datacenters = Some(vec![String::from("west-1")]);
+        listing[2].Datacenters = Some(vec![String::from("east-1"), String::from("east-2")]);
+        let matched = |filter: &ListingFilter| -> Vec<&str> {
+            listing
+                .iter()
+                .filter(|job| filter.matches(job))
+                .map(|job| job.ID.as_str())
+                .collect()
+        };
+        let east = ListingFilter {
+            datacenter: Some(String::from("east-2")),
+            ..Default::default()
+        };
+        assert_eq!(matched(&east), vec!["anywhere", "east"]);
+        let any = ListingFilter {
+            any_datacenter: true,
+            ..Default::default()
+        };
+        assert_eq!(matched(&any), vec!["anywhere"]);
+    }
+
+    #[test]
+    fn test_listing_filter_status_description() {
+        let mut listing: Vec<nomad::JobListing> =
+            serde_json::from_str(&nomad::test::JOB_LISTING.replace(
+                r#""StatusDescription":"""#,
+                r#""StatusDescription":"Constraint missing drivers filtered 3 nodes""#,
+            ))
+            .unwrap();
+        listing.push(listing_with_ids(&["healthy"]).remove(0));
+        assert_eq!(
+            listing[0].StatusDescription,
+            "Constraint missing drivers filtered 3 nodes"
+        );
+        let filter = ListingFilter {
+            status_description_contains: Some(String::from("Missing Drivers")),
+            ..Default::default()
+        };
+        let matched: Vec<&str> = listing
+            .iter()
+            .filter(|job| filter.matches(job))
+            .map(|job| job.ID.as_str())
+            .collect();
+        assert_eq!(matched, vec!["example"]);
+    }
+
+    #[test]
+    fn test_listing_filter_no_running() {
+        let down = |id: &str, job_type: &str, status: &str| {
+            let mut job = listing();
+            for group in job.JobSummary.as_mut().unwrap().Summary.values_mut() {
+                group.Running = 0;
+            }
+            job.ID = id.into();
+            job.Type = job_type.into();
+            job.Status = status.into();
+            job
+        };
+        let listing = [
+            listing(),
+            down("down", "service", "pending"),
+            down("stopped", "service", "dead"),
+            down("idle", "batch", "running"),
+        ];
+        let filter = ListingFilter {
+            no_running: true,
+            ..Default::default()
+        };
+        let matched: Vec<&str> = listing
+            .iter()
+            .filter(|job| filter.matches(job))
+            .map(|job| job.ID.as_str())
+            .collect();
+        assert_eq!(matched, vec!["down"]);
+    }
+
+    #[test]
+    fn test_listing_filter_status_not() {
+        let mut listing = listing_with_ids(&["running", "stopped", "finished"]);
+        listing[1].Status = String::from("dead");
+        listing[2].Status = String::from("dead");
+        let finished = listing[2].JobSummary.as_mut().unwrap();
+        let cache = finished.Summary.get_mut("cache").unwrap();
+        cache.Running = 0;
+        cache.Complete = 1;
+        let ids = |filter: &ListingFilter| -> Vec<&str> {
+            listing
+                .iter()
+                .filter(|job| filter.matches(job))
+                .map(|job| job.ID.as_str())
+                .collect()
+        };
+        // What --no-dead expands to
+        let no_dead = ListingFilter {
+            status_not: vec![String::from("dead")],
+            ..Default::default()
+        };
+        assert_eq!(ids(&no_dead), ["running"]);
+        let no_complete = ListingFilter {
+            no_complete: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(&no_complete), ["running", "stopped"]);
+        let failed = ListingFilter {
+            status: Some(String::from("dead")),
+            no_complete: true,
+            ..Default::default()
+        };
+        assert_eq!(ids(&failed), ["stopped"]);
+    }
+
+    #[test]
+    fn test_listing_filter_dispatch_status() {
+        let listing: Vec<nomad::JobListing> = [
+            nomad::test::dispatched_listing(2, 0),
+            nomad::test::dispatched_listing(1, 1),
+            nomad::test::JOB_LISTING.to_string(),
+        ]
+        .iter()
+        .flat_map(|listing| serde_json::from_str::<Vec<nomad::JobListing>>(listing).unwrap())
+        .collect();
+        let count = |status| {
+            let filter = ListingFilter {
+                dispatch_status: Some(status),
+                ..Default::default()
+            };
+            listing.iter().filter(|job| filter.matches(job)).count()
+        };
+        assert_eq!(count(nomad::DispatchStatus::Complete), 1);
+        assert_eq!(count(nomad::DispatchStatus::Failed), 1);
+    }
+
+    #[test]
+    fn test_job_filter_dispatched() {
+        let filter = JobFilter {
+            dispatched: true,
+            ..Default::default()
+        };
+        let dispatched: nomad::Job =
+            nomad::test::full_job_with(&[("/Dispatched", "true"), ("/ParentID", r#""example""#)]);
+        assert!(filter.matches(&dispatched));
+        assert!(!filter.matches(&full_job()));
+    }
+
+    #[test]
+    fn test_listing_filter_glob_star() {
+        let filter = ListingFilter {
+            glob: Some(Pattern::new("*-canary").unwrap()),
+            ..Default::default()
+        };
+        let listing = listing_with_ids(&["web-canary", "API-Canary", "web", "canary-web"]);
+        let matched: Vec<&str> = listing
+            .iter()
+            .filter(|job| filter.matches(job))
+            .map(|job| job.ID.as_str())
+            .collect();
+        assert_eq!(matched, vec!["web-canary", "API-Canary"]);
+    }
+
+    #[test]
+    fn test_listing_filter_glob_character_class() {
+        let filter = ListingFilter {
+            glob: Some(Pattern::new("web-[ab]?").unwrap()),
+            ..Default::default()
+        };
+        let listing = listing_with_ids(&["web-a1", "web-b2", "web-c1", "web-a", "web-a12"]);
+        let matched: Vec<&str> = listing
+            .iter()
+            .filter(|job| filter.matches(job))
+            .map(|job| job.ID.as_str())
+            .collect();
+        assert_eq!(matched, vec!["web-a1", "web-b2"]);
+    }
+
+    #[test]
+    fn test_job_filter_disk() {
+        let job = full_job();
+        assert!(JobFilter::default().matches(&job));
+        let in_range = JobFilter {
+            min_disk: Some(300),
+            max_disk: Some(500),
+            ..Default::default()
+        };
+        assert!(in_range.matches(&job));
+        let too_small = JobFilter {
+            min_disk: Some(301),
+            ..Default::default()
+        };
+        assert!(!too_small.matches(&job));
+    }
+
+    fn listing() -> nomad::JobListing {
+        let mut listing: Vec<nomad::JobListing> =
+            serde_json::from_str(nomad::test::JOB_LISTING).unwrap();
+        listing.remove(0)
+    }
+
+    fn full_job() -> nomad::Job {
+        serde_json::from_str(nomad::test::FULL_JOB).unwrap()
+    }
+
+    fn matched() -> MatchedJob {
+        MatchedJob::new(listing(), full_job())
+    }
+
+    /// Listing entries copied from the fixture, with the given IDs
+    fn listing_with_ids(ids: &[&str]) -> Vec<nomad::JobListing> {
+        ids.iter()
+            .map(|id| {
+                let mut entry = listing();
+                entry.ID = String::from(*id);
+                entry
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fetch_jobs_skips_timeouts() {
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/example",
+                Reply::Body(200, nomad::test::FULL_JOB.into()),
+            ),
+            ("job/slow", Reply::TimedOut),
+        ]);
+        let listing = listing_with_ids(&["slow", "example"]);
+        let fetched = fetch_jobs(
+            &mut client,
+            listing,
+            &JobFilter::default(),
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(client.requests, vec!["job/slow", "job/example"]);
+        assert_eq!(fetched.jobs.len(), 1);
+        assert_eq!(fetched.jobs[0].listing.ID, "example");
+        assert_eq!(fetched.errors.len(), 1);
+        assert_eq!(fetched.errors[0].0, "slow");
+        assert_eq!(fetched.skipped_indices, vec![410]);
+        assert_eq!(fetched.attempted, 2);
+
+        // Under --strict the timeout fails the query, even when other failures are skipped
+        for &keep_going in &[false, true] {
+            let listing = listing_with_ids(&["example", "slow"]);
+            let err = fetch_jobs(
+                &mut client,
+                listing,
+                &JobFilter::default(),
+                keep_going,
+                true,
+                None,
+            )
+            .unwrap_err();
+            assert!(err.is::<nomad::Timeout>(), "{}", err);
+        }
+
+        // A fetch cut short by the deadline is skipped too
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/example",
+                Reply::Body(200, nomad::test::FULL_JOB.into()),
+            ),
+            ("job/late", Reply::DeadlineExceeded),
+        ]);
+        let listing = listing_with_ids(&["example", "late"]);
+        let fetched = fetch_jobs(
+            &mut client,
+            listing,
+            &JobFilter::default(),
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(fetched.jobs.len(), 1);
+        assert_eq!(fetched.errors[0].0, "late");
+    }
+
+    #[test]
+    fn test_fetch_jobs_in_listed_namespace() {
+        let listing = nomad::test::JOB_LISTING.replace(r#""Namespace":"""#, r#""Namespace":"etl""#);
+        let mut client = RoutedClient::new(vec![
+            ("jobs?prefix=&namespace=%2A", Reply::Body(200, listing)),
+            (
+                "job/example?namespace=etl",
+                Reply::Body(200, nomad::test::FULL_JOB.into()),
+            ),
+        ]);
+        let filter = ListingFilter {
+            namespace: Some(String::from("*")),
+            ..Default::default()
+        };
+        let listing = get_listing(&mut client, &filter).unwrap();
+        let fetched = fetch_jobs(
+            &mut client,
+            listing,
+            &JobFilter::default(),
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            client.requests,
+            vec!["jobs?prefix=&namespace=%2A", "job/example?namespace=etl"]
+        );
+        assert_eq!(fetched.jobs.len(), 1);
+        assert!(fetched.errors.is_empty());
+    }
+
+    #[test]
+    fn test_get_listing_retrying() {
+        let mut empty = nomad::test::TestClient::new(200, "OK", "[]");
+        let mut caught_up = nomad::test::TestClient::new(200, "OK", nomad::test::JOB_LISTING);
+        let filter = ListingFilter::default();
+        let listing =
+            get_listing_retrying(&mut empty, &mut caught_up, &filter, 0, Duration::ZERO, None)
+                .unwrap();
+        assert!(listing.is_empty());
+        assert_eq!(caught_up.path, None);
+        let listing =
+            get_listing_retrying(&mut empty, &mut caught_up, &filter, 3, Duration::ZERO, None)
+                .unwrap();
+        assert_eq!(listing.len(), 1);
+        assert_eq!(listing[0].ID, "example");
+        assert_eq!(caught_up.path, Some(String::from("jobs?prefix=")));
+
+        // Waiting to retry stops at the deadline
+        let mut still_empty = nomad::test::TestClient::new(200, "OK", "[]");
+        let started = Instant::now();
+        let deadline = Some(started + Duration::from_millis(50));
+        let listing = get_listing_retrying(
+            &mut empty,
+            &mut still_empty,
+            &filter,
+            3,
+            Duration::from_secs(60),
+            deadline,
+        )
+        .unwrap();
+        assert!(listing.is_empty());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let err = anyhow::Error::from(nomad::DeadlineExceeded {
+            resource: String::from("job/example"),
+        });
+        assert_eq!(exit_code(&err, None), interrupt::DEADLINE_EXIT_CODE);
+        let err = anyhow!("failed to read response");
+        assert_eq!(exit_code(&err, None), 1);
+        let future = Instant::now() + Duration::from_secs(60);
+        assert_eq!(exit_code(&err, Some(future)), 1);
+        let past = Instant::now();
+        assert_eq!(exit_code(&err, Some(past)), interrupt::DEADLINE_EXIT_CODE);
+    }
+
+    #[test]
+    fn test_fetch_jobs_timings() {
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/example",
+                Reply::Body(200, nomad::test::FULL_JOB.into()),
+            ),
+            ("job/slow", Reply::TimedOut),
+        ]);
+        let listing = listing_with_ids(&["slow", "example"]);
+        let fetched = fetch_jobs(
+            &mut client,
+            listing,
+            &JobFilter::default(),
+            false,
+            false,
+            None,
+        )
+        .unwrap();
+        let timings = &fetched.timings;
+        let (slowest_id, slowest) = timings.slowest.as_ref().unwrap();
+        assert!(slowest_id == "slow" || slowest_id == "example");
+        assert!(*slowest <= timings.total);
+        assert!(timings.filtering <= timings.total);
+    }
+
+    #[test]
+    fn test_fetch_timings_keeps_slowest() {
+        let mut timings = FetchTimings::default();
+        timings.record_fetch("fast", Duration::from_millis(5));
+        timings.record_fetch("slow", Duration::from_millis(50));
+        timings.record_fetch("medium", Duration::from_millis(20));
+        assert_eq!(
+            timings.slowest,
+            Some((String::from("slow"), Duration::from_millis(50)))
+        );
+    }
+
+    #[test]
+    fn test_fetch_jobs_fails_on_errors() {
+        let mut client = RoutedClient::new(vec![(
+            "job/example",
+            Reply::Body(200, nomad::test::FULL_JOB.into()),
+        )]);
+        let listing = listing_with_ids(&["missing", "example"]);
+        assert!(fetch_jobs(
+            &mut client,
+            listing,
+            &JobFilter::default(),
+            false,
+            false,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_fetch_jobs_reports_errors() {
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/example",
+                Reply::Body(200, nomad::test::FULL_JOB.into()),
+            ),
+            ("job/broken", Reply::Body(500, "".into())),
+        ]);
+        let listing = listing_with_ids(&["broken", "example"]);
+        let fetched = fetch_jobs(
+            &mut client,
+            listing,
+            &JobFilter::default(),
+            true,
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(fetched.jobs.len(), 1);
+        assert_eq!(fetched.jobs[0].listing.ID, "example");
+        assert_eq!(
+            error_report(&fetched.errors),
+            json!([{"ID": "broken", "error": "failed to read response"}])
+        );
+    }
+
+    #[test]
+    fn test_job_filter_count() {
+        let job = full_job();
+        let single = JobFilter {
+            max_count: Some(1),
+            ..Default::default()
+        };
+        assert!(single.matches(&job));
+        let redundant = JobFilter {
+            min_count: Some(2),
+            ..Default::default()
+        };
+        assert!(!redundant.matches(&job));
+        let exact = JobFilter {
+            min_count: Some(1),
+            max_count: Some(1),
+            ..Default::default()
+        };
+        assert!(exact.matches(&job));
+    }
+
+    #[test]
+    fn test_job_filter_version() {
+        // The fixture has never been updated
+        let job = full_job();
+        let never_updated = JobFilter {
+            max_version: Some(0),
+            ..Default::default()
+        };
+        assert!(never_updated.matches(&job));
+        let churned = JobFilter {
+            min_version: Some(5),
+            ..Default::default()
+        };
+        assert!(!churned.matches(&job));
+    }
+
+    #[test]
+    fn test_sort_jobs() {
+        let mut matched: Vec<MatchedJob> = listing_with_ids(&["web", "api", "cache"])
+            .into_iter()
+            .zip([3, 0, 7])
+            .map(|(entry, version)| {
+                let mut job = full_job();
+                job.Version = version;
+                MatchedJob::new(entry, job)
+            })
+            .collect();
+        let ids = |matched: &[MatchedJob]| -> Vec<String> {
+            matched.iter().map(|m| m.listing.ID.clone()).collect()
+        };
+        sort_jobs(&mut matched, SortKey::Version);
+        assert_eq!(ids(&matched), ["api", "web", "cache"]);
+        sort_jobs(&mut matched, SortKey::Id);
+        assert_eq!(ids(&matched), ["api", "cache", "web"]);
+        assert!(SortKey::from_str("Priority").is_err());
+    }
+
+    #[test]
+    fn test_job_filter_restart_attempts() {
+        let job = full_job();
+        let risky = JobFilter {
+            min_restart_attempts: Some(2),
+            ..Default::default()
+        };
+        assert!(risky.matches(&job));
+        let exact = JobFilter {
+            min_restart_attempts: Some(2),
+            max_restart_attempts: Some(2),
+            ..Default::default()
+        };
+        assert!(exact.matches(&job));
+        let none_allowed = JobFilter {
+            max_restart_attempts: Some(1),
+            ..Default::default()
+        };
+        assert!(!none_allowed.matches(&job));
+    }
+
+    #[test]
+    fn test_job_filter_region_count() {
+        let job: nomad::Job =
+            nomad::test::full_job_with(&[("/Multiregion", nomad::test::MULTIREGION)]);
+        let two = JobFilter {
+            region_count: Some(2),
+            ..Default::default()
+        };
+        assert!(two.matches(&job));
+        assert!(!two.matches(&full_job()));
+        let single = JobFilter {
+            region_count: Some(0),
+            ..Default::default()
+        };
+        assert!(single.matches(&full_job()));
+        assert!(!single.matches(&job));
+    }
+
+    #[test]
+    fn test_field_eq_parse() {
+        assert_eq!(
+            "Priority=50".parse::<FieldEq>().unwrap(),
+            FieldEq {
+                key: String::from("Priority"),
+                value: String::from("50"),
+            }
+        );
+        assert_eq!(
+            "Region=a=b".parse::<FieldEq>().unwrap().value,
+            String::from("a=b")
+        );
+        assert!("Priority".parse::<FieldEq>().is_err());
+        assert!("=50".parse::<FieldEq>().is_err());
+    }
+
+    #[test]
+    fn test_job_filter_field_eq() {
+        let job = full_job();
+        let filter = |conditions: &[&str]| JobFilter {
+            field_eq: conditions.iter().map(|c| c.parse().unwrap()).collect(),
+            ..Default::default()
+        };
+        // Modeled fields
+        assert!(filter(&["Status=running"]).matches(&job));
+        assert!(!filter(&["Status=dead"]).matches(&job));
+        // Fields only captured in `extra`
+        assert!(filter(&["Priority=50"]).matches(&job));
+        assert!(filter(&["Priority=50.0"]).matches(&job));
+        assert!(filter(&["Stop=false"]).matches(&job));
+        assert!(filter(&["Meta=null"]).matches(&job));
+        assert!(filter(&[r#"Datacenters=["dc1"]"#]).matches(&job));
+        assert!(!filter(&["Priority=high"]).matches(&job));
+        assert!(!filter(&["Missing=anything"]).matches(&job));
+        // Every condition must hold
+        assert!(filter(&["Priority=50", "Region=global"]).matches(&job));
+        assert!(!filter(&["Priority=50", "Region=east"]).matches(&job));
+    }
+
+    #[test]
+    fn test_project_fields_order() {
+        let job = serde_json::to_value(full_job()).unwrap();
+        let fields: Vec<String> = vec!["Type", "Priority", "Name", "Priority"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let projected = project_fields(&job, &fields, false);
+        let keys: Vec<&str> = projected
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(keys, vec!["ID", "Type", "Priority", "Name"]);
+        assert_eq!(
+            serde_json::to_string(&projected).unwrap(),
+            r#"{"ID":"example","Type":"service","Priority":50,"Name":"example"}"#
+        );
+
+        // Asking for the ID explicitly puts it where it was asked for
+        let fields = vec![String::from("Name"), String::from("ID")];
+        let keys: Vec<String> = project_fields(&job, &fields, false)
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        assert_eq!(keys, vec!["Name", "ID"]);
+    }
+
+    #[test]
+    fn test_estimate_requests() {
+        assert_eq!(estimate_requests(1000, None, 1), 1000);
+        assert_eq!(estimate_requests(1000, Some(10), 1), 10);
+        assert_eq!(estimate_requests(5, Some(10), 1), 5);
+        assert_eq!(estimate_requests(1000, Some(10), 2), 20);
+        assert_eq!(estimate_requests(1000, None, 3), 3000);
+    }
+
+    #[test]
+    fn test_confirm_fan_out() {
+        let mut prompt = Vec::new();
+        // Small queries go ahead without asking
+        assert!(confirm_fan_out(10, false, &mut "".as_bytes(), &mut prompt).unwrap());
+        assert!(prompt.is_empty());
+        assert!(confirm_fan_out(1000, false, &mut "y\n".as_bytes(), &mut prompt).unwrap());
+        assert_eq!(
+            String::from_utf8(prompt).unwrap(),
+            "This query will make about 1000 requests to the cluster. Continue? [y/N] "
+        );
+        assert!(!confirm_fan_out(1000, false, &mut "\n".as_bytes(), &mut Vec::new()).unwrap());
+        assert!(!confirm_fan_out(1000, false, &mut "".as_bytes(), &mut Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn test_confirm_fan_out_yes() {
+        let mut prompt = Vec::new();
+        // Nothing is read, so an answer of no is never seen
+        assert!(confirm_fan_out(1000, true, &mut "n\n".as_bytes(), &mut prompt).unwrap());
+        assert!(prompt.is_empty());
+    }
+
+    #[test]
+    fn test_expand_env() {
+        let lookup = |name: &str| match name {
+            "MY_NOMAD" => Some(String::from("https://nomad.example:4646")),
+            _ => None,
+        };
+        assert_eq!(
+            expand_env("${MY_NOMAD}/", lookup).unwrap(),
+            "https://nomad.example:4646/"
+        );
+        assert_eq!(
+            expand_env("http://localhost:4646", lookup).unwrap(),
+            "http://localhost:4646"
+        );
+        match expand_env("${UNSET_NOMAD}", lookup) {
+            Err(err) => assert_eq!(
+                err.to_string(),
+                "environment variable UNSET_NOMAD is not set"
+            ),
+            Ok(_) => unreachable!(),
+        };
+        assert!(expand_env("${MY_NOMAD", lookup).is_err());
+    }
+
+    #[test]
+    fn test_check_fields() {
+        let job = serde_json::to_value(full_job()).unwrap();
+        let fields = |fields: &[&str]| -> Vec<String> {
+            fields.iter().map(|field| field.to_string()).collect()
+        };
+        assert!(check_fields(
+            &job,
+            &fields(&["Priority", "Meta", "EphemeralDiskMB"]),
+            false
+        )
+        .is_ok());
+        match check_fields(
+            &job,
+            &fields(&["Priorty", "TaskGroups[*].Nmae", "Bogus"]),
+            false,
+        ) {
+            Err(err) => assert_eq!(
+                err.to_string(),
+                "unknown fields: Priorty (did you mean Priority?), \
+                 TaskGroups[*].Nmae (did you mean TaskGroups[*].Name?), Bogus"
+            ),
+            Ok(_) => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_unwrap_single() {
+        assert_eq!(
+            unwrap_single(vec![json!({"ID": "example"})]),
+            json!({"ID": "example"})
+        );
+        assert_eq!(
+            unwrap_single(vec![json!({"ID": "example"}), json!({"ID": "other"})]),
+            json!([{"ID": "example"}, {"ID": "other"}])
+        );
+        assert_eq!(unwrap_single(vec![]), json!([]));
+    }
+
+    #[test]
+    fn test_write_stream() {
+        let values: Vec<_> = (0..20)
+            .map(|n| json!({"ID": format!("job-{}", n), "Version": n}))
+            .collect();
+        let expected: String = values.iter().map(|value| format!("{}\n", value)).collect();
+        for buffer_size in [0, 16, 1 << 16] {
+            let mut out = Vec::new();
+            write_stream(
+                &output::JsonLines,
+                values.clone().into_iter(),
+                &mut out,
+                buffer_size,
+            )
+            .unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_job_ids() {
+        let listing = listing_with_ids(&["example", "other", "third"]);
+        let listing: Vec<_> = listing.iter().collect();
+        assert_eq!(job_ids(&listing), "example\nother\nthird\n");
+        assert_eq!(job_ids(&[]), "");
+    }
+
+    #[test]
+    fn test_oneline() {
+        let mut listing: Vec<nomad::JobListing> =
+            serde_json::from_str(nomad::test::JOB_LISTING).unwrap();
+        let mut batch: Vec<nomad::JobListing> =
+            serde_json::from_str(nomad::test::JOB_LISTING).unwrap();
+        batch[0].ID = "report".into();
+        batch[0].Type = "batch".into();
+        batch[0].Status = "dead".into();
+        batch[0].Datacenters = Some(vec!["dc1".into(), "dc2".into()]);
+        batch[0].Priority = None;
+        listing.append(&mut batch);
+        let listing: Vec<_> = listing.iter().collect();
+        let fields: Vec<String> = ONELINE_FIELDS
+            .iter()
+            .map(|field| field.to_string())
+            .collect();
+        assert_eq!(
+            oneline(&listing, &fields),
+            "example  service  running  dc1  prio=50\n\
+             report  batch  dead  dc1,dc2\n"
+        );
+        let fields = [String::from("Status"), String::from("Periodic")];
+        assert_eq!(
+            oneline(&listing, &fields),
+            "example  running  periodic=false\nreport  dead  periodic=false\n"
+        );
+    }
+
+    #[test]
+    fn test_listing_output_skips_fan_out() {
+        let mut client = RoutedClient::new(vec![(
+            "jobs?prefix=",
+            Reply::Body(200, nomad::test::JOB_LISTING.into()),
+        )]);
+        let listing = get_listing(&mut client, &ListingFilter::default()).unwrap();
+        let listing: Vec<_> = listing.iter().collect();
+        assert_eq!(ListingOutput::Count.render(&listing), "1\n");
+        assert_eq!(ListingOutput::Ids.render(&listing), "example\n");
+        assert_eq!(ListingOutput::Tree.render(&listing), "example\n");
+        let fields = [String::from("Namespace"), String::from("JobModifyIndex")];
+        assert_eq!(
+            ListingOutput::Oneline(&fields).render(&listing),
+            "example  index=403\n"
+        );
+        assert_eq!(
+            ListingOutput::CountTable(&[String::from("Status")]).render(&listing),
+            "Status   Total\nrunning  1\nTotal    1\n"
+        );
+        // Only the listing was fetched, never a full job
+        assert_eq!(client.requests, vec!["jobs?prefix="]);
+    }
+
+    #[test]
+    fn test_renders_from_listing() {
+        let none = JobFilter::default();
+        let by_version = JobFilter {
+            min_version: Some(1),
+            ..Default::default()
+        };
+        let fields = [String::from("Status")];
+        let outputs = [
+            ListingOutput::Count,
+            ListingOutput::Ids,
+            ListingOutput::Tree,
+            ListingOutput::Oneline(&fields),
+            ListingOutput::CountTable(&fields),
+        ];
+        for &output in &outputs {
+            assert!(renders_from_listing(Some(output), false, &none, false, false).unwrap());
+            // Criteria only checked on fetched jobs can't be silently ignored
+            for &(job_filter, on_node, stuck) in &[
+                (&by_version, false, false),
+                (&none, true, false),
+                (&none, false, true),
+            ] {
+                let err = renders_from_listing(Some(output), false, job_filter, on_node, stuck)
+                    .unwrap_err()
+                    .to_string();
+                assert!(err.starts_with(output.flag()), "{}", err);
+                assert!(err.contains("--force-full"), "{}", err);
+                assert!(
+                    !renders_from_listing(Some(output), true, job_filter, on_node, stuck).unwrap()
+                );
+            }
+            assert!(!renders_from_listing(Some(output), true, &none, false, false).unwrap());
+        }
+        assert!(!renders_from_listing(None, false, &by_version, true, true).unwrap());
+        // The scope alone isn't a criterion
+        let scoped = JobFilter {
+            meta_scope: nomad::MetaScope::Group,
+            ..Default::default()
+        };
+        assert!(
+            renders_from_listing(Some(ListingOutput::Count), false, &scoped, false, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_job_filter_port() {
+        let job = full_job();
+        let db = JobFilter {
+            port: Some(String::from("db")),
+            ..Default::default()
+        };
+        assert!(db.matches(&job));
+        let http = JobFilter {
+            port: Some(String::from("http")),
+            ..Default::default()
+        };
+        assert!(!http.matches(&job));
+    }
+
+    #[test]
+    fn test_job_filter_meta_scope() {
+        let job: nomad::Job =
+            nomad::test::full_job_with(&[("/TaskGroups/0/Meta", r#"{"team":"cache"}"#)]);
+        let filter = |scope: &str| JobFilter {
+            meta: vec!["team=cache".parse().unwrap()],
+            meta_scope: scope.parse().unwrap(),
+            ..Default::default()
+        };
+        assert!(!filter("job").matches(&job));
+        assert!(filter("group").matches(&job));
+        assert!(!filter("task").matches(&job));
+        assert!(filter("any").matches(&job));
+        let other_team = JobFilter {
+            meta: vec!["team=web".parse().unwrap()],
+            meta_scope: nomad::MetaScope::Any,
+            ..Default::default()
+        };
+        assert!(!other_team.matches(&job));
+    }
+
+    #[test]
+    fn test_job_filter_has_constraint() {
+        let constrained: nomad::Job = nomad::test::full_job_with(&[
+            ("/Constraints", nomad::test::JOB_CONSTRAINT),
+            ("/TaskGroups/0/Constraints", nomad::test::GROUP_CONSTRAINT),
+        ]);
+        let rack = JobFilter {
+            has_constraint: Some(String::from("${meta.rack}")),
+            ..Default::default()
+        };
+        assert!(rack.matches(&constrained));
+        assert!(!rack.matches(&full_job()));
+        let datacenter = JobFilter {
+            has_constraint: Some(String::from("node.datacenter")),
+            ..Default::default()
+        };
+        assert!(!datacenter.matches(&constrained));
+    }
+
+    #[test]
+    fn test_job_filter_cron_matches() {
+        let periodic: nomad::Job = nomad::test::full_job_with(&[
+            ("/Periodic", nomad::test::PERIODIC),
+            ("/Type", r#""batch""#),
+        ]);
+        let nightly = JobFilter {
+            cron_matches: Some(String::from("0 2 * * *")),
+            ..Default::default()
+        };
+        assert!(nightly.matches(&periodic));
+        assert!(!nightly.matches(&full_job()));
+        let hourly = JobFilter {
+            cron_matches: Some(String::from("0 * * * *")),
+            ..Default::default()
+        };
+        assert!(!hourly.matches(&periodic));
+    }
+
+    #[test]
+    fn test_job_filter_check_type() {
+        let job = full_job();
+        let tcp = JobFilter {
+            check_type: Some(String::from("tcp")),
+            ..Default::default()
+        };
+        assert!(tcp.matches(&job));
+        let http = JobFilter {
+            check_type: Some(String::from("http")),
+            ..Default::default()
+        };
+        assert!(!http.matches(&job));
+    }
+
+    #[test]
+    fn test_job_filter_uses_artifacts() {
+        let filter = JobFilter {
+            uses_artifacts: true,
+            ..Default::default()
+        };
+        let artifact_job: nomad::Job = nomad::test::full_job_with(&[(
+            "/TaskGroups/0/Tasks/0/Artifacts",
+            nomad::test::ARTIFACTS,
+        )]);
+        assert!(filter.matches(&artifact_job));
+        assert!(!filter.matches(&full_job()));
+    }
+
+    #[test]
+    fn test_job_filter_env_has() {
+        let filter = JobFilter {
+            env_has: Some(String::from("LOG_LEVEL")),
+            ..Default::default()
+        };
+        let env_job: nomad::Job = nomad::test::full_job_with(&[(
+            "/TaskGroups/0/Tasks/0/Env",
+            r#"{"LOG_LEVEL":"debug","REDIS_PORT":"${NOMAD_PORT_db}"}"#,
+        )]);
+        assert!(filter.matches(&env_job));
+        assert!(!filter.matches(&full_job()));
+        let env = project_fields(
+            &serde_json::to_value(&env_job).unwrap(),
+            &["TaskGroups[*].Tasks[*].Env.LOG_LEVEL".to_string()],
+            false,
+        );
+        assert_eq!(
+            env["TaskGroups[*].Tasks[*].Env.LOG_LEVEL"],
+            json!(["debug"])
+        );
+    }
+
+    #[test]
+    fn test_job_filter_has_shutdown_delay() {
+        let filter = JobFilter {
+            has_shutdown_delay: true,
+            ..Default::default()
+        };
+        let delayed: nomad::Job =
+            nomad::test::full_job_with(&[("/TaskGroups/0/Tasks/0/ShutdownDelay", "5000000000")]);
+        assert!(filter.matches(&delayed));
+        assert!(!filter.matches(&full_job()));
+    }
+
+    #[test]
+    fn test_job_filter_spread_and_affinity() {
+        let spread: nomad::Job = nomad::test::full_job_with(&[(
+            "/TaskGroups/0/Spreads",
+            r#"[{"Attribute":"${node.datacenter}","Weight":50,"SpreadTarget":[{"Value":"dc1","Percent":50},{"Value":"dc2","Percent":50}]}]"#,
+        )]);
+        let affinity: nomad::Job = nomad::test::full_job_with(&[(
+            "/Affinities",
+            r#"[{"LTarget":"${node.class}","RTarget":"cache","Operand":"=","Weight":75}]"#,
+        )]);
+        let has_spread = JobFilter {
+            has_spread: true,
+            ..Default::default()
+        };
+        assert!(has_spread.matches(&spread));
+        assert!(!has_spread.matches(&affinity));
+        let has_affinity = JobFilter {
+            has_affinity: true,
+            ..Default::default()
+        };
+        assert!(has_affinity.matches(&affinity));
+        assert!(!has_affinity.matches(&spread));
+    }
+
+    #[test]
+    fn test_job_filter_uses_device() {
+        let filter = JobFilter {
+            uses_device: Some(String::from("nvidia/gpu")),
+            ..Default::default()
+        };
+        let device_job: nomad::Job = nomad::test::full_job_with(&[(
+            "/TaskGroups/0/Tasks/0/Resources/Devices",
+            nomad::test::DEVICES,
+        )]);
+        assert!(filter.matches(&device_job));
+        assert!(!filter.matches(&full_job()));
+    }
+
+    #[test]
+    fn test_job_filter_service_tags() {
+        let job = full_job();
+        let cache = JobFilter {
+            service_tags: vec![String::from("cache")],
+            ..Default::default()
+        };
+        assert!(cache.matches(&job));
+        let any = JobFilter {
+            service_tags: vec![String::from("web"), String::from("global")],
+            ..Default::default()
+        };
+        assert!(any.matches(&job));
+        let web = JobFilter {
+            service_tags: vec![String::from("web")],
+            ..Default::default()
+        };
+        assert!(!web.matches(&job));
+    }
+
+    #[test]
+    fn test_project_ports() {
+        let job = serde_json::to_value(full_job()).unwrap();
+        let projected = project_fields(&job, &["DynamicPorts".to_string()], false);
+        assert_eq!(
+            projected["DynamicPorts"],
+            json!([{"Label": "db", "Value": 0, "To": 0, "HostNetwork": "default"}])
+        );
+        assert!(project_fields(&job, &["ReservedPorts".to_string()], false)
+            .get("ReservedPorts")
+            .is_none());
+    }
+
+    #[test]
+    fn test_project_pointer() {
+        let job = serde_json::to_value(full_job()).unwrap();
+        let by_pointer = project_fields(&job, &["/TaskGroups/0/Name".to_string()], true);
+        let by_jsonpath = project_fields(&job, &["TaskGroups[0].Name".to_string()], false);
+        assert_eq!(by_pointer["/TaskGroups/0/Name"], "cache");
+        assert_eq!(
+            by_pointer["/TaskGroups/0/Name"],
+            by_jsonpath["TaskGroups[0].Name"]
+        );
+        // The ID is still included, and pointers that select nothing are left out
+        let projected = project_fields(&job, &["/Priority".into(), "/Missing".into()], true);
+        assert_eq!(projected, json!({"ID": "example", "/Priority": 50}));
+        assert!(check_fields(&job, &["/Missing".into()], true).is_err());
+        assert!(check_fields(&job, &["/Priority".into()], true).is_ok());
+    }
+
+    #[test]
+    fn test_project_regions() {
+        let job: serde_json::Value =
+            nomad::test::full_job_with(&[("/Multiregion", nomad::test::MULTIREGION)]);
+        let projected = project_fields(&job, &["Regions".to_string()], false);
+        assert_eq!(projected["Regions"], json!(["west", "east"]));
+    }
+
+    #[test]
+    fn test_cluster_info() {
+        let mut client = RoutedClient::new(vec![
+            (
+                "agent/self",
+                Reply::Body(200, nomad::test::AGENT_SELF.into()),
+            ),
+            (
+                "status/leader",
+                Reply::Body(200, r#""10.0.0.12:4647""#.into()),
+            ),
+        ]);
+        let info = cluster_info(&mut client, "http://nomad.example:4646").unwrap();
+        assert_eq!(
+            info,
+            json!({
+                "Address": "http://nomad.example:4646",
+                "Server": "server-1.global",
+                "ServerAddr": "10.0.0.11:4648",
+                "Region": "global",
+                "Datacenter": "dc1",
+                "Version": "1.0.0-beta3",
+                "Leader": "10.0.0.12:4647",
+            })
+        );
+    }
+
+    #[test]
+    fn test_fetch_jobs_head() {
+        let mut client = RoutedClient::new(vec![
+            ("job/first", Reply::Body(200, nomad::test::FULL_JOB.into())),
+            ("job/second", Reply::Body(200, nomad::test::FULL_JOB.into())),
+            ("job/third", Reply::Body(200, nomad::test::FULL_JOB.into())),
+        ]);
+        let listing = listing_with_ids(&["first", "second", "third"]);
+        let fetched = fetch_jobs(
+            &mut client,
+            listing,
+            &JobFilter::default(),
+            false,
+            false,
+            Some(2),
+        )
+        .unwrap();
+        let ids: Vec<&str> = fetched
+            .jobs
+            .iter()
+            .map(|matched| matched.listing.ID.as_str())
+            .collect();
+        assert_eq!(ids, vec!["first", "second"]);
+        // The third job is never fetched
+        assert_eq!(client.requests, vec!["job/first", "job/second"]);
+    }
+
+    #[test]
+    fn test_take_tail() {
+        let ids = vec!["first", "second", "third"];
+        assert_eq!(take_tail(ids.clone(), 2), vec!["second", "third"]);
+        assert_eq!(take_tail(ids.clone(), 5), ids);
+        assert!(take_tail(ids, 0).is_empty());
+    }
+
+    #[test]
+    fn test_attach_deployments_stuck_only() {
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/stuck/deployment",
+                Reply::Body(200, nomad::test::STUCK_DEPLOYMENT.into()),
+            ),
+            (
+                "job/example/deployment",
+                Reply::Body(200, nomad::test::HEALTHY_DEPLOYMENT.into()),
+            ),
+            ("job/new/deployment", Reply::Body(200, "null".into())),
+        ]);
+        let jobs: Vec<_> = listing_with_ids(&["stuck", "example", "new"])
+            .into_iter()
+            .map(|entry| MatchedJob::new(entry, full_job()))
+            .collect();
+        let stuck = attach_deployments(&mut client, jobs, true, SystemTime::now()).unwrap();
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].listing.ID, "stuck");
+        let rendered = JobView::default().render(&stuck[0]);
+        assert_eq!(
+            rendered["Deployment"]["TaskGroups"]["cache"]["UnhealthyAllocs"],
+            2
+        );
+    }
+
+    #[test]
+    fn test_filter_on_node() {
+        let elsewhere = nomad::test::ALLOCATIONS
+            .replace("client-1", "client-3")
+            .replace("fb2170a8", "9c1d2e3f");
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/example/allocations",
+                Reply::Body(200, nomad::test::ALLOCATIONS.into()),
+            ),
+            ("job/other/allocations", Reply::Body(200, elsewhere)),
+            ("job/late/allocations", Reply::DeadlineExceeded),
+        ]);
+        let jobs: Vec<_> = listing_with_ids(&["example", "other", "late"])
+            .into_iter()
+            .map(|entry| MatchedJob::new(entry, full_job()))
+            .collect();
+        let on_node = filter_on_node(&mut client, jobs, "client-1").unwrap();
+        // The job whose allocations weren't fetched in time can't be checked, so is dropped
+        assert_eq!(on_node.len(), 1);
+        assert_eq!(on_node[0].listing.ID, "example");
+        assert_eq!(
+            client.requests,
+            vec![
+                "job/example/allocations",
+                "job/other/allocations",
+                "job/late/allocations"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_alloc_counts() {
+        let lost = nomad::test::ALLOCATIONS
+            .replace(r#""ClientStatus":"running""#, r#""ClientStatus":"lost""#);
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/example/allocations",
+                Reply::Body(200, nomad::test::ALLOCATIONS.into()),
+            ),
+            ("job/other/allocations", Reply::Body(200, lost)),
+            ("job/late/allocations", Reply::DeadlineExceeded),
+        ]);
+        let jobs: Vec<_> = listing_with_ids(&["example", "other", "late"])
+            .into_iter()
+            .map(|entry| MatchedJob::new(entry, full_job()))
+            .collect();
+        // The job whose allocations weren't fetched in time is left out of the partial counts
+        assert_eq!(
+            alloc_counts(&mut client, &jobs).unwrap(),
+            json!([
+                {"ID": "example", "Allocations": {"running": 2}},
+                {"ID": "other", "Allocations": {"lost": 2}},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_select_jsonpath_table() {
+        let jobs = json!([serde_json::to_value(full_job()).unwrap()]);
+        let selected = select_jsonpath(&jobs, "$[*].TaskGroups[*].RestartPolicy").unwrap();
+        let mut table = Vec::new();
+        output::Table.write(&selected, &mut table).unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "Attempts  Interval       Delay        Mode\n\
+             2         1800000000000  15000000000  fail\n"
+        );
+        assert!(select_jsonpath(&jobs, "$[").is_err());
+    }
+
+    #[test]
+    fn test_deployment_statuses() {
+        let listing = listing_with_ids(&["rolling", "example"]);
+        let rolling = nomad::test::STUCK_DEPLOYMENT.replace(
+            r#""HealthyAllocs":1,"UnhealthyAllocs":2"#,
+            r#""HealthyAllocs":1,"UnhealthyAllocs":0"#,
+        );
+        let mut client = RoutedClient::new(vec![
+            ("job/rolling/deployment", Reply::Body(200, rolling)),
+            (
+                "job/example/deployment",
+                Reply::Body(200, nomad::test::HEALTHY_DEPLOYMENT.into()),
+            ),
+        ]);
+        let statuses = deployment_statuses(&mut client, &listing).unwrap();
+        assert_eq!(statuses[0]["ID"], "rolling");
+        assert_eq!(statuses[0]["Rollout"], "running");
+        assert_eq!(statuses[1]["ID"], "example");
+        assert_eq!(statuses[1]["Rollout"], "complete");
+        assert_eq!(
+            statuses[1]["Description"],
+            "Deployment completed successfully"
+        );
+    }
+
+    #[test]
+    fn test_deployment_statuses_deadline() {
+        let listing = listing_with_ids(&["example", "late"]);
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/example/deployment",
+                Reply::Body(200, nomad::test::HEALTHY_DEPLOYMENT.into()),
+            ),
+            ("job/late/deployment", Reply::DeadlineExceeded),
+        ]);
+        // The statuses fetched in time are still returned
+        let statuses = deployment_statuses(&mut client, &listing).unwrap();
+        assert_eq!(statuses.as_array().unwrap().len(), 1);
+        assert_eq!(statuses[0]["ID"], "example");
+    }
+
+    #[test]
+    fn test_drop_empty_fields() {
+        let view = json!({
+            "ID": "example",
+            "Constraints": null,
+            "Affinities": [],
+            "User": "",
+            "Update": {"Canary": 0, "HealthCheck": null},
+            "Meta": {"owner": null},
+            "Tags": [null, "cache"],
+        });
+        assert_eq!(
+            drop_empty_fields(view.clone(), false),
+            json!({
+                "ID": "example",
+                "Update": {"Canary": 0},
+                "Tags": [null, "cache"],
+            })
+        );
+        assert_eq!(drop_empty_fields(view, true)["User"], "");
+
+        let job = full_job();
+        let rendered = JobView {
+            compact_nulls: true,
+            ..Default::default()
+        }
+        .render(&MatchedJob::new(listing(), job));
+        assert!(rendered.get("Constraints").is_none());
+        assert!(rendered.get("Meta").is_none());
+        assert_eq!(rendered["ID"], "example");
+    }
+
+    #[test]
+    fn test_render_named() {
+        let member = json!({"Name": "server-1.global", "Addr": "10.0.0.11", "Status": "alive"});
+        let view = JobView {
+            fields: vec![String::from("Status")],
+            ..Default::default()
+        };
+        assert_eq!(
+            view.render_named(member.clone()),
+            json!({"Status": "alive", "Name": "server-1.global"})
+        );
+        assert_eq!(JobView::default().render_named(member.clone()), member);
+    }
+
+    #[test]
+    fn test_blocked_jobs() {
+        let mut listing = listing_with_ids(&["example", "pending", "queued"]);
+        listing[1].Status = String::from("pending");
+        listing[2]
+            .JobSummary
+            .as_mut()
+            .unwrap()
+            .Summary
+            .get_mut("cache")
+            .unwrap()
+            .Queued = 1;
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/pending/evaluations",
+                Reply::Body(200, nomad::test::BLOCKED_EVALUATIONS.into()),
+            ),
+            ("job/queued/evaluations", Reply::Body(200, "[]".into())),
+        ]);
+        let blocked = blocked_jobs(&mut client, &listing).unwrap();
+        assert_eq!(blocked.as_array().unwrap().len(), 1);
+        assert_eq!(blocked[0]["ID"], "pending");
+        assert_eq!(blocked[0]["Reason"], "created due to placement conflicts");
+        // The running job with nothing queued isn't checked
+        assert_eq!(
+            client.requests,
+            vec!["job/pending/evaluations", "job/queued/evaluations"]
+        );
+    }
+
+    #[test]
+    fn test_blocked_jobs_deadline() {
+        let mut listing = listing_with_ids(&["pending", "late"]);
+        for entry in &mut listing {
+            entry.Status = String::from("pending");
+        }
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/pending/evaluations",
+                Reply::Body(200, nomad::test::BLOCKED_EVALUATIONS.into()),
+            ),
+            ("job/late/evaluations", Reply::DeadlineExceeded),
+        ]);
+        let blocked = blocked_jobs(&mut client, &listing).unwrap();
+        assert_eq!(blocked.as_array().unwrap().len(), 1);
+        assert_eq!(blocked[0]["ID"], "pending");
+    }
+
+    #[test]
+    fn test_validate_jobs() {
+        let unexpected =
+            nomad::test::full_job_with::<serde_json::Value>(&[("/TaskGroups/0/Count", r#""one""#)])
+                .to_string();
+        let mut client = RoutedClient::new(vec![
+            (
+                "job/example",
+                Reply::Body(200, nomad::test::FULL_JOB.into()),
+            ),
+            ("job/unexpected", Reply::Body(200, unexpected)),
+        ]);
+        let failures = validate_jobs(&mut client, listing_with_ids(&["example", "unexpected"]));
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "unexpected");
+        let report = error_report(&failures);
+        assert!(report[0]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid type: string \"one\", expected u64"));
+    }
+
+    #[test]
+    fn test_validate_jobs_deadline() {
+        let unexpected =
+            nomad::test::full_job_with::<serde_json::Value>(&[("/TaskGroups/0/Count", r#""one""#)])
+                .to_string();
+        let mut client = RoutedClient::new(vec![
+            ("job/unexpected", Reply::Body(200, unexpected)),
+            ("job/late", Reply::DeadlineExceeded),
+        ]);
+        // Running out of time isn't the job's fault, so only the failures found in time are kept
+        let failures = validate_jobs(&mut client, listing_with_ids(&["unexpected", "late"]));
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "unexpected");
+    }
+
+    #[test]
+    fn test_attach_deployments() {
+        let mut client = RoutedClient::new(vec![(
+            "job/example/deployment",
+            Reply::Body(200, nomad::test::HEALTHY_DEPLOYMENT.into()),
+        )]);
+        let jobs = vec![matched()];
+        let jobs = attach_deployments(&mut client, jobs, false, SystemTime::now()).unwrap();
+        let view = JobView {
+            fields: vec!["Deployment.TaskGroups.cache.HealthyAllocs".into()],
+            ..Default::default()
+        };
+        let rendered = view.render(&jobs[0]);
+        assert_eq!(rendered["Deployment.TaskGroups.cache.HealthyAllocs"], 1);
+    }
+
+    #[test]
+    fn test_view_merge_listing() {
+        let view = JobView {
+            merge_listing: true,
+            ..Default::default()
+        };
+        let rendered = view.render(&matched());
+        assert_eq!(rendered["ID"], "example");
+        assert_eq!(
+            rendered["Listing"]["JobSummary"]["Summary"]["cache"]["Running"],
+            1
+        );
+        let rendered = JobView::default().render(&matched());
+        assert!(rendered.get("Listing").is_none());
+    }
+
+    #[test]
+    fn test_view_merge_listing_projected() {
+        let view = JobView {
+            fields: vec!["Listing.JobSummary.Summary.cache.Running".into()],
+            merge_listing: true,
+            ..Default::default()
+        };
+        let rendered = view.render(&matched());
+        assert_eq!(rendered["Listing.JobSummary.Summary.cache.Running"], 1);
+    }
+
+    #[test]
+    fn test_view_strip_extra() {
+        let view = JobView {
+            strip_extra: true,
+            ..Default::default()
+        };
+        let rendered = view.render(&matched());
+        assert_eq!(rendered["ID"], "example");
+        assert!(rendered.get("AllAtOnce").is_none());
+        assert!(rendered.get("Region").is_none());
+        let rendered = JobView::default().render(&matched());
+        assert!(rendered.get("AllAtOnce").is_some());
+    }
+
+    #[test]
+    fn test_fetch_job_record() {
+        let mut client = nomad::test::TestClient::new(200, "OK", nomad::test::FULL_JOB);
+        let record = fetch_job_record(
+            &mut client,
+            listing(),
+            &JobFilter::default(),
+            &JobView::default(),
+        )
+        .unwrap();
+        assert_eq!(record["id"], "example");
+        assert!(record["fetched_at"].is_string());
+        assert!(record["duration_ms"].is_u64());
+        assert_eq!(record["job"]["ID"], "example");
+        assert!(record.get("error").is_none());
+    }
+
+    #[test]
+    fn test_fetch_job_record_error() {
+        let mut client = nomad::test::TestClient::new(400, "Bad Request", "");
+        let record = fetch_job_record(
+            &mut client,
+            listing(),
+            &JobFilter::default(),
+            &JobView::default(),
+        )
+        .unwrap();
+        assert_eq!(record["id"], "example");
+        assert_eq!(record["error"], "failed to read response");
+        assert!(record.get("job").is_none());
+    }
+
+    #[test]
+    fn test_fetch_job_record_filtered() {
+        let mut client = nomad::test::TestClient::new(200, "OK", nomad::test::FULL_JOB);
+        let job_filter = JobFilter {
+            max_disk: Some(100),
+            ..Default::default()
+        };
+        assert!(
+            fetch_job_record(&mut client, listing(), &job_filter, &JobView::default()).is_none()
+        );
     }
 }
@@ -1,57 +1,1315 @@
+use crate::tls::TlsConfig;
 use anyhow::{anyhow, Result};
-use log::trace;
+use log::{log_enabled, trace, Level};
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, io};
+use url::Url;
+
+/// The error a server gives for a query that needs the leader while leadership is changing hands
+const NO_LEADER_ERROR: &str = "No cluster leader";
+
+/// How long to wait before retrying a request that failed for want of a leader, which is usually
+/// long enough for a new one to be elected
+const NO_LEADER_RETRY_DELAY: Duration = Duration::from_millis(500);
 
 #[derive(Clone, Debug)]
 pub struct Client {
     address: String,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    max_redirects: u32,
+    stale: bool,
+    /// The ACL token sent with each request, if any
+    token: Option<String>,
+    /// The TLS settings for an agent served over HTTPS, if not the defaults
+    tls: Option<TlsConfig>,
+    /// When every request made through this client must have finished by, if ever
+    deadline: Option<Instant>,
+}
+
+impl Client {
+    /// Bound how long each request made through this client may take.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The longest a request may take before it is abandoned
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Bound how long connecting to the agent may take, independently of how long it then takes to
+    /// respond.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The longest connecting may take before the request is abandoned
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Bound how long the agent may go without sending any of its response once connected.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The longest each read of the response may take before the request is abandoned
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Bound when every request made through this client must have finished by. Each request may
+    /// take only what's left until then, and none is started once it has passed.
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - When requests are abandoned, however long they've been running
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Limit how many redirects each request made through this client will follow.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_redirects` - The most redirects to follow before failing, or 0 to not follow any
+    pub fn with_max_redirects(mut self, max_redirects: u32) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Allow requests made through this client to be served by any server, not just the leader.
+    /// This spreads the load of reads across the cluster, but results may be slightly out of date.
+    ///
+    /// # Arguments
+    ///
+    /// * `stale` - Whether to allow stale reads
+    pub fn with_stale(mut self, stale: bool) -> Self {
+        self.stale = stale;
+        self
+    }
+
+    /// Send an ACL token with each request made through this client, in place of any token in
+    /// `NOMAD_TOKEN`.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The token's secret ID
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Make requests through this client with custom TLS settings, e.g. to trust a private CA.
+    ///
+    /// # Arguments
+    ///
+    /// * `tls` - The TLS settings to use
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// The URL of a resource on the agent, with any query options this client adds. The address is
+    /// parsed rather than concatenated, so IPv6 literals like `http://[::1]:4646` and addresses
+    /// with a trailing slash or a path prefix are handled.
+    fn url(&self, resource: &str) -> Result<String> {
+        let mut base = parse_address(&self.address)
+            .map_err(|err| anyhow!("invalid Nomad address: {}", err))?;
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        let mut url = base
+            .join(&format!("v1/{}", resource))
+            .map_err(|err| anyhow!("invalid resource {}: {}", resource, err))?;
+        if self.stale {
+            url.query_pairs_mut().append_key_only("stale");
+        }
+        Ok(url.into())
+    }
+
+    /// Build a request for the given URL with this client's timeout and redirect policy.
+    fn request(&self, url: &str) -> ureq::Request {
+        let mut request = ureq::get(url);
+        request.redirects(self.max_redirects);
+        let remaining = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if let Some(timeout) = self.timeout.into_iter().chain(remaining).min() {
+            request.timeout(timeout);
+        }
+        // ureq also gives the response headers whatever is left of the connect timeout, with the
+        // read timeout only applying to the body after that
+        if let Some(timeout) = self.connect_timeout {
+            request.timeout_connect(timeout.as_millis() as u64);
+        }
+        if let Some(timeout) = self.read_timeout {
+            request.timeout_read(timeout.as_millis() as u64);
+        }
+        if let Some(token) = &self.token {
+            request.set("X-Nomad-Token", token);
+        }
+        if let Some(tls) = &self.tls {
+            request.set_tls_config(tls.0.clone());
+        }
+        request
+    }
+
+    /// Whether the client's deadline, if it has one, has passed.
+    fn past_deadline(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// The address of the Nomad agent this client talks to.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The ACL token this client sends with its requests, if any.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+}
+
+/// The error returned when a request doesn't complete within the client's timeout
+#[derive(Debug)]
+pub struct Timeout {
+    pub resource: String,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request for {} timed out", self.resource)
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// The error returned when a request is cut short, or never started, because the client's deadline
+/// passed
+#[derive(Debug)]
+pub struct DeadlineExceeded {
+    pub resource: String,
+}
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline exceeded before {} was fetched", self.resource)
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// The error returned when the cluster's ACLs don't allow a request
+#[derive(Debug)]
+pub struct PermissionDenied {
+    pub resource: String,
+    /// Why the server refused, as it described it, e.g. `ACL token not found`
+    pub reason: String,
+    /// Whether the request was made with a token
+    pub with_token: bool,
+}
+
+impl fmt::Display for PermissionDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.with_token {
+            write!(
+                f,
+                "permission denied for {} ({}): check that the ACL token is valid and its \
+                 policies allow reading it",
+                self.resource, self.reason
+            )
+        } else {
+            write!(
+                f,
+                "permission denied for {}: the cluster has ACLs enabled, so set NOMAD_TOKEN or \
+                 pass --token",
+                self.resource
+            )
+        }
+    }
+}
+
+impl std::error::Error for PermissionDenied {}
+
+/// The error returned when a job doesn't match nquery's model of it
+#[derive(Debug)]
+pub struct DecodeError {
+    /// Where in the job decoding failed, e.g. `TaskGroups[0].Count`, or empty for the top level
+    pub path: String,
+    /// Why decoding failed, as serde described it
+    pub message: String,
+    /// The raw JSON surrounding the failure
+    pub snippet: String,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{} at {}", self.message, self.path)
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// The longest snippet of raw JSON included in a `DecodeError`, in characters
+const DECODE_SNIPPET_LEN: usize = 240;
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct ParameterizedJob {
+    pub Payload: String,
+    pub MetaRequired: Option<Vec<String>>,
+    pub MetaOptional: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct Periodic {
+    pub Enabled: bool,
+    pub Spec: String,
+    pub SpecType: String,
+    pub ProhibitOverlap: bool,
+    /// The time zone the spec is evaluated in, if not UTC
+    #[serde(default)]
+    pub TimeZone: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct EphemeralDisk {
+    pub Sticky: bool,
+    pub SizeMB: u64,
+    pub Migrate: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct RestartPolicy {
+    pub Attempts: u64,
+    pub Interval: u64,
+    pub Delay: u64,
+    pub Mode: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct Port {
+    pub Label: String,
+    pub Value: u64,
+    pub To: i64,
+    pub HostNetwork: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct NetworkResource {
+    pub Mode: String,
+    pub MBits: u64,
+    pub ReservedPorts: Option<Vec<Port>>,
+    pub DynamicPorts: Option<Vec<Port>>,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+/// A placement rule comparing a node attribute, e.g. `${attr.kernel.name}`, against a value
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct Constraint {
+    pub LTarget: String,
+    pub RTarget: String,
+    pub Operand: String,
+}
+
+/// A placement preference for nodes matching a condition, weighted from -100 to 100
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct Affinity {
+    pub LTarget: String,
+    pub RTarget: String,
+    pub Operand: String,
+    pub Weight: i64,
+}
+
+/// The share of allocations a spread aims to place on nodes with an attribute's value
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct SpreadTarget {
+    pub Value: String,
+    pub Percent: u64,
+}
+
+/// A placement preference for spreading allocations across the values of a node attribute
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct Spread {
+    pub Attribute: String,
+    pub Weight: i64,
+    pub SpreadTarget: Option<Vec<SpreadTarget>>,
+}
+
+impl Constraint {
+    /// Check whether either side of the constraint is the given attribute. The attribute can be
+    /// given with or without its `${...}` interpolation.
+    ///
+    /// # Arguments
+    ///
+    /// * `attribute` - The attribute to look for, e.g. `attr.kernel.name`
+    pub fn references(&self, attribute: &str) -> bool {
+        let unwrap = |target: &str| {
+            let target = target.trim();
+            target
+                .strip_prefix("${")
+                .and_then(|target| target.strip_suffix('}'))
+                .unwrap_or(target)
+                .to_string()
+        };
+        let attribute = unwrap(attribute);
+        unwrap(&self.LTarget) == attribute || unwrap(&self.RTarget) == attribute
+    }
+}
+
+/// A health check on a service
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct ServiceCheck {
+    pub Name: String,
+    /// How the check is made: tcp, http, grpc or script
+    pub Type: String,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct Service {
+    pub Name: String,
+    /// The label of the port the service is advertised on
+    pub PortLabel: String,
+    /// The tags the service is registered in Consul with
+    pub Tags: Option<Vec<String>>,
+    pub Checks: Option<Vec<ServiceCheck>>,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+/// A file or archive downloaded into a task's directory before it starts
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct TaskArtifact {
+    /// Where the artifact is downloaded from
+    pub GetterSource: String,
+    /// Where in the task directory the artifact is put
+    pub RelativeDest: String,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+/// A device a task needs on the node it's placed on, such as a GPU
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct RequestedDevice {
+    /// The device, given as `type`, `vendor/type` or `vendor/type/model`, e.g. `nvidia/gpu`
+    pub Name: String,
+    pub Count: u64,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct Resources {
+    pub Networks: Option<Vec<NetworkResource>>,
+    pub Devices: Option<Vec<RequestedDevice>>,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct Task {
+    pub Name: String,
+    pub Driver: String,
+    /// Driver-specific configuration, e.g. the image for the docker driver
+    pub Config: Option<HashMap<String, Value>>,
+    /// The environment variables set for the task
+    pub Env: Option<HashMap<String, String>>,
+    pub Resources: Option<Resources>,
+    pub Services: Option<Vec<Service>>,
+    pub Constraints: Option<Vec<Constraint>>,
+    pub Meta: Option<HashMap<String, String>>,
+    pub Artifacts: Option<Vec<TaskArtifact>>,
+    /// How long to wait, in nanoseconds, between deregistering the task's services and killing it
+    pub ShutdownDelay: Option<u64>,
+    /// The signal sent to stop the task, or empty for the driver's default
+    #[serde(default)]
+    pub KillSignal: String,
+    /// How long to wait, in nanoseconds, after signalling the task before force killing it
+    pub KillTimeout: Option<u64>,
+    /// When the task runs relative to the group's main tasks, if it isn't one of them
+    pub Lifecycle: Option<TaskLifecycle>,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+/// When a task runs relative to the main tasks of its group
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct TaskLifecycle {
+    /// prestart, poststart or poststop
+    pub Hook: String,
+    /// Whether the task keeps running alongside the main tasks, rather than running to completion
+    pub Sidecar: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct TaskGroup {
+    pub Name: String,
+    /// The number of allocations of the group that should be running
+    pub Count: u64,
+    pub EphemeralDisk: Option<EphemeralDisk>,
+    pub RestartPolicy: Option<RestartPolicy>,
+    pub Networks: Option<Vec<NetworkResource>>,
+    pub Services: Option<Vec<Service>>,
+    pub Constraints: Option<Vec<Constraint>>,
+    pub Affinities: Option<Vec<Affinity>>,
+    pub Spreads: Option<Vec<Spread>>,
+    pub Meta: Option<HashMap<String, String>>,
+    /// How long to wait, in nanoseconds, between deregistering the group's services and killing
+    /// its tasks
+    pub ShutdownDelay: Option<u64>,
+    pub Tasks: Option<Vec<Task>>,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct MultiregionStrategy {
+    pub MaxParallel: u64,
+    pub OnFailure: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct MultiregionRegion {
+    pub Name: String,
+    pub Count: u64,
+    pub Datacenters: Option<Vec<String>>,
+    pub Meta: Option<HashMap<String, String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct Multiregion {
+    pub Strategy: Option<MultiregionStrategy>,
+    pub Regions: Option<Vec<MultiregionRegion>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct TaskGroupSummary {
+    pub Queued: u64,
+    pub Complete: u64,
+    pub Failed: u64,
+    pub Running: u64,
+    pub Starting: u64,
+    pub Lost: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct JobSummary {
+    pub Summary: HashMap<String, TaskGroupSummary>,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct JobListing {
+    pub ID: String,
+    pub ParentID: String,
+    pub Name: String,
+    pub Type: String,
+    /// How the scheduler ranks the job against others when placing allocations, from 1 to 100
+    #[serde(default)]
+    pub Priority: Option<u64>,
+    pub Status: String,
+    /// Why the job is in its status, e.g. which drivers its nodes are missing
+    #[serde(default)]
+    pub StatusDescription: String,
+    /// The datacenters the job may run in, where `*` means any of them
+    #[serde(default)]
+    pub Datacenters: Option<Vec<String>>,
+    pub ParameterizedJob: Option<bool>,
+    pub Periodic: Option<bool>,
+    /// When the job was submitted, in nanoseconds since the Unix epoch
+    pub SubmitTime: Option<u64>,
+    /// The Raft index at which the job's specification was last changed
+    pub JobModifyIndex: u64,
+    /// The Raft index at which the job last changed in any way, such as its status as well as its
+    /// specification
+    #[serde(default)]
+    pub ModifyIndex: u64,
+    /// The namespace the job lives in, which requests for the job must be made in
+    #[serde(default)]
+    pub Namespace: String,
+    // Only present in the job listing, not on fully-defined Job objects
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub JobSummary: Option<JobSummary>,
+}
+
+impl JobListing {
+    /// The number of allocations running across all of the job's task groups, if the listing
+    /// included a summary.
+    pub fn running_count(&self) -> Option<u64> {
+        self.JobSummary
+            .as_ref()
+            .map(|summary| summary.Summary.values().map(|group| group.Running).sum())
+    }
+
+    /// The number of allocations waiting to be placed across all of the job's task groups, if the
+    /// listing included a summary.
+    pub fn queued_count(&self) -> Option<u64> {
+        self.JobSummary
+            .as_ref()
+            .map(|summary| summary.Summary.values().map(|group| group.Queued).sum())
+    }
+
+    /// Check whether the job has finished successfully: it's dead, and its allocations all completed
+    /// without any failing or being lost. Jobs listed without a summary never count as complete.
+    pub fn is_complete(&self) -> bool {
+        let groups = match &self.JobSummary {
+            Some(summary) if self.Status == "dead" => summary.Summary.values(),
+            _ => return false,
+        };
+        let mut complete = 0;
+        for group in groups {
+            if group.Failed + group.Lost + group.Running + group.Starting + group.Queued > 0 {
+                return false;
+            }
+            complete += group.Complete;
+        }
+        complete > 0
+    }
+
+    /// Check whether the job was dispatched from a parameterized job, going by the ID Nomad gives
+    /// dispatched jobs: the parent's ID followed by `/dispatch-`.
+    pub fn is_dispatched(&self) -> bool {
+        !self.ParentID.is_empty()
+            && self
+                .ID
+                .strip_prefix(self.ParentID.as_str())
+                .is_some_and(|rest| rest.starts_with("/dispatch-"))
+    }
+
+    /// How a dispatched job turned out, once it has finished, going by the listing's summary. It has
+    /// finished once it's dead or has nothing queued, starting or running, as a failed allocation
+    /// may still be rescheduled until then. Lost allocations count as failed, as for
+    /// `is_complete`. Jobs that weren't dispatched, haven't finished, or weren't listed with a
+    /// summary have no outcome.
+    pub fn dispatch_status(&self) -> Option<DispatchStatus> {
+        if !self.is_dispatched() {
+            return None;
+        }
+        let groups = &self.JobSummary.as_ref()?.Summary;
+        let total = |count: fn(&TaskGroupSummary) -> u64| groups.values().map(count).sum::<u64>();
+        let finished = self.Status == "dead"
+            || total(|group| group.Queued + group.Starting + group.Running) == 0;
+        if !finished {
+            None
+        } else if total(|group| group.Failed + group.Lost) > 0 {
+            Some(DispatchStatus::Failed)
+        } else if total(|group| group.Complete) > 0 {
+            Some(DispatchStatus::Complete)
+        } else {
+            None
+        }
+    }
+
+    /// Check whether the job may run in any datacenter, having been given the `*` wildcard.
+    pub fn runs_anywhere(&self) -> bool {
+        self.Datacenters
+            .iter()
+            .flatten()
+            .any(|datacenter| datacenter == "*")
+    }
+
+    /// Check whether the job may run in a datacenter, either by name or through the wildcard.
+    ///
+    /// # Arguments
+    ///
+    /// * `datacenter` - The datacenter's name
+    pub fn runs_in(&self, datacenter: &str) -> bool {
+        self.runs_anywhere()
+            || self
+                .Datacenters
+                .iter()
+                .flatten()
+                .any(|candidate| candidate == datacenter)
+    }
+}
+
+/// How a dispatched batch job turned out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DispatchStatus {
+    /// Every allocation completed successfully
+    Complete,
+    /// At least one allocation failed or was lost
+    Failed,
+}
+
+impl FromStr for DispatchStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "complete" => Ok(DispatchStatus::Complete),
+            "failed" => Ok(DispatchStatus::Failed),
+            _ => Err(anyhow!("unknown dispatch status: {}", s)),
+        }
+    }
+}
+
+/// The levels of a job that metadata can be set at
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum MetaScope {
+    #[default]
+    Job,
+    Group,
+    Task,
+    /// Every level
+    Any,
+}
+
+impl FromStr for MetaScope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "job" => Ok(MetaScope::Job),
+            "group" => Ok(MetaScope::Group),
+            "task" => Ok(MetaScope::Task),
+            "any" => Ok(MetaScope::Any),
+            _ => Err(anyhow!("unknown meta scope: {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct Job {
+    /// The fields shared with the job listing. Being flattened, these are serialized at the top
+    /// level of the job, but have to be accessed through here.
+    #[serde(flatten)]
+    pub listing: JobListing,
+    /// The job's version, incremented each time its specification changes
+    pub Version: u64,
+    /// Whether this version of the job has been marked stable after a successful deployment
+    pub Stable: bool,
+    // Annoyingly, these fields have different types in a fullly-defined Job object
+    pub ParameterizedJob: Option<ParameterizedJob>,
+    pub Periodic: Option<Periodic>,
+    pub TaskGroups: Option<Vec<TaskGroup>>,
+    pub Multiregion: Option<Multiregion>,
+    pub Constraints: Option<Vec<Constraint>>,
+    pub Affinities: Option<Vec<Affinity>>,
+    pub Spreads: Option<Vec<Spread>>,
+    pub Meta: Option<HashMap<String, String>>,
+    /// Whether the job was dispatched from a parameterized job
+    #[serde(default)]
+    pub Dispatched: bool,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+impl Job {
+    /// Serialize only the fields nquery explicitly models, dropping everything that was captured
+    /// in the catch-all `extra` maps.
+    pub fn to_modeled_value(&self) -> serde_json::Result<Value> {
+        let mut value = serde_json::to_value(self)?;
+        strip_keys(&mut value, self.extra.keys());
+        let strip_network = |network: &NetworkResource, network_value: &mut Value| {
+            strip_keys(network_value, network.extra.keys())
+        };
+        let strip_service = |service: &Service, service_value: &mut Value| {
+            strip_keys(service_value, service.extra.keys());
+            strip_each(
+                service.Checks.as_ref(),
+                service_value.get_mut("Checks"),
+                |check, check_value| strip_keys(check_value, check.extra.keys()),
+            );
+        };
+        strip_each(
+            self.TaskGroups.as_ref(),
+            value.get_mut("TaskGroups"),
+            |group, group_value| {
+                strip_keys(group_value, group.extra.keys());
+                strip_each(
+                    group.Networks.as_ref(),
+                    group_value.get_mut("Networks"),
+                    strip_network,
+                );
+                strip_each(
+                    group.Services.as_ref(),
+                    group_value.get_mut("Services"),
+                    strip_service,
+                );
+                strip_each(
+                    group.Tasks.as_ref(),
+                    group_value.get_mut("Tasks"),
+                    |task, task_value| {
+                        strip_keys(task_value, task.extra.keys());
+                        strip_each(
+                            task.Services.as_ref(),
+                            task_value.get_mut("Services"),
+                            strip_service,
+                        );
+                        strip_each(
+                            task.Artifacts.as_ref(),
+                            task_value.get_mut("Artifacts"),
+                            |artifact, artifact_value| {
+                                strip_keys(artifact_value, artifact.extra.keys())
+                            },
+                        );
+                        if let (Some(resources), Some(resources_value)) =
+                            (&task.Resources, task_value.get_mut("Resources"))
+                        {
+                            strip_keys(resources_value, resources.extra.keys());
+                            strip_each(
+                                resources.Networks.as_ref(),
+                                resources_value.get_mut("Networks"),
+                                strip_network,
+                            );
+                            strip_each(
+                                resources.Devices.as_ref(),
+                                resources_value.get_mut("Devices"),
+                                |device, device_value| {
+                                    strip_keys(device_value, device.extra.keys())
+                                },
+                            );
+                        }
+                    },
+                );
+            },
+        );
+        Ok(value)
+    }
+
+    /// Serialize the job with a stable key order: at each level, the fields nquery models come first,
+    /// in the order they're declared, followed by everything else sorted alphabetically.
+    pub fn to_canonical_value(&self) -> serde_json::Result<Value> {
+        let value = serde_json::to_value(self)?;
+        let modeled = self.to_modeled_value()?;
+        Ok(order_like(value, Some(&modeled)))
+    }
+
+    /// Every port exposed by the job, whether dynamic or reserved, and whether requested by a task
+    /// group or one of its tasks.
+    pub fn ports(&self) -> Vec<&Port> {
+        let mut ports = Vec::new();
+        for group in self.TaskGroups.iter().flatten() {
+            let task_networks = group
+                .Tasks
+                .iter()
+                .flatten()
+                .filter_map(|task| task.Resources.as_ref())
+                .flat_map(|resources| resources.Networks.iter().flatten());
+            for network in group.Networks.iter().flatten().chain(task_networks) {
+                ports.extend(network.DynamicPorts.iter().flatten());
+                ports.extend(network.ReservedPorts.iter().flatten());
+            }
+        }
+        ports
+    }
+
+    /// The total ephemeral disk, in MB, reserved across all of the job's task groups.
+    pub fn ephemeral_disk_mb(&self) -> u64 {
+        self.TaskGroups
+            .iter()
+            .flatten()
+            .filter_map(|group| group.EphemeralDisk.as_ref())
+            .map(|disk| disk.SizeMB)
+            .sum()
+    }
+
+    /// The distinct images run by the job's tasks, as given by their driver config, in order of
+    /// appearance.
+    pub fn images(&self) -> Vec<&str> {
+        let mut images: Vec<&str> = Vec::new();
+        let tasks = self
+            .TaskGroups
+            .iter()
+            .flatten()
+            .flat_map(|group| group.Tasks.iter().flatten());
+        for task in tasks {
+            let image = task
+                .Config
+                .as_ref()
+                .and_then(|config| config.get("image"))
+                .and_then(Value::as_str);
+            if let Some(image) = image {
+                if !images.contains(&image) {
+                    images.push(image);
+                }
+            }
+        }
+        images
+    }
+
+    /// Every constraint placed on the job, whether on the job itself, one of its task groups or
+    /// one of their tasks.
+    pub fn constraints(&self) -> Vec<&Constraint> {
+        let mut constraints: Vec<&Constraint> = self.Constraints.iter().flatten().collect();
+        for group in self.TaskGroups.iter().flatten() {
+            constraints.extend(group.Constraints.iter().flatten());
+            for task in group.Tasks.iter().flatten() {
+                constraints.extend(task.Constraints.iter().flatten());
+            }
+        }
+        constraints
+    }
+
+    /// Every service the job registers, whether defined on a task group or one of its tasks.
+    pub fn services(&self) -> Vec<&Service> {
+        let mut services = Vec::new();
+        for group in self.TaskGroups.iter().flatten() {
+            services.extend(group.Services.iter().flatten());
+            for task in group.Tasks.iter().flatten() {
+                services.extend(task.Services.iter().flatten());
+            }
+        }
+        services
+    }
+
+    /// Every task in the job, along with the name of the group it belongs to.
+    pub fn tasks(&self) -> Vec<(&str, &Task)> {
+        self.TaskGroups
+            .iter()
+            .flatten()
+            .flat_map(|group| {
+                group
+                    .Tasks
+                    .iter()
+                    .flatten()
+                    .map(move |task| (group.Name.as_str(), task))
+            })
+            .collect()
+    }
+
+    /// The metadata set on the job at the given level, one map for each job, group or task that
+    /// has any.
+    ///
+    /// # Arguments
+    ///
+    /// * `scope` - The level to collect metadata from
+    pub fn meta(&self, scope: MetaScope) -> Vec<&HashMap<String, String>> {
+        let mut meta = Vec::new();
+        if matches!(scope, MetaScope::Job | MetaScope::Any) {
+            meta.extend(self.Meta.iter());
+        }
+        for group in self.TaskGroups.iter().flatten() {
+            if matches!(scope, MetaScope::Group | MetaScope::Any) {
+                meta.extend(group.Meta.iter());
+            }
+            if matches!(scope, MetaScope::Task | MetaScope::Any) {
+                meta.extend(
+                    group
+                        .Tasks
+                        .iter()
+                        .flatten()
+                        .filter_map(|task| task.Meta.as_ref()),
+                );
+            }
+        }
+        meta
+    }
+
+    /// Check whether any of the job's tasks sets an environment variable.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The name of the variable, e.g. `LOG_LEVEL`
+    pub fn sets_env(&self, key: &str) -> bool {
+        self.TaskGroups
+            .iter()
+            .flatten()
+            .flat_map(|group| group.Tasks.iter().flatten())
+            .any(|task| task.Env.as_ref().is_some_and(|env| env.contains_key(key)))
+    }
+
+    /// Check whether any of the job's task groups or tasks waits before being killed, giving
+    /// traffic time to drain.
+    pub fn has_shutdown_delay(&self) -> bool {
+        self.TaskGroups.iter().flatten().any(|group| {
+            group.ShutdownDelay.unwrap_or(0) > 0
+                || group
+                    .Tasks
+                    .iter()
+                    .flatten()
+                    .any(|task| task.ShutdownDelay.unwrap_or(0) > 0)
+        })
+    }
+
+    /// Check whether the job, or any of its task groups, spreads its allocations across the values
+    /// of a node attribute.
+    pub fn has_spread(&self) -> bool {
+        let defined =
+            |spreads: &Option<Vec<Spread>>| spreads.as_ref().is_some_and(|s| !s.is_empty());
+        defined(&self.Spreads)
+            || self
+                .TaskGroups
+                .iter()
+                .flatten()
+                .any(|group| defined(&group.Spreads))
+    }
+
+    /// Check whether the job, or any of its task groups, prefers nodes through an affinity.
+    pub fn has_affinity(&self) -> bool {
+        let defined =
+            |affinities: &Option<Vec<Affinity>>| affinities.as_ref().is_some_and(|a| !a.is_empty());
+        defined(&self.Affinities)
+            || self
+                .TaskGroups
+                .iter()
+                .flatten()
+                .any(|group| defined(&group.Affinities))
+    }
+
+    /// Check whether any of the job's tasks requests a device.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The device's name, exactly as the task requests it, e.g. `nvidia/gpu`
+    pub fn uses_device(&self, name: &str) -> bool {
+        self.TaskGroups
+            .iter()
+            .flatten()
+            .flat_map(|group| group.Tasks.iter().flatten())
+            .filter_map(|task| task.Resources.as_ref())
+            .flat_map(|resources| resources.Devices.iter().flatten())
+            .any(|device| device.Name == name)
+    }
+
+    /// Check whether any of the job's tasks downloads artifacts.
+    pub fn uses_artifacts(&self) -> bool {
+        self.TaskGroups
+            .iter()
+            .flatten()
+            .flat_map(|group| group.Tasks.iter().flatten())
+            .any(|task| {
+                task.Artifacts
+                    .as_ref()
+                    .is_some_and(|artifacts| !artifacts.is_empty())
+            })
+    }
+
+    /// Check whether any of the job's services has a health check of the given type, ignoring case.
+    ///
+    /// # Arguments
+    ///
+    /// * `check_type` - The type of check, e.g. `tcp` or `http`
+    pub fn has_check_type(&self, check_type: &str) -> bool {
+        self.services()
+            .iter()
+            .flat_map(|service| service.Checks.iter().flatten())
+            .any(|check| check.Type.eq_ignore_ascii_case(check_type))
+    }
+
+    /// The distinct Consul tags across every service the job registers.
+    pub fn service_tags(&self) -> Vec<&str> {
+        let mut tags: Vec<&str> = Vec::new();
+        for service in self.services() {
+            for tag in service.Tags.iter().flatten() {
+                if !tags.contains(&tag.as_str()) {
+                    tags.push(tag);
+                }
+            }
+        }
+        tags
+    }
+
+    /// The total number of allocations that should be running, across all of the job's task groups.
+    pub fn desired_count(&self) -> u64 {
+        self.TaskGroups
+            .iter()
+            .flatten()
+            .map(|group| group.Count)
+            .sum()
+    }
+
+    /// The number of restart attempts allowed by each task group's restart policy.
+    pub fn restart_attempts(&self) -> Vec<u64> {
+        self.TaskGroups
+            .iter()
+            .flatten()
+            .filter_map(|group| group.RestartPolicy.as_ref())
+            .map(|policy| policy.Attempts)
+            .collect()
+    }
+
+    /// The number of regions the job is federated across, or zero if it isn't multiregion.
+    pub fn region_count(&self) -> usize {
+        self.Multiregion
+            .iter()
+            .filter_map(|multiregion| multiregion.Regions.as_ref())
+            .map(Vec::len)
+            .sum()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[allow(non_snake_case)]
-pub struct ParameterizedJob {
-    pub Payload: String,
-    pub MetaRequired: Option<Vec<String>>,
-    pub MetaOptional: Option<Vec<String>>,
+pub struct Namespace {
+    pub Name: String,
+    pub Description: String,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[allow(non_snake_case)]
-pub struct Periodic {
-    pub Enabled: bool,
-    pub Spec: String,
-    pub SpecType: String,
-    pub ProhibitOverlap: bool,
+pub struct DeploymentState {
+    pub DesiredCanaries: u64,
+    pub DesiredTotal: u64,
+    pub PlacedAllocs: u64,
+    pub HealthyAllocs: u64,
+    pub UnhealthyAllocs: u64,
+    pub RequireProgressBy: Option<String>,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[allow(non_snake_case)]
-pub struct JobListing {
+pub struct Deployment {
     pub ID: String,
-    pub ParentID: String,
-    pub Name: String,
-    pub Type: String,
+    pub JobID: String,
     pub Status: String,
-    pub ParameterizedJob: Option<bool>,
-    pub Periodic: Option<bool>,
+    pub StatusDescription: String,
+    pub TaskGroups: HashMap<String, DeploymentState>,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
 }
 
+/// An allocation of one of a job's task groups to a client node
 #[derive(Serialize, Deserialize, Debug)]
 #[allow(non_snake_case)]
-pub struct Job {
+pub struct Allocation {
+    pub ID: String,
+    pub JobID: String,
+    pub TaskGroup: String,
+    pub NodeID: String,
+    pub NodeName: String,
+    pub DesiredStatus: String,
+    pub ClientStatus: String,
+
     #[serde(flatten)]
-    listing: JobListing,
-    // Annoyingly, these fields have different types in a fullly-defined Job object
-    pub ParameterizedJob: Option<ParameterizedJob>,
-    pub Periodic: Option<Periodic>,
+    extra: HashMap<String, Value>,
+}
+
+/// Why some of a task group's allocations couldn't be placed
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+pub struct AllocationMetric {
+    pub NodesEvaluated: u64,
+    pub NodesFiltered: u64,
+    pub NodesExhausted: u64,
+    /// The number of nodes filtered out by each constraint
+    pub ConstraintFiltered: Option<HashMap<String, u64>>,
+    /// The number of nodes that ran out of each resource, e.g. memory
+    pub DimensionExhausted: Option<HashMap<String, u64>>,
+}
+
+/// A scheduling pass over a job, deciding where its allocations should be placed
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+pub struct Evaluation {
+    pub ID: String,
+    pub JobID: String,
+    /// pending, complete, failed, canceled, or blocked when allocations are waiting for capacity
+    pub Status: String,
+    pub StatusDescription: String,
+    /// The evaluation created to retry the placements this one couldn't make
+    #[serde(default)]
+    pub BlockedEval: String,
+    /// The allocations that couldn't be placed, by task group
+    pub FailedTGAllocs: Option<HashMap<String, AllocationMetric>>,
+    pub CreateIndex: u64,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+impl Allocation {
+    /// Whether the allocation was placed on a node, given by name, ID, or an ID prefix as the
+    /// Nomad CLI shows them.
+    pub fn is_on_node(&self, node: &str) -> bool {
+        !node.is_empty() && (self.NodeName == node || self.NodeID.starts_with(node))
+    }
+}
+
+impl Deployment {
+    /// Whether the deployment is still running despite a task group having unhealthy allocations
+    /// past its progress deadline.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The time to compare progress deadlines against
+    pub fn is_stuck(&self, now: SystemTime) -> bool {
+        self.Status == "running"
+            && self.TaskGroups.values().any(|state| {
+                // Deadlines which are unset come back as the zero time, which doesn't parse
+                let deadline = state
+                    .RequireProgressBy
+                    .as_deref()
+                    .and_then(|deadline| humantime::parse_rfc3339(deadline).ok());
+                state.UnhealthyAllocs > 0 && deadline.is_some_and(|deadline| deadline < now)
+            })
+    }
+
+    /// A one-word summary of how the rollout is going: complete once it has succeeded, unhealthy
+    /// if it failed or any allocation is unhealthy, healthy if every wanted allocation is placed
+    /// and healthy but it hasn't been marked successful yet, and running otherwise. Cancelled
+    /// deployments keep their status.
+    pub fn rollout_status(&self) -> &str {
+        let unhealthy = self
+            .TaskGroups
+            .values()
+            .any(|state| state.UnhealthyAllocs > 0);
+        let healthy = self
+            .TaskGroups
+            .values()
+            .all(|state| state.HealthyAllocs >= state.DesiredTotal);
+        match self.Status.as_str() {
+            "successful" => "complete",
+            "failed" => "unhealthy",
+            "running" if unhealthy => "unhealthy",
+            "running" if healthy => "healthy",
+            "running" => "running",
+            other => other,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+pub struct AgentVersion {
+    pub Version: String,
+    pub VersionPrerelease: String,
+    pub Revision: String,
+}
+
+impl fmt::Display for AgentVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.VersionPrerelease.is_empty() {
+            write!(f, "{}", self.Version)
+        } else {
+            write!(f, "{}-{}", self.Version, self.VersionPrerelease)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+pub struct AgentConfig {
+    pub Region: String,
+    pub Datacenter: String,
+    pub NodeName: String,
+    pub Version: AgentVersion,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+pub struct AgentMember {
+    pub Name: String,
+    pub Addr: String,
+    pub Port: u16,
+    /// alive, leaving, left or failed, as gossip sees the member
+    pub Status: String,
+    /// Describe the member's role, e.g. its region, datacenter, and whether it's bootstrapping
+    #[serde(default)]
+    pub Tags: HashMap<String, String>,
+
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
+
+/// The server peers known to the agent being queried, as described by `/v1/agent/members`
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct AgentMembers {
+    Members: Vec<AgentMember>,
+}
+
+/// The ACL token requests are made with, as described by `/v1/acl/token/self`
+#[derive(Serialize, Deserialize, Debug)]
+#[allow(non_snake_case)]
+pub struct AclToken {
+    pub AccessorID: String,
+    pub Name: String,
+    /// client or management
+    pub Type: String,
+    /// When the token stops being accepted, as an RFC 3339 timestamp, if it ever does
+    #[serde(default)]
+    pub ExpirationTime: Option<String>,
 
     #[serde(flatten)]
     extra: HashMap<String, Value>,
 }
 
+impl AclToken {
+    /// How long the token has left if it expires within a threshold, or zero if it already has.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - How soon an expiry must be to be returned
+    /// * `now` - The time to measure what's left from
+    pub fn expires_within(&self, threshold: Duration, now: SystemTime) -> Option<Duration> {
+        let expiry = humantime::parse_rfc3339(self.ExpirationTime.as_deref()?).ok()?;
+        let remaining = expiry.duration_since(now).unwrap_or_default();
+        Some(remaining).filter(|remaining| *remaining < threshold)
+    }
+}
+
+/// The agent being queried, as described by `/v1/agent/self`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AgentSelf {
+    pub config: AgentConfig,
+    pub member: AgentMember,
+}
+
 pub trait NomadClient {
     fn get(&mut self, resource: &str) -> Result<ureq::Response>;
 }
@@ -63,15 +1321,70 @@ impl NomadClient for Client {
     ///
     /// * `resource` the path to the resource being fetched.
     fn get(&mut self, resource: &str) -> Result<ureq::Response> {
-        let url = format!("{}/v1/{}", self.address, resource);
-        let resp = ureq::get(&url).call();
+        let resp = self.get_once(resource)?;
+        if resp.status() == 403 {
+            return Err(PermissionDenied {
+                resource: resource.into(),
+                reason: resp.into_string()?.trim().into(),
+                with_token: self.token.is_some(),
+            }
+            .into());
+        }
+        if resp.status() != 500 {
+            return Ok(resp);
+        }
+        // The body has to be read to tell a missing leader apart from other server errors
+        let status_text = resp.status_text().to_string();
+        let body = resp.into_string()?;
+        if !body.contains(NO_LEADER_ERROR) {
+            return Ok(ureq::Response::new(500, &status_text, &body));
+        }
+        trace!(
+            "No cluster leader for <{}>, retrying in {:?}",
+            resource,
+            NO_LEADER_RETRY_DELAY
+        );
+        std::thread::sleep(NO_LEADER_RETRY_DELAY);
+        self.get_once(resource)
+    }
+}
+
+impl Client {
+    /// Issue a single HTTP Get against the given resource, without retrying.
+    fn get_once(&self, resource: &str) -> Result<ureq::Response> {
+        if self.past_deadline() {
+            return Err(DeadlineExceeded {
+                resource: resource.into(),
+            }
+            .into());
+        }
+        let url = self.url(resource)?;
+        let resp = self.request(&url).call();
         trace!("Response <{}> [{}]", url, resp.status());
+        if log_enabled!(Level::Trace) {
+            for header in header_lines(&resp) {
+                trace!("Response <{}> {}", url, header);
+            }
+        }
         match resp.synthetic_error() {
+            Some(ureq::Error::Io(err)) if err.kind() == io::ErrorKind::TimedOut => {
+                if self.past_deadline() {
+                    Err(DeadlineExceeded {
+                        resource: resource.into(),
+                    }
+                    .into())
+                } else {
+                    Err(Timeout {
+                        resource: resource.into(),
+                    }
+                    .into())
+                }
+            }
             Some(resp) => {
                 let msg = if resp.to_string().contains("Connection refused") {
                     format!("Could not connect to server at {}", &self.address)
                 } else {
-                    format!("{}: {}", resp.status(), resp.to_string())
+                    format!("{}: {}", resp.status(), resp)
                 };
                 Err(anyhow!(msg))
             }
@@ -80,13 +1393,147 @@ impl NomadClient for Client {
     }
 }
 
+/// A `Name: value` line for each header of a response, sorted by name, such as the
+/// `X-Nomad-Index` and `X-Nomad-KnownLeader` headers that describe blocking queries.
+fn header_lines(resp: &ureq::Response) -> Vec<String> {
+    let mut names = resp.headers_names();
+    names.sort();
+    names.dedup();
+    names
+        .iter()
+        .flat_map(|name| {
+            resp.all(name)
+                .into_iter()
+                .map(move |value| format!("{}: {}", name, value))
+        })
+        .collect()
+}
+
+/// Apply `strip` to each modeled item alongside its serialized form.
+fn strip_each<T>(
+    items: Option<&Vec<T>>,
+    values: Option<&mut Value>,
+    strip: impl Fn(&T, &mut Value),
+) {
+    if let (Some(items), Some(Value::Array(values))) = (items, values) {
+        for (item, value) in items.iter().zip(values) {
+            strip(item, value);
+        }
+    }
+}
+
+/// Reorder a value's keys to follow a model of it, recursively. Keys found in the model come first,
+/// in the model's order, followed by the rest sorted alphabetically.
+///
+/// # Arguments
+///
+/// * `value` - The value to reorder
+/// * `model` - The modeled form of the value, if it has one
+fn order_like(value: Value, model: Option<&Value>) -> Value {
+    match value {
+        Value::Object(mut map) => {
+            let model = model.and_then(Value::as_object);
+            let mut ordered = serde_json::Map::new();
+            for (key, child_model) in model.into_iter().flatten() {
+                if let Some(child) = map.remove(key) {
+                    ordered.insert(key.clone(), order_like(child, Some(child_model)));
+                }
+            }
+            let mut rest: Vec<_> = map.into_iter().collect();
+            rest.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (key, child) in rest {
+                ordered.insert(key, order_like(child, None));
+            }
+            Value::Object(ordered)
+        }
+        Value::Array(items) => {
+            let models = model.and_then(Value::as_array);
+            items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| order_like(item, models.and_then(|models| models.get(i))))
+                .collect()
+        }
+        value => value,
+    }
+}
+
+/// Remove the given keys from a serialized object.
+fn strip_keys<'a>(value: &mut Value, keys: impl Iterator<Item = &'a String>) {
+    if let Value::Object(map) = value {
+        for key in keys {
+            map.remove(key);
+        }
+    }
+}
+
+/// The JSON Schemas of the job listing and full job, as nquery models them. Fields nquery doesn't
+/// model are still kept, as additional properties.
+pub fn model_schema() -> Value {
+    let mut generator = schemars::gen::SchemaGenerator::default();
+    // Fields the job has in common with its flattened listing take the listing's schema, but have
+    // different types in a full job, where the job's own fields are the ones decoded
+    let periodic = generator.subschema_for::<Option<Periodic>>();
+    let parameterized = generator.subschema_for::<Option<ParameterizedJob>>();
+    let mut job = generator.into_root_schema_for::<Job>();
+    let properties = &mut job.schema.object().properties;
+    properties.insert(String::from("Periodic"), periodic);
+    properties.insert(String::from("ParameterizedJob"), parameterized);
+    serde_json::json!({
+        "JobListing": schemars::schema_for!(JobListing),
+        "Job": job,
+    })
+}
+
+/// How many redirects a client follows unless told otherwise
+const DEFAULT_MAX_REDIRECTS: u32 = 5;
+
+/// Parse the address of a Nomad agent, which must be an http:// or https:// URL, optionally with
+/// a path prefix, as when the API is behind a reverse proxy.
+///
+/// # Arguments
+///
+/// * `address` - The address, e.g. `http://127.0.0.1:4646`
+pub fn parse_address(address: &str) -> Result<Url> {
+    let url = Url::parse(address).map_err(|err| {
+        anyhow!(
+            "'{}' is not a URL ({}), expected one like http://127.0.0.1:4646",
+            address,
+            err
+        )
+    })?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(anyhow!("'{}' must start with http:// or https://", address));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(anyhow!(
+            "'{}' can't have a query string or fragment",
+            address
+        ));
+    }
+    Ok(url)
+}
+
 /// Get the Nomad client
-pub fn get_client() -> Client {
+///
+/// # Arguments
+///
+/// * `address` - The address of the cluster, overriding `NOMAD_ADDR` if specified
+pub fn get_client(address: Option<String>) -> Client {
     Client {
-        address: match std::env::var("NOMAD_ADDR") {
-            Ok(addr) => addr,
-            Err(_) => String::from("http://127.0.0.1:4646"),
-        },
+        address: address
+            .or_else(|| std::env::var("NOMAD_ADDR").ok())
+            .unwrap_or_else(|| String::from("http://127.0.0.1:4646")),
+        timeout: None,
+        connect_timeout: None,
+        read_timeout: None,
+        max_redirects: DEFAULT_MAX_REDIRECTS,
+        stale: false,
+        token: std::env::var("NOMAD_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty()),
+        tls: None,
+        deadline: None,
     }
 }
 
@@ -95,12 +1542,24 @@ pub fn get_client() -> Client {
 /// # Arguments
 ///
 /// * `prefix` a string prefix which all the returned jobs must match
-pub fn get_jobs(client: &mut dyn NomadClient, prefix: &str) -> Result<Vec<JobListing>> {
-    let path = format!(
+/// * `namespace` the namespace to list jobs in, or `*` for every namespace. Nomad uses the default
+///   namespace if not given.
+pub fn get_jobs(
+    client: &mut dyn NomadClient,
+    prefix: &str,
+    namespace: Option<&str>,
+) -> Result<Vec<JobListing>> {
+    let mut path = format!(
         "{}?prefix={}",
         "jobs",
-        utf8_percent_encode(prefix, NON_ALPHANUMERIC).to_string()
+        utf8_percent_encode(prefix, NON_ALPHANUMERIC)
     );
+    if let Some(namespace) = namespace {
+        path.push_str(&format!(
+            "&namespace={}",
+            utf8_percent_encode(namespace, NON_ALPHANUMERIC)
+        ));
+    }
     let jobs: Vec<JobListing> = match client.get(&path) {
         Ok(resp) => match resp.into_json() {
             Ok(buf) => serde_json::from_value(buf).expect("failed to decode response"),
@@ -108,35 +1567,389 @@ pub fn get_jobs(client: &mut dyn NomadClient, prefix: &str) -> Result<Vec<JobLis
         },
         Err(err) => return Err(err),
     };
-    Ok(jobs)
+    Ok(jobs)
+}
+
+/// The API resource for a job, or one of its sub-resources, in the namespace the job lives in.
+///
+/// # Arguments
+///
+/// * `id` - the ID of the job
+/// * `namespace` - the job's namespace, or empty for Nomad's default
+/// * `sub_resource` - the path under the job, e.g. `/allocations`, or empty for the job itself
+fn job_resource(id: &str, namespace: &str, sub_resource: &str) -> String {
+    let resource = format!("job/{}{}", id, sub_resource);
+    if namespace.is_empty() {
+        resource
+    } else {
+        format!(
+            "{}?namespace={}",
+            resource,
+            utf8_percent_encode(namespace, NON_ALPHANUMERIC)
+        )
+    }
+}
+
+/// Get a job by its ID.
+///
+/// # Arguments
+///
+/// * `id` - the ID of the job to retrieve.
+/// * `namespace` - the namespace the job lives in, as given by its listing.
+pub fn get_job(client: &mut dyn NomadClient, id: &str, namespace: &str) -> Result<Job> {
+    let resource = job_resource(id, namespace, "");
+    let job: Job = match client.get(&resource)?.into_json() {
+        Ok(buf) => decode_job(&buf)?,
+        Err(err) if err.kind() == io::ErrorKind::TimedOut => {
+            return Err(Timeout { resource }.into())
+        }
+        Err(_) => return Err(anyhow!("failed to read response")),
+    };
+    Ok(job)
+}
+
+/// Decode a job, pinpointing where it doesn't match the model if decoding fails.
+///
+/// # Arguments
+///
+/// * `value` - The job's JSON, as returned by Nomad
+fn decode_job(value: &Value) -> Result<Job, DecodeError> {
+    Job::deserialize(value).map_err(|err| locate_decode_error(value, &err.to_string()))
+}
+
+/// Work out where in a job a decoding error happened. The catch-all `extra` fields make serde
+/// buffer each struct before decoding it, which loses track of the position, so the failure is
+/// narrowed down to the innermost task group or task that fails on its own, then to the value
+/// within it that serde complained about.
+///
+/// # Arguments
+///
+/// * `job` - The job's JSON
+/// * `message` - The error serde gave decoding the whole job
+fn locate_decode_error(job: &Value, message: &str) -> DecodeError {
+    let mut path = String::new();
+    let mut scope = job;
+    if let Some((i, group)) = failing_element::<TaskGroup>(job.get("TaskGroups")) {
+        path = format!("TaskGroups[{}]", i);
+        scope = group;
+        if let Some((i, task)) = failing_element::<Task>(group.get("Tasks")) {
+            path.push_str(&format!(".Tasks[{}]", i));
+            scope = task;
+        }
+    }
+    let unexpected = message
+        .strip_prefix("invalid type: ")
+        .or_else(|| message.strip_prefix("invalid value: "))
+        .and_then(|rest| rest.split(", expected").next());
+    let mut surrounding = scope;
+    if let Some((field, parent)) =
+        unexpected.and_then(|unexpected| find_unexpected(scope, unexpected))
+    {
+        if !path.is_empty() && !field.starts_with('[') {
+            path.push('.');
+        }
+        path.push_str(&field);
+        surrounding = parent;
+    }
+    let mut snippet = surrounding.to_string();
+    if let Some((cut, _)) = snippet.char_indices().nth(DECODE_SNIPPET_LEN) {
+        snippet.truncate(cut);
+        snippet.push_str("...");
+    }
+    DecodeError {
+        path,
+        message: message.to_string(),
+        snippet,
+    }
+}
+
+/// Find the first element of an array that doesn't decode as `T`, along with its index.
+fn failing_element<'a, T: Deserialize<'a>>(array: Option<&'a Value>) -> Option<(usize, &'a Value)> {
+    array?
+        .as_array()?
+        .iter()
+        .enumerate()
+        .find(|(_, element)| T::deserialize(*element).is_err())
+}
+
+/// Find the shallowest value under `scope` that serde would describe as `unexpected`, e.g.
+/// `string "one"`, returning its path relative to `scope` and the object or array holding it.
+fn find_unexpected<'a>(scope: &'a Value, unexpected: &str) -> Option<(String, &'a Value)> {
+    let mut queue = std::collections::VecDeque::from(vec![(String::new(), scope)]);
+    while let Some((path, parent)) = queue.pop_front() {
+        let children: Vec<(String, &Value)> = match parent {
+            Value::Object(fields) => fields
+                .iter()
+                .map(|(key, value)| {
+                    let child = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    (child, value)
+                })
+                .collect(),
+            Value::Array(elements) => elements
+                .iter()
+                .enumerate()
+                .map(|(i, value)| (format!("{}[{}]", path, i), value))
+                .collect(),
+            _ => Vec::new(),
+        };
+        for (child, value) in children {
+            if describe_unexpected(value) == unexpected {
+                return Some((child, parent));
+            }
+            queue.push_back((child, value));
+        }
+    }
+    None
+}
+
+/// Describe a value the way serde does when it's of the wrong type.
+fn describe_unexpected(value: &Value) -> String {
+    use serde::de::Unexpected;
+    match value {
+        Value::Null => Unexpected::Unit.to_string(),
+        Value::Bool(b) => Unexpected::Bool(*b).to_string(),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(u), _) => Unexpected::Unsigned(u).to_string(),
+            (None, Some(i)) => Unexpected::Signed(i).to_string(),
+            _ => Unexpected::Float(n.as_f64().unwrap_or_default()).to_string(),
+        },
+        Value::String(s) => Unexpected::Str(s).to_string(),
+        Value::Array(_) => Unexpected::Seq.to_string(),
+        Value::Object(_) => Unexpected::Map.to_string(),
+    }
+}
+
+/// Get a job by its ID, exactly as Nomad returned it, without deserializing it.
+///
+/// # Arguments
+///
+/// * `id` - the ID of the job to retrieve.
+/// * `namespace` - the namespace the job lives in, as given by its listing.
+pub fn get_job_raw(client: &mut dyn NomadClient, id: &str, namespace: &str) -> Result<String> {
+    match client.get(&job_resource(id, namespace, ""))?.into_string() {
+        Ok(body) => Ok(body),
+        Err(_) => Err(anyhow!("failed to read response")),
+    }
+}
+
+/// Check that a job, exactly as Nomad returned it, survives a round trip through the typed `Job`
+/// model: deserializing it, serializing it back, and deserializing that again.
+///
+/// # Arguments
+///
+/// * `raw` - The job's JSON, as returned by Nomad
+pub fn check_round_trip(raw: &str) -> Result<()> {
+    let job: Job = serde_json::from_str(raw)?;
+    let value = serde_json::to_value(&job)?;
+    serde_json::from_value::<Job>(value)
+        .map_err(|err| anyhow!("failed to decode re-serialized job: {}", err))?;
+    Ok(())
+}
+
+/// Get the most recent deployment of a job, if it has ever been deployed.
+///
+/// # Arguments
+///
+/// * `id` - the ID of the job whose deployment to retrieve.
+/// * `namespace` - the namespace the job lives in, as given by its listing.
+pub fn get_deployment(
+    client: &mut dyn NomadClient,
+    id: &str,
+    namespace: &str,
+) -> Result<Option<Deployment>> {
+    let deployment: Option<Deployment> = match client
+        .get(&job_resource(id, namespace, "/deployment"))?
+        .into_json()
+    {
+        Ok(buf) => serde_json::from_value(buf)?,
+        Err(_) => return Err(anyhow!("failed to read response")),
+    };
+    Ok(deployment)
+}
+
+/// Get every allocation of a job, including those that are no longer running.
+///
+/// # Arguments
+///
+/// * `id` - the ID of the job whose allocations to retrieve.
+/// * `namespace` - the namespace the job lives in, as given by its listing.
+pub fn get_allocations(
+    client: &mut dyn NomadClient,
+    id: &str,
+    namespace: &str,
+) -> Result<Vec<Allocation>> {
+    let allocations: Vec<Allocation> = match client
+        .get(&job_resource(id, namespace, "/allocations"))?
+        .into_json()
+    {
+        Ok(buf) => serde_json::from_value(buf)?,
+        Err(_) => return Err(anyhow!("failed to read response")),
+    };
+    Ok(allocations)
+}
+
+/// Get all evaluations of a job.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the job
+/// * `namespace` - The namespace the job lives in, as given by its listing
+pub fn get_evaluations(
+    client: &mut dyn NomadClient,
+    id: &str,
+    namespace: &str,
+) -> Result<Vec<Evaluation>> {
+    let evaluations: Vec<Evaluation> = match client
+        .get(&job_resource(id, namespace, "/evaluations"))?
+        .into_json()
+    {
+        Ok(buf) => serde_json::from_value(buf)?,
+        Err(_) => return Err(anyhow!("failed to read response")),
+    };
+    Ok(evaluations)
+}
+
+/// Get all namespaces in the cluster.
+pub fn get_namespaces(client: &mut dyn NomadClient) -> Result<Vec<Namespace>> {
+    let namespaces: Vec<Namespace> = match client.get("namespaces")?.into_json() {
+        Ok(buf) => serde_json::from_value(buf)?,
+        Err(_) => return Err(anyhow!("failed to read response")),
+    };
+    Ok(namespaces)
+}
+
+/// Get the configuration and membership of the agent being queried.
+pub fn get_agent_self(client: &mut dyn NomadClient) -> Result<AgentSelf> {
+    let agent: AgentSelf = match client.get("agent/self")?.into_json() {
+        Ok(buf) => serde_json::from_value(buf)?,
+        Err(_) => return Err(anyhow!("failed to read response")),
+    };
+    Ok(agent)
+}
+
+/// Get the servers in the cluster, as gossip between them sees them.
+pub fn get_members(client: &mut dyn NomadClient) -> Result<Vec<AgentMember>> {
+    let members: AgentMembers = match client.get("agent/members")?.into_json() {
+        Ok(buf) => serde_json::from_value(buf)?,
+        Err(_) => return Err(anyhow!("failed to read response")),
+    };
+    Ok(members.Members)
 }
 
-/// Get a job by its ID.
-///
-/// # Arguments
-///
-/// * `id` - the ID of the job to retrieve.
-pub fn get_job(client: &mut dyn NomadClient, id: &str) -> Result<Job> {
-    let job: Job = match client.get(&format!("job/{}", id)).unwrap().into_json() {
+/// Get the ACL token the client makes its requests with.
+pub fn get_token_self(client: &mut dyn NomadClient) -> Result<AclToken> {
+    let token: AclToken = match client.get("acl/token/self")?.into_json() {
         Ok(buf) => serde_json::from_value(buf)?,
         Err(_) => return Err(anyhow!("failed to read response")),
     };
-    Ok(job)
+    Ok(token)
+}
+
+/// Get the RPC address of the cluster's current leader.
+pub fn get_leader(client: &mut dyn NomadClient) -> Result<String> {
+    let leader: String = match client.get("status/leader")?.into_json() {
+        Ok(buf) => serde_json::from_value(buf)?,
+        Err(_) => return Err(anyhow!("failed to read response")),
+    };
+    Ok(leader)
 }
 
 #[cfg(test)]
-mod test {
+pub mod test {
     use super::*;
 
-    const FULL_JOB: &str = r#"{"Stop":false,"Region":"global","Namespace":"default","ID":"example","ParentID":"","Name":"example","Type":"service","Priority":50,"AllAtOnce":false,"Datacenters":["dc1"],"Constraints":null,"Affinities":null,"Spreads":null,"TaskGroups":[{"Name":"cache","Count":1,"Update":{"Stagger":30000000000,"MaxParallel":1,"HealthCheck":"checks","MinHealthyTime":10000000000,"HealthyDeadline":180000000000,"ProgressDeadline":600000000000,"AutoRevert":false,"AutoPromote":false,"Canary":0},"Migrate":{"MaxParallel":1,"HealthCheck":"checks","MinHealthyTime":10000000000,"HealthyDeadline":300000000000},"Constraints":null,"Scaling":null,"RestartPolicy":{"Attempts":2,"Interval":1800000000000,"Delay":15000000000,"Mode":"fail"},"Tasks":[{"Name":"redis","Driver":"docker","User":"","Config":{"image":"redis:3.2","port_map":[{"db":6379.0}]},"Env":null,"Services":[{"Name":"redis-cache","TaskName":"","PortLabel":"db","AddressMode":"auto","EnableTagOverride":false,"Tags":["global","cache"],"CanaryTags":null,"Checks":[{"Name":"alive","Type":"tcp","Command":"","Args":null,"Path":"","Protocol":"","PortLabel":"","Expose":false,"AddressMode":"","Interval":10000000000,"Timeout":2000000000,"InitialStatus":"","TLSSkipVerify":false,"Method":"","Header":null,"CheckRestart":null,"GRPCService":"","GRPCUseTLS":false,"TaskName":"","SuccessBeforePassing":0,"FailuresBeforeCritical":0}],"Connect":null,"Meta":null,"CanaryMeta":null}],"Vault":null,"Templates":null,"Constraints":null,"Affinities":null,"Resources":{"CPU":500,"MemoryMB":256,"DiskMB":0,"IOPS":0,"Networks":[{"Mode":"","Device":"","CIDR":"","IP":"","MBits":10,"DNS":null,"ReservedPorts":null,"DynamicPorts":[{"Label":"db","Value":0,"To":0,"HostNetwork":"default"}]}],"Devices":null},"RestartPolicy":{"Attempts":2,"Interval":1800000000000,"Delay":15000000000,"Mode":"fail"},"DispatchPayload":null,"Lifecycle":null,"Meta":null,"KillTimeout":5000000000,"LogConfig":{"MaxFiles":10,"MaxFileSizeMB":10},"Artifacts":null,"Leader":false,"ShutdownDelay":0,"VolumeMounts":null,"KillSignal":"","Kind":"","CSIPluginConfig":null}],"EphemeralDisk":{"Sticky":false,"SizeMB":300,"Migrate":false},"Meta":null,"ReschedulePolicy":{"Attempts":0,"Interval":0,"Delay":30000000000,"DelayFunction":"exponential","MaxDelay":3600000000000,"Unlimited":true},"Affinities":null,"Spreads":null,"Networks":null,"Services":null,"Volumes":null,"ShutdownDelay":null,"StopAfterClientDisconnect":null}],"Update":{"Stagger":30000000000,"MaxParallel":1,"HealthCheck":"","MinHealthyTime":0,"HealthyDeadline":0,"ProgressDeadline":0,"AutoRevert":false,"AutoPromote":false,"Canary":0},"Multiregion":null,"Periodic":null,"ParameterizedJob":null,"Dispatched":false,"Payload":null,"Meta":null,"ConsulToken":"","VaultToken":"","VaultNamespace":"","NomadTokenID":"","Status":"running","StatusDescription":"","Stable":false,"Version":0,"SubmitTime":1604360707460244478,"CreateIndex":403,"ModifyIndex":410,"JobModifyIndex":403}"#;
+    pub const FULL_JOB: &str = r#"{"Stop":false,"Region":"global","Namespace":"default","ID":"example","ParentID":"","Name":"example","Type":"service","Priority":50,"AllAtOnce":false,"Datacenters":["dc1"],"Constraints":null,"Affinities":null,"Spreads":null,"TaskGroups":[{"Name":"cache","Count":1,"Update":{"Stagger":30000000000,"MaxParallel":1,"HealthCheck":"checks","MinHealthyTime":10000000000,"HealthyDeadline":180000000000,"ProgressDeadline":600000000000,"AutoRevert":false,"AutoPromote":false,"Canary":0},"Migrate":{"MaxParallel":1,"HealthCheck":"checks","MinHealthyTime":10000000000,"HealthyDeadline":300000000000},"Constraints":null,"Scaling":null,"RestartPolicy":{"Attempts":2,"Interval":1800000000000,"Delay":15000000000,"Mode":"fail"},"Tasks":[{"Name":"redis","Driver":"docker","User":"","Config":{"image":"redis:3.2","port_map":[{"db":6379.0}]},"Env":null,"Services":[{"Name":"redis-cache","TaskName":"","PortLabel":"db","AddressMode":"auto","EnableTagOverride":false,"Tags":["global","cache"],"CanaryTags":null,"Checks":[{"Name":"alive","Type":"tcp","Command":"","Args":null,"Path":"","Protocol":"","PortLabel":"","Expose":false,"AddressMode":"","Interval":10000000000,"Timeout":2000000000,"InitialStatus":"","TLSSkipVerify":false,"Method":"","Header":null,"CheckRestart":null,"GRPCService":"","GRPCUseTLS":false,"TaskName":"","SuccessBeforePassing":0,"FailuresBeforeCritical":0}],"Connect":null,"Meta":null,"CanaryMeta":null}],"Vault":null,"Templates":null,"Constraints":null,"Affinities":null,"Resources":{"CPU":500,"MemoryMB":256,"DiskMB":0,"IOPS":0,"Networks":[{"Mode":"","Device":"","CIDR":"","IP":"","MBits":10,"DNS":null,"ReservedPorts":null,"DynamicPorts":[{"Label":"db","Value":0,"To":0,"HostNetwork":"default"}]}],"Devices":null},"RestartPolicy":{"Attempts":2,"Interval":1800000000000,"Delay":15000000000,"Mode":"fail"},"DispatchPayload":null,"Lifecycle":null,"Meta":null,"KillTimeout":5000000000,"LogConfig":{"MaxFiles":10,"MaxFileSizeMB":10},"Artifacts":null,"Leader":false,"ShutdownDelay":0,"VolumeMounts":null,"KillSignal":"","Kind":"","CSIPluginConfig":null}],"EphemeralDisk":{"Sticky":false,"SizeMB":300,"Migrate":false},"Meta":null,"ReschedulePolicy":{"Attempts":0,"Interval":0,"Delay":30000000000,"DelayFunction":"exponential","MaxDelay":3600000000000,"Unlimited":true},"Affinities":null,"Spreads":null,"Networks":null,"Services":null,"Volumes":null,"ShutdownDelay":null,"StopAfterClientDisconnect":null}],"Update":{"Stagger":30000000000,"MaxParallel":1,"HealthCheck":"","MinHealthyTime":0,"HealthyDeadline":0,"ProgressDeadline":0,"AutoRevert":false,"AutoPromote":false,"Canary":0},"Multiregion":null,"Periodic":null,"ParameterizedJob":null,"Dispatched":false,"Payload":null,"Meta":null,"ConsulToken":"","VaultToken":"","VaultNamespace":"","NomadTokenID":"","Status":"running","StatusDescription":"","Stable":false,"Version":0,"SubmitTime":1604360707460244478,"CreateIndex":403,"ModifyIndex":410,"JobModifyIndex":403}"#;
+
+    pub const JOB_LISTING: &str = r#"[{"ID":"example","ParentID":"","Name":"example","Namespace":"","Datacenters":["dc1"],"Multiregion":null,"Type":"service","Priority":50,"Periodic":false,"ParameterizedJob":false,"Stop":false,"Status":"running","StatusDescription":"","JobSummary":{"JobID":"example","Namespace":"default","Summary":{"cache":{"Queued":0,"Complete":0,"Failed":0,"Running":1,"Starting":0,"Lost":0}},"Children":{"Pending":0,"Running":0,"Dead":0},"CreateIndex":403,"ModifyIndex":413},"CreateIndex":403,"ModifyIndex":410,"JobModifyIndex":403,"SubmitTime":1604360707460244478}]"#;
+
+    /// `FULL_JOB`, with the value at each JSON pointer, such as `/TaskGroups/0/Tasks/0/Env`,
+    /// replaced by the given JSON, decoded as a `Job` or left as a `Value`
+    pub fn full_job_with<T: serde::de::DeserializeOwned>(overrides: &[(&str, &str)]) -> T {
+        let mut job: Value = serde_json::from_str(FULL_JOB).unwrap();
+        for (pointer, value) in overrides {
+            *job.pointer_mut(pointer)
+                .unwrap_or_else(|| panic!("FULL_JOB has no {}", pointer)) =
+                serde_json::from_str(value).unwrap();
+        }
+        serde_json::from_value(job).unwrap()
+    }
+
+    /// A multiregion stanza, to substitute for the null one in `FULL_JOB`
+    pub const MULTIREGION: &str = r#"{"Strategy":{"MaxParallel":1,"OnFailure":"fail_all"},"Regions":[{"Name":"west","Count":2,"Datacenters":["west-1"],"Meta":{"region_code":"W"}},{"Name":"east","Count":1,"Datacenters":["east-1","east-2"],"Meta":null}]}"#;
+
+    /// Constraints to substitute for the null ones on the job and its task group in `FULL_JOB`
+    pub const JOB_CONSTRAINT: &str =
+        r#"[{"LTarget":"${attr.kernel.name}","RTarget":"linux","Operand":"="}]"#;
+    pub const GROUP_CONSTRAINT: &str =
+        r#"[{"LTarget":"${meta.rack}","RTarget":"r1,r2","Operand":"set_contains_any"}]"#;
+
+    /// `JOB_LISTING`, as a job dispatched from `example` that has finished with the given numbers
+    /// of complete and failed allocations
+    pub fn dispatched_listing(complete: u64, failed: u64) -> String {
+        JOB_LISTING
+            .replace(r#""Status":"running""#, r#""Status":"dead""#)
+            .replace(
+                r#""ID":"example","ParentID":"""#,
+                r#""ID":"example/dispatch-1604360707-6a2b1c3d","ParentID":"example""#,
+            )
+            .replace(
+                r#""Complete":0,"Failed":0,"Running":1"#,
+                &format!(r#""Complete":{},"Failed":{},"Running":0"#, complete, failed),
+            )
+    }
+
+    /// A GPU request, to substitute for the null devices on the task in `FULL_JOB`
+    pub const DEVICES: &str = r#"[{"Name":"nvidia/gpu","Count":1,"Constraints":[{"LTarget":"${device.attr.memory}","RTarget":"2 GiB","Operand":">="}],"Affinities":null}]"#;
+
+    /// An artifact stanza, to substitute for the null one on the task in `FULL_JOB`
+    pub const ARTIFACTS: &str = r#"[{"GetterSource":"https://example.com/redis.conf.tpl","GetterOptions":{"checksum":"sha256:abd123"},"GetterMode":"any","RelativeDest":"local/"}]"#;
+
+    /// A periodic stanza, to substitute for the null one in `FULL_JOB`
+    pub const PERIODIC: &str = r#"{"Enabled":true,"Spec":"0 2 * * *","SpecType":"cron","ProhibitOverlap":true,"TimeZone":"America/New_York"}"#;
+
+    pub const STUCK_DEPLOYMENT: &str = r#"{"ID":"70638f62-5c19-193e-30d6-f9d6e689ab8e","Namespace":"default","JobID":"example","JobVersion":1,"JobModifyIndex":420,"JobSpecModifyIndex":420,"JobCreateIndex":403,"IsMultiregion":false,"TaskGroups":{"cache":{"AutoRevert":false,"AutoPromote":false,"ProgressDeadline":600000000000,"RequireProgressBy":"2020-11-03T00:15:07.460244478Z","Promoted":false,"PlacedCanaries":null,"DesiredCanaries":0,"DesiredTotal":3,"PlacedAllocs":3,"HealthyAllocs":1,"UnhealthyAllocs":2}},"Status":"running","StatusDescription":"Deployment is running","CreateIndex":421,"ModifyIndex":430}"#;
+
+    pub const HEALTHY_DEPLOYMENT: &str = r#"{"ID":"a5e1c3f0-6b0e-d6d1-2c39-1f0b7f1c4b2a","Namespace":"default","JobID":"example","JobVersion":0,"JobModifyIndex":403,"JobSpecModifyIndex":403,"JobCreateIndex":403,"IsMultiregion":false,"TaskGroups":{"cache":{"AutoRevert":false,"AutoPromote":false,"ProgressDeadline":600000000000,"RequireProgressBy":"2020-11-02T23:55:07.460244478Z","Promoted":false,"PlacedCanaries":null,"DesiredCanaries":0,"DesiredTotal":1,"PlacedAllocs":1,"HealthyAllocs":1,"UnhealthyAllocs":0}},"Status":"successful","StatusDescription":"Deployment completed successfully","CreateIndex":404,"ModifyIndex":412}"#;
+
+    pub const ALLOCATIONS: &str = r#"[{"ID":"5456bd7a-9fc0-c0dd-6131-cbee77f57577","EvalID":"3a6c2bd7-0ae5-cfd6-8e2a-0ee0b5b3a5c8","Name":"example.cache[0]","Namespace":"default","NodeID":"fb2170a8-257d-3c64-b14d-bc06cc94e34c","NodeName":"client-1","JobID":"example","JobType":"service","JobVersion":0,"TaskGroup":"cache","DesiredStatus":"run","DesiredDescription":"","ClientStatus":"running","ClientDescription":"Tasks are running","TaskStates":{"redis":{"State":"running","Failed":false,"Restarts":0}},"CreateIndex":405,"ModifyIndex":409,"CreateTime":1604360707500000000,"ModifyTime":1604360717500000000},{"ID":"a2f2a4f3-1d6c-8b4e-5c1e-0d1f7a0c9e21","EvalID":"3a6c2bd7-0ae5-cfd6-8e2a-0ee0b5b3a5c8","Name":"example.cache[1]","Namespace":"default","NodeID":"0e5b2a6c-3f4d-7e8a-9b0c-1d2e3f4a5b6c","NodeName":"client-2","JobID":"example","JobType":"service","JobVersion":0,"TaskGroup":"cache","DesiredStatus":"run","DesiredDescription":"","ClientStatus":"running","ClientDescription":"Tasks are running","TaskStates":{"redis":{"State":"running","Failed":false,"Restarts":0}},"CreateIndex":405,"ModifyIndex":410,"CreateTime":1604360707500000000,"ModifyTime":1604360717600000000}]"#;
+
+    /// A placement that failed for lack of memory, followed by the blocked evaluation retrying it
+    pub const BLOCKED_EVALUATIONS: &str = r#"[{"ID":"0f3a9c4e-8d2b-4b1e-9c55-2a7d6e1f8b30","Namespace":"default","Priority":50,"Type":"service","TriggeredBy":"queued-allocs","JobID":"example","Status":"blocked","StatusDescription":"created due to placement conflicts","PreviousEval":"3a6c2bd7-0ae5-cfd6-8e2a-0ee0b5b3a5c8","ClassEligibility":{"v1:1234":true},"FailedTGAllocs":null,"CreateIndex":421,"ModifyIndex":421},{"ID":"3a6c2bd7-0ae5-cfd6-8e2a-0ee0b5b3a5c8","Namespace":"default","Priority":50,"Type":"service","TriggeredBy":"job-register","JobID":"example","Status":"complete","StatusDescription":"","BlockedEval":"0f3a9c4e-8d2b-4b1e-9c55-2a7d6e1f8b30","FailedTGAllocs":{"cache":{"NodesEvaluated":2,"NodesFiltered":0,"NodesAvailable":{"dc1":2},"ClassFiltered":null,"ConstraintFiltered":null,"NodesExhausted":2,"ClassExhausted":null,"DimensionExhausted":{"memory":2},"QuotaExhausted":null,"Scores":null,"AllocationTime":51000,"CoalescedFailures":0}},"CreateIndex":420,"ModifyIndex":421}]"#;
+
+    pub const AGENT_SELF: &str = r#"{"config":{"Region":"global","Datacenter":"dc1","NodeName":"server-1","DataDir":"/opt/nomad/data","LogLevel":"INFO","BindAddr":"0.0.0.0","Version":{"Revision":"9b54b8a","Version":"1.0.0","VersionMetadata":"","VersionPrerelease":"beta3"},"Server":{"Enabled":true,"BootstrapExpect":3},"Client":{"Enabled":false}},"member":{"Name":"server-1.global","Addr":"10.0.0.11","Port":4648,"Tags":{"role":"nomad","region":"global","dc":"dc1","build":"1.0.0-beta3"},"Status":"alive","ProtocolMin":1,"ProtocolMax":5,"ProtocolCur":2,"DelegateMin":2,"DelegateMax":5,"DelegateCur":4},"stats":{"nomad":{"leader":"true","server":"true"}}}"#;
+
+    const MEMBERS: &str = r#"{"ServerName":"server-1","ServerRegion":"global","ServerDC":"dc1","Members":[{"Name":"server-1.global","Addr":"10.0.0.11","Port":4648,"Tags":{"role":"nomad","region":"global","dc":"dc1","build":"1.0.0-beta3","bootstrap":"1"},"Status":"alive","ProtocolMin":1,"ProtocolMax":5,"ProtocolCur":2,"DelegateMin":2,"DelegateMax":5,"DelegateCur":4},{"Name":"server-2.global","Addr":"10.0.0.12","Port":4648,"Tags":{"role":"nomad","region":"global","dc":"dc1","build":"1.0.0-beta3"},"Status":"alive","ProtocolMin":1,"ProtocolMax":5,"ProtocolCur":2,"DelegateMin":2,"DelegateMax":5,"DelegateCur":4},{"Name":"server-3.global","Addr":"10.0.0.13","Port":4648,"Tags":{"role":"nomad","region":"global","dc":"dc1","build":"1.0.0-beta3"},"Status":"failed","ProtocolMin":1,"ProtocolMax":5,"ProtocolCur":2,"DelegateMin":2,"DelegateMax":5,"DelegateCur":4}]}"#;
+
+    const NAMESPACES: &str = r#"[{"Name":"default","Description":"Default shared namespace","Quota":"","Meta":null,"CreateIndex":1,"ModifyIndex":1},{"Name":"etl","Description":"Data pipelines","Quota":"","Meta":{"team":"data"},"CreateIndex":20,"ModifyIndex":20}]"#;
+
+    /// A client token, as `/v1/acl/token/self` describes it, expiring at the given time
+    pub fn acl_token(expiration_time: &str) -> String {
+        format!(
+            r#"{{"AccessorID":"aa534e09-6a07-0a45-2295-a7f77063d429","SecretID":"8176afd3-772d-0b71-8f85-7fa5d903e9d4","Name":"deploy","Type":"client","Policies":["readonly"],"Global":false,"ExpirationTime":"{}","ExpirationTTL":86400000000000,"CreateTime":"2020-11-02T12:00:00Z","CreateIndex":52,"ModifyIndex":52}}"#,
+            expiration_time
+        )
+    }
 
-    const JOB_LISTING: &str = r#"[{"ID":"example","ParentID":"","Name":"example","Namespace":"","Datacenters":["dc1"],"Multiregion":null,"Type":"service","Priority":50,"Periodic":false,"ParameterizedJob":false,"Stop":false,"Status":"running","StatusDescription":"","JobSummary":{"JobID":"example","Namespace":"default","Summary":{"cache":{"Queued":0,"Complete":0,"Failed":0,"Running":1,"Starting":0,"Lost":0}},"Children":{"Pending":0,"Running":0,"Dead":0},"CreateIndex":403,"ModifyIndex":413},"CreateIndex":403,"ModifyIndex":410,"JobModifyIndex":403,"SubmitTime":1604360707460244478}]"#;
+    pub struct TestClient {
+        pub path: Option<String>,
+        pub response_status_code: u16,
+        pub response_body: &'static str,
+        pub response_status_text: &'static str,
+    }
 
-    struct TestClient {
-        path: Option<String>,
-        response_status_code: u16,
-        response_body: &'static str,
-        response_status_text: &'static str,
+    impl TestClient {
+        pub fn new(status_code: u16, status_text: &'static str, body: &'static str) -> Self {
+            TestClient {
+                path: None,
+                response_status_code: status_code,
+                response_status_text: status_text,
+                response_body: body,
+            }
+        }
     }
 
     impl NomadClient for TestClient {
@@ -150,6 +1963,52 @@ mod test {
         }
     }
 
+    /// A canned reply to a request made through a `RoutedClient`
+    pub enum Reply {
+        /// Respond with this status code and body
+        Body(u16, String),
+        /// Fail as though the request had timed out
+        TimedOut,
+        /// Fail as though the client's deadline had passed
+        DeadlineExceeded,
+    }
+
+    /// A client with a canned reply for each resource, which records every request made
+    pub struct RoutedClient {
+        pub replies: HashMap<String, Reply>,
+        pub requests: Vec<String>,
+    }
+
+    impl RoutedClient {
+        pub fn new(replies: Vec<(&str, Reply)>) -> Self {
+            RoutedClient {
+                replies: replies
+                    .into_iter()
+                    .map(|(resource, reply)| (resource.into(), reply))
+                    .collect(),
+                requests: Vec::new(),
+            }
+        }
+    }
+
+    impl NomadClient for RoutedClient {
+        fn get(&mut self, resource: &str) -> Result<ureq::Response> {
+            self.requests.push(resource.into());
+            match self.replies.get(resource) {
+                Some(Reply::Body(status, body)) => Ok(ureq::Response::new(*status, "", body)),
+                Some(Reply::TimedOut) => Err(Timeout {
+                    resource: resource.into(),
+                }
+                .into()),
+                Some(Reply::DeadlineExceeded) => Err(DeadlineExceeded {
+                    resource: resource.into(),
+                }
+                .into()),
+                None => Ok(ureq::Response::new(404, "Not Found", "")),
+            }
+        }
+    }
+
     #[test]
     fn test_get_job() {
         let mut client = TestClient {
@@ -158,14 +2017,350 @@ mod test {
             response_status_text: "OK",
             response_body: FULL_JOB,
         };
-        let result = get_job(&mut client, "example");
+        let result = get_job(&mut client, "example", "");
         assert_eq!(client.path, Some(String::from("job/example")));
         assert!(result.is_ok());
         let job = result.unwrap();
-        // For some reason, serde flatten doesn't work in test mode *shrug*
+        // Fields shared with the listing live on the flattened `listing`
         assert_eq!(job.listing.ID, "example");
     }
 
+    #[test]
+    fn test_check_round_trip() {
+        assert!(check_round_trip(FULL_JOB).is_ok());
+        assert!(check_round_trip(
+            &full_job_with::<Value>(&[("/Multiregion", MULTIREGION)]).to_string()
+        )
+        .is_ok());
+        let unexpected = full_job_with::<Value>(&[("/TaskGroups/0/Count", r#""one""#)]).to_string();
+        let err = check_round_trip(&unexpected).unwrap_err().to_string();
+        assert!(
+            err.starts_with(r#"invalid type: string "one", expected u64"#),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_version_metadata() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert_eq!(job.Version, 0);
+        assert!(!job.Stable);
+        assert_eq!(job.listing.SubmitTime, Some(1604360707460244478));
+        assert_eq!(job.listing.JobModifyIndex, 403);
+        // They're modeled, so they survive stripping unmodeled fields, at the top level
+        let value = job.to_modeled_value().unwrap();
+        assert_eq!(value["Version"], 0);
+        assert_eq!(value["Stable"], false);
+        assert_eq!(value["SubmitTime"], 1604360707460244478u64);
+        assert_eq!(value["JobModifyIndex"], 403);
+        assert!(value.get("listing").is_none());
+
+        let listing: Vec<JobListing> = serde_json::from_str(JOB_LISTING).unwrap();
+        assert_eq!(listing[0].JobModifyIndex, 403);
+    }
+
+    #[test]
+    fn test_ephemeral_disk() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        let groups = job.TaskGroups.as_ref().unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].EphemeralDisk.as_ref().unwrap().SizeMB, 300);
+        assert_eq!(job.ephemeral_disk_mb(), 300);
+    }
+
+    #[test]
+    fn test_images() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        let task = &job.TaskGroups.as_ref().unwrap()[0].Tasks.as_ref().unwrap()[0];
+        assert_eq!(task.Driver, "docker");
+        assert_eq!(job.images(), vec!["redis:3.2"]);
+    }
+
+    #[test]
+    fn test_service_tags() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        let task = &job.TaskGroups.as_ref().unwrap()[0].Tasks.as_ref().unwrap()[0];
+        assert_eq!(task.Services.as_ref().unwrap()[0].Name, "redis-cache");
+        assert_eq!(job.services()[0].PortLabel, "db");
+        assert_eq!(job.service_tags(), vec!["global", "cache"]);
+    }
+
+    #[test]
+    fn test_model_schema() {
+        let schema = model_schema();
+        for model in &["JobListing", "Job"] {
+            let properties = &schema[model]["properties"];
+            assert!(properties.get("ID").is_some(), "{} has no ID", model);
+            assert!(
+                properties.get("Periodic").is_some(),
+                "{} has no Periodic",
+                model
+            );
+        }
+        assert!(schema["Job"]["definitions"].get("TaskGroup").is_some());
+        assert_eq!(
+            schema["Job"]["properties"]["Periodic"]["anyOf"][0]["$ref"],
+            "#/definitions/Periodic"
+        );
+    }
+
+    #[test]
+    fn test_sets_env() {
+        let job: Job = full_job_with(&[(
+            "/TaskGroups/0/Tasks/0/Env",
+            r#"{"LOG_LEVEL":"debug","REDIS_PORT":"${NOMAD_PORT_db}"}"#,
+        )]);
+        assert!(job.sets_env("LOG_LEVEL"));
+        assert!(!job.sets_env("log_level"));
+        let plain: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert!(!plain.sets_env("LOG_LEVEL"));
+    }
+
+    #[test]
+    fn test_has_shutdown_delay() {
+        let job: Job = full_job_with(&[("/TaskGroups/0/Tasks/0/ShutdownDelay", "5000000000")]);
+        assert!(job.has_shutdown_delay());
+        let job: Job = full_job_with(&[("/TaskGroups/0/ShutdownDelay", "10000000000")]);
+        assert!(job.has_shutdown_delay());
+        // The task's zero delay doesn't count
+        let plain: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert!(!plain.has_shutdown_delay());
+    }
+
+    #[test]
+    fn test_has_spread_and_affinity() {
+        let spread: Job = full_job_with(&[(
+            "/TaskGroups/0/Spreads",
+            r#"[{"Attribute":"${node.datacenter}","Weight":50,"SpreadTarget":[{"Value":"dc1","Percent":50},{"Value":"dc2","Percent":50}]}]"#,
+        )]);
+        assert!(spread.has_spread());
+        assert!(!spread.has_affinity());
+        let targets = spread.TaskGroups.as_ref().unwrap()[0]
+            .Spreads
+            .as_ref()
+            .unwrap()[0]
+            .SpreadTarget
+            .as_ref()
+            .unwrap()
+            .len();
+        assert_eq!(targets, 2);
+        let affinity: Job = full_job_with(&[(
+            "/Affinities",
+            r#"[{"LTarget":"${node.class}","RTarget":"cache","Operand":"=","Weight":75}]"#,
+        )]);
+        assert!(affinity.has_affinity());
+        assert!(!affinity.has_spread());
+        let plain: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert!(!plain.has_spread());
+        assert!(!plain.has_affinity());
+    }
+
+    #[test]
+    fn test_uses_device() {
+        let job: Job = full_job_with(&[("/TaskGroups/0/Tasks/0/Resources/Devices", DEVICES)]);
+        assert!(job.uses_device("nvidia/gpu"));
+        assert!(!job.uses_device("gpu"));
+        let plain: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert!(!plain.uses_device("nvidia/gpu"));
+    }
+
+    #[test]
+    fn test_uses_artifacts() {
+        let job: Job = full_job_with(&[("/TaskGroups/0/Tasks/0/Artifacts", ARTIFACTS)]);
+        let task = &job.TaskGroups.as_ref().unwrap()[0].Tasks.as_ref().unwrap()[0];
+        assert_eq!(
+            task.Artifacts.as_ref().unwrap()[0].GetterSource,
+            "https://example.com/redis.conf.tpl"
+        );
+        assert!(job.uses_artifacts());
+        let plain: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert!(!plain.uses_artifacts());
+    }
+
+    #[test]
+    fn test_constraints() {
+        let job: Job = full_job_with(&[
+            ("/Constraints", JOB_CONSTRAINT),
+            ("/TaskGroups/0/Constraints", GROUP_CONSTRAINT),
+        ]);
+        let constraints = job.constraints();
+        assert_eq!(constraints.len(), 2);
+        assert_eq!(constraints[0].RTarget, "linux");
+        assert_eq!(constraints[1].Operand, "set_contains_any");
+        let unconstrained: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert!(unconstrained.constraints().is_empty());
+    }
+
+    #[test]
+    fn test_constraint_references() {
+        let job: Job = full_job_with(&[
+            ("/Constraints", JOB_CONSTRAINT),
+            ("/TaskGroups/0/Constraints", GROUP_CONSTRAINT),
+        ]);
+        let kernel = job.constraints()[0];
+        assert!(kernel.references("attr.kernel.name"));
+        assert!(kernel.references("${attr.kernel.name}"));
+        assert!(!kernel.references("attr.kernel"));
+        assert!(!kernel.references("linux2"));
+    }
+
+    #[test]
+    fn test_dispatch_status() {
+        let status = |listing: &str| {
+            let listing: Vec<JobListing> = serde_json::from_str(listing).unwrap();
+            listing[0].dispatch_status()
+        };
+        assert_eq!(
+            status(&dispatched_listing(1, 0)),
+            Some(DispatchStatus::Complete)
+        );
+        assert_eq!(
+            status(&dispatched_listing(1, 1)),
+            Some(DispatchStatus::Failed)
+        );
+        // No allocation has finished
+        assert_eq!(status(&dispatched_listing(0, 0)), None);
+        // An allocation failed, but the job is still running and may yet be rescheduled
+        let rescheduling = dispatched_listing(0, 1)
+            .replace(r#""Status":"dead""#, r#""Status":"running""#)
+            .replace(r#""Starting":0"#, r#""Starting":1"#);
+        assert_eq!(status(&rescheduling), None);
+        // A lost allocation counts as failed, just as it stops the job counting as complete
+        let lost = dispatched_listing(1, 0).replace(r#""Lost":0"#, r#""Lost":1"#);
+        assert_eq!(status(&lost), Some(DispatchStatus::Failed));
+        let is_complete = |listing: &str| {
+            let listing: Vec<JobListing> = serde_json::from_str(listing).unwrap();
+            listing[0].is_complete()
+        };
+        assert!(is_complete(&dispatched_listing(1, 0)));
+        assert!(!is_complete(&lost));
+        // Not dispatched at all
+        assert_eq!(status(JOB_LISTING), None);
+        let dispatched: Job =
+            full_job_with(&[("/Dispatched", "true"), ("/ParentID", r#""example""#)]);
+        assert!(dispatched.Dispatched);
+    }
+
+    #[test]
+    fn test_runs_in() {
+        let mut job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert!(job.listing.runs_in("dc1"));
+        assert!(!job.listing.runs_in("dc2"));
+        assert!(!job.listing.runs_anywhere());
+        job.listing.Datacenters = Some(vec![String::from("*")]);
+        assert!(job.listing.runs_in("dc2"));
+        assert!(job.listing.runs_anywhere());
+    }
+
+    #[test]
+    fn test_meta() {
+        let job: Job = full_job_with(&[("/TaskGroups/0/Meta", r#"{"team":"cache"}"#)]);
+        assert!(job.meta(MetaScope::Job).is_empty());
+        assert!(job.meta(MetaScope::Task).is_empty());
+        assert_eq!(job.meta(MetaScope::Group)[0]["team"], "cache");
+        assert_eq!(job.meta(MetaScope::Any).len(), 1);
+        assert_eq!("group".parse::<MetaScope>().unwrap(), MetaScope::Group);
+        assert!("region".parse::<MetaScope>().is_err());
+    }
+
+    #[test]
+    fn test_has_check_type() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert!(job.has_check_type("tcp"));
+        assert!(job.has_check_type("TCP"));
+        assert!(!job.has_check_type("http"));
+    }
+
+    #[test]
+    fn test_desired_count() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert_eq!(job.TaskGroups.as_ref().unwrap()[0].Count, 1);
+        assert_eq!(job.desired_count(), 1);
+    }
+
+    #[test]
+    fn test_restart_attempts() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        let policy = job.TaskGroups.as_ref().unwrap()[0]
+            .RestartPolicy
+            .as_ref()
+            .unwrap();
+        assert_eq!(policy.Mode, "fail");
+        assert_eq!(job.restart_attempts(), vec![2]);
+    }
+
+    #[test]
+    fn test_multiregion() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        assert!(job.Multiregion.is_none());
+        assert_eq!(job.region_count(), 0);
+
+        let job: Job = full_job_with(&[("/Multiregion", MULTIREGION)]);
+        let multiregion = job.Multiregion.as_ref().unwrap();
+        assert_eq!(multiregion.Strategy.as_ref().unwrap().OnFailure, "fail_all");
+        let regions = multiregion.Regions.as_ref().unwrap();
+        assert_eq!(regions[0].Name, "west");
+        assert_eq!(regions[1].Datacenters.as_ref().unwrap().len(), 2);
+        assert_eq!(job.region_count(), 2);
+    }
+
+    #[test]
+    fn test_ports() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        let ports = job.ports();
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0].Label, "db");
+        assert_eq!(ports[0].HostNetwork.as_deref(), Some("default"));
+        let task = &job.TaskGroups.as_ref().unwrap()[0].Tasks.as_ref().unwrap()[0];
+        let network = &task.Resources.as_ref().unwrap().Networks.as_ref().unwrap()[0];
+        assert_eq!(network.MBits, 10);
+        assert!(network.ReservedPorts.is_none());
+    }
+
+    #[test]
+    fn test_to_modeled_value() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        let value = job.to_modeled_value().unwrap();
+        assert_eq!(value["ID"], "example");
+        assert_eq!(value["Status"], "running");
+        assert!(value.get("Periodic").is_some());
+        assert_eq!(value["Datacenters"], serde_json::json!(["dc1"]));
+        assert_eq!(value["Priority"], 50);
+        assert!(value.get("AllAtOnce").is_none());
+        assert!(value.get("Region").is_none());
+        let group = &value["TaskGroups"][0];
+        assert_eq!(group["Name"], "cache");
+        assert_eq!(group["EphemeralDisk"]["SizeMB"], 300);
+        assert!(group.get("Update").is_none());
+        let task = &group["Tasks"][0];
+        assert_eq!(task["Name"], "redis");
+        assert_eq!(task["Driver"], "docker");
+        assert!(task.get("User").is_none());
+        assert!(task["Resources"].get("CPU").is_none());
+        assert_eq!(task["Services"][0]["Tags"][1], "cache");
+        let check = &task["Services"][0]["Checks"][0];
+        assert_eq!(check["Type"], "tcp");
+        assert!(check.get("Interval").is_none());
+        let network = &task["Resources"]["Networks"][0];
+        assert_eq!(network["DynamicPorts"][0]["Label"], "db");
+        assert!(network.get("DNS").is_none());
+    }
+
+    #[test]
+    fn test_to_canonical_value() {
+        let job: Job = serde_json::from_str(FULL_JOB).unwrap();
+        let value = job.to_canonical_value().unwrap();
+        let keys: Vec<&String> = value.as_object().unwrap().keys().collect();
+        assert_eq!(keys[..5], ["ID", "ParentID", "Name", "Type", "Priority"]);
+        // Unmodeled fields follow, alphabetically
+        let extra = &keys[keys.iter().position(|key| *key == "AllAtOnce").unwrap()..];
+        assert!(extra.windows(2).all(|pair| pair[0] < pair[1]));
+        let group_keys: Vec<&String> = value["TaskGroups"][0].as_object().unwrap().keys().collect();
+        assert_eq!(group_keys[..2], ["Name", "Count"]);
+        assert_eq!(value, serde_json::to_value(&job).unwrap());
+    }
+
     #[test]
     fn test_get_job_missing() {
         let mut client = TestClient {
@@ -174,7 +2369,7 @@ mod test {
             response_status_text: "Bad Request",
             response_body: "",
         };
-        let result = get_job(&mut client, "example");
+        let result = get_job(&mut client, "example", "");
         assert_eq!(client.path, Some(String::from("job/example")));
         assert!(result.is_err());
         match result {
@@ -191,13 +2386,15 @@ mod test {
             response_status_text: "OK",
             response_body: JOB_LISTING,
         };
-        let result = get_jobs(&mut client, "");
+        let result = get_jobs(&mut client, "", None);
         assert_eq!(client.path, Some(String::from("jobs?prefix=")));
         assert!(result.is_ok());
         let job = result.unwrap();
-        // For some reason, serde flatten doesn't work in test mode *shrug*
         assert_eq!(job.len(), 1);
         assert_eq!(job[0].ID, "example");
+        assert_eq!(job[0].SubmitTime, Some(1604360707460244478));
+        let summary = job[0].JobSummary.as_ref().unwrap();
+        assert_eq!(summary.Summary["cache"].Running, 1);
     }
 
     #[test]
@@ -208,10 +2405,188 @@ mod test {
             response_status_text: "Bad Request",
             response_body: "",
         };
-        let result = get_jobs(&mut client, "");
+        let result = get_jobs(&mut client, "", None);
         assert_eq!(client.path, Some(String::from("jobs?prefix=")));
         assert!(result.is_err());
-        // For some reason, serde flatten doesn't work in test mode *shrug*
+        match result {
+            Err(err) => assert_eq!(err.to_string(), "failed to read response"),
+            Ok(_) => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_get_job_decode_error() {
+        let mismatched = full_job_with::<Value>(&[("/TaskGroups/0/Count", r#""one""#)]).to_string();
+        let mut client = RoutedClient::new(vec![("job/example", Reply::Body(200, mismatched))]);
+        let err = get_job(&mut client, "example", "").unwrap_err();
+        let err = err.downcast::<DecodeError>().unwrap();
+        assert_eq!(err.path, "TaskGroups[0].Count");
+        assert_eq!(
+            err.to_string(),
+            "invalid type: string \"one\", expected u64 at TaskGroups[0].Count"
+        );
+        assert!(err.snippet.starts_with(r#"{"Name":"cache","Count":"one""#));
+        assert!(err.snippet.ends_with("..."));
+    }
+
+    #[test]
+    fn test_get_job_raw() {
+        let mut client = TestClient::new(200, "OK", FULL_JOB);
+        let raw = get_job_raw(&mut client, "example", "").unwrap();
+        assert_eq!(client.path, Some(String::from("job/example")));
+        // ureq's synthetic test responses end their body with a newline
+        assert_eq!(raw, format!("{}\n", FULL_JOB));
+    }
+
+    #[test]
+    fn test_get_deployment() {
+        let mut client = TestClient::new(200, "OK", STUCK_DEPLOYMENT);
+        let deployment = get_deployment(&mut client, "example", "").unwrap().unwrap();
+        assert_eq!(client.path, Some(String::from("job/example/deployment")));
+        assert_eq!(deployment.Status, "running");
+        let cache = &deployment.TaskGroups["cache"];
+        assert_eq!(cache.DesiredCanaries, 0);
+        assert_eq!(cache.PlacedAllocs, 3);
+        assert_eq!(cache.HealthyAllocs, 1);
+        assert_eq!(cache.UnhealthyAllocs, 2);
+    }
+
+    #[test]
+    fn test_get_deployment_never_deployed() {
+        let mut client = TestClient::new(200, "OK", "null");
+        assert!(get_deployment(&mut client, "example", "")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_deployment_is_stuck() {
+        let now = SystemTime::now();
+        let stuck: Deployment = serde_json::from_str(STUCK_DEPLOYMENT).unwrap();
+        assert!(stuck.is_stuck(now));
+        // Unhealthy allocations are expected until the progress deadline passes
+        let before_deadline = humantime::parse_rfc3339("2020-11-03T00:00:00Z").unwrap();
+        assert!(!stuck.is_stuck(before_deadline));
+        let healthy: Deployment = serde_json::from_str(HEALTHY_DEPLOYMENT).unwrap();
+        assert!(!healthy.is_stuck(now));
+    }
+
+    #[test]
+    fn test_deployment_rollout_status() {
+        let stuck: Deployment = serde_json::from_str(STUCK_DEPLOYMENT).unwrap();
+        assert_eq!(stuck.rollout_status(), "unhealthy");
+        let healthy: Deployment = serde_json::from_str(HEALTHY_DEPLOYMENT).unwrap();
+        assert_eq!(healthy.rollout_status(), "complete");
+        let mut rolling = stuck;
+        let cache = rolling.TaskGroups.get_mut("cache").unwrap();
+        cache.UnhealthyAllocs = 0;
+        assert_eq!(rolling.rollout_status(), "running");
+        rolling.TaskGroups.get_mut("cache").unwrap().HealthyAllocs = 3;
+        assert_eq!(rolling.rollout_status(), "healthy");
+    }
+
+    #[test]
+    fn test_get_allocations() {
+        let mut client = TestClient::new(200, "OK", ALLOCATIONS);
+        let allocations = get_allocations(&mut client, "example", "").unwrap();
+        assert_eq!(client.path, Some(String::from("job/example/allocations")));
+        assert_eq!(allocations.len(), 2);
+        assert_eq!(allocations[0].TaskGroup, "cache");
+        assert_eq!(allocations[0].NodeName, "client-1");
+        assert_eq!(allocations[1].ClientStatus, "running");
+    }
+
+    #[test]
+    fn test_allocation_is_on_node() {
+        let allocations: Vec<Allocation> = serde_json::from_str(ALLOCATIONS).unwrap();
+        let allocation = &allocations[0];
+        assert!(allocation.is_on_node("fb2170a8-257d-3c64-b14d-bc06cc94e34c"));
+        assert!(allocation.is_on_node("fb2170a8"));
+        assert!(allocation.is_on_node("client-1"));
+        assert!(!allocation.is_on_node("client-2"));
+        assert!(!allocation.is_on_node(""));
+    }
+
+    #[test]
+    fn test_get_agent_self() {
+        let mut client = TestClient::new(200, "OK", AGENT_SELF);
+        let agent = get_agent_self(&mut client).unwrap();
+        assert_eq!(client.path, Some(String::from("agent/self")));
+        assert_eq!(agent.config.Region, "global");
+        assert_eq!(agent.config.Datacenter, "dc1");
+        assert_eq!(agent.config.NodeName, "server-1");
+        assert_eq!(agent.config.Version.to_string(), "1.0.0-beta3");
+        assert_eq!(agent.member.Name, "server-1.global");
+        assert_eq!(agent.member.Addr, "10.0.0.11");
+        assert_eq!(agent.member.Port, 4648);
+        assert_eq!(agent.member.Status, "alive");
+    }
+
+    #[test]
+    fn test_token_expires_within() {
+        let now = humantime::parse_rfc3339("2020-11-03T11:00:00Z").unwrap();
+        let token: AclToken =
+            serde_json::from_str(&acl_token("2020-11-03T12:00:00.123456789Z")).unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(
+            token.expires_within(day, now),
+            Some(Duration::new(3600, 123456789))
+        );
+        assert_eq!(token.expires_within(Duration::from_secs(60), now), None);
+        let later = humantime::parse_rfc3339("2020-11-04T00:00:00Z").unwrap();
+        assert_eq!(token.expires_within(day, later), Some(Duration::ZERO));
+        let token: AclToken =
+            serde_json::from_str(r#"{"AccessorID":"a","Name":"","Type":"management"}"#).unwrap();
+        assert_eq!(token.expires_within(day, now), None);
+    }
+
+    #[test]
+    fn test_get_members() {
+        let mut client = TestClient::new(200, "OK", MEMBERS);
+        let members = get_members(&mut client).unwrap();
+        assert_eq!(client.path, Some(String::from("agent/members")));
+        let statuses: Vec<(&str, &str)> = members
+            .iter()
+            .map(|member| (member.Name.as_str(), member.Status.as_str()))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("server-1.global", "alive"),
+                ("server-2.global", "alive"),
+                ("server-3.global", "failed"),
+            ]
+        );
+        assert_eq!(members[0].Addr, "10.0.0.11");
+        assert_eq!(members[0].Port, 4648);
+        assert_eq!(members[0].Tags["bootstrap"], "1");
+        assert!(get_members(&mut TestClient::new(500, "Internal Server Error", "")).is_err());
+    }
+
+    #[test]
+    fn test_get_leader() {
+        let mut client = TestClient::new(200, "OK", r#""10.0.0.11:4647""#);
+        assert_eq!(get_leader(&mut client).unwrap(), "10.0.0.11:4647");
+        assert_eq!(client.path, Some(String::from("status/leader")));
+    }
+
+    #[test]
+    fn test_get_namespaces() {
+        let mut client = TestClient::new(200, "OK", NAMESPACES);
+        let result = get_namespaces(&mut client);
+        assert_eq!(client.path, Some(String::from("namespaces")));
+        let namespaces = result.unwrap();
+        assert_eq!(namespaces.len(), 2);
+        assert_eq!(namespaces[0].Name, "default");
+        assert_eq!(namespaces[0].Description, "Default shared namespace");
+        assert_eq!(namespaces[1].Name, "etl");
+        assert_eq!(namespaces[1].Description, "Data pipelines");
+    }
+
+    #[test]
+    fn test_get_namespaces_malformed() {
+        let mut client = TestClient::new(400, "Bad Request", "");
+        let result = get_namespaces(&mut client);
         match result {
             Err(err) => assert_eq!(err.to_string(), "failed to read response"),
             Ok(_) => unreachable!(),
@@ -226,7 +2601,7 @@ mod test {
             response_status_text: "OK",
             response_body: JOB_LISTING,
         };
-        let result = get_jobs(&mut client, "example");
+        let result = get_jobs(&mut client, "example", None);
         assert_eq!(client.path, Some(String::from("jobs?prefix=example")));
         assert!(result.is_ok());
     }
@@ -239,7 +2614,7 @@ mod test {
             response_status_text: "OK",
             response_body: JOB_LISTING,
         };
-        let result = get_jobs(&mut client, "dispatch-example/periodic-102002");
+        let result = get_jobs(&mut client, "dispatch-example/periodic-102002", None);
         assert_eq!(
             client.path,
             Some(String::from(
@@ -248,4 +2623,269 @@ mod test {
         );
         assert!(result.is_ok());
     }
+
+    /// Serve redirects back to the same resource, returning how many requests were answered.
+    fn serve_redirect_loop(requests: usize) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        serve_responses(vec![
+            b"HTTP/1.1 302 Found\r\nLocation: /v1/jobs\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            requests
+        ])
+    }
+
+    /// Answer a request with each of the given raw responses in turn, returning the requests
+    /// answered.
+    fn serve_responses(
+        responses: Vec<&'static [u8]>,
+    ) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut answered = Vec::new();
+            // Take the response first, so the server stops without waiting for another request
+            for (response, stream) in responses.into_iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let read = stream.read(&mut buf).unwrap_or_default();
+                let _ = stream.write_all(response);
+                answered.push(String::from_utf8_lossy(&buf[..read]).into_owned());
+            }
+            answered
+        });
+        (address, server)
+    }
+
+    #[test]
+    fn test_header_lines() {
+        let resp: ureq::Response = "HTTP/1.1 200 OK\r\nX-Nomad-Index: 410\r\nContent-Type: application/json\r\nX-Nomad-KnownLeader: true\r\n\r\n[]"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            header_lines(&resp),
+            [
+                "content-type: application/json",
+                "x-nomad-index: 410",
+                "x-nomad-knownleader: true",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_connect_and_read_timeouts() {
+        let client = get_client(None)
+            .with_connect_timeout(Duration::from_secs(2))
+            .with_read_timeout(Duration::from_secs(30));
+        assert_eq!(client.connect_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(client.read_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(client.timeout, None);
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        // The server accepts the connection, but never responds
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let mut client = get_client(Some(address)).with_read_timeout(Duration::from_millis(100));
+        let err = client.get("jobs").unwrap_err();
+        assert!(err.is::<Timeout>(), "{}", err);
+        drop(listener);
+    }
+
+    #[test]
+    fn test_deadline() {
+        // The server accepts the connection, but never responds, and no timeout is set
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let started = Instant::now();
+        let mut client =
+            get_client(Some(address)).with_deadline(started + Duration::from_millis(200));
+        let err = client.get("jobs").unwrap_err();
+        assert!(err.is::<DeadlineExceeded>(), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // Once the deadline has passed, nothing more is requested
+        drop(listener);
+        let err = client.get("jobs").unwrap_err();
+        assert!(err.is::<DeadlineExceeded>(), "{}", err);
+    }
+
+    #[test]
+    fn test_stale_url() {
+        let client = get_client(Some(String::from("http://nomad:4646")));
+        assert_eq!(
+            client.url("job/example").unwrap(),
+            "http://nomad:4646/v1/job/example"
+        );
+        let client = client.with_stale(true);
+        assert_eq!(
+            client.url("job/example").unwrap(),
+            "http://nomad:4646/v1/job/example?stale"
+        );
+        assert_eq!(
+            client.url("jobs?prefix=example").unwrap(),
+            "http://nomad:4646/v1/jobs?prefix=example&stale"
+        );
+    }
+
+    #[test]
+    fn test_ipv6_url() {
+        let client = get_client(Some(String::from("http://[::1]:4646")));
+        assert_eq!(
+            client.url("job/example%2Fbatch?namespace=etl").unwrap(),
+            "http://[::1]:4646/v1/job/example%2Fbatch?namespace=etl"
+        );
+        let client = get_client(Some(String::from("https://[2001:db8::10]:4646/")));
+        assert_eq!(
+            client.url("jobs?prefix=").unwrap(),
+            "https://[2001:db8::10]:4646/v1/jobs?prefix="
+        );
+        // A path prefix, as when Nomad is behind a reverse proxy, is kept
+        let client = get_client(Some(String::from("http://proxy/nomad")));
+        assert_eq!(client.url("jobs").unwrap(), "http://proxy/nomad/v1/jobs");
+        let client = get_client(Some(String::from("[::1]:4646")));
+        assert!(client.url("jobs").is_err());
+    }
+
+    #[test]
+    fn test_parse_address() {
+        for address in [
+            "http://127.0.0.1:4646",
+            "https://nomad.example.com",
+            "http://[::1]:4646",
+            "https://proxy.example.com/nomad/",
+        ] {
+            assert!(parse_address(address).is_ok(), "{}", address);
+        }
+        let err = |address| parse_address(address).unwrap_err().to_string();
+        assert_eq!(
+            err("127.0.0.1:4646"),
+            "'127.0.0.1:4646' is not a URL (relative URL without a base), expected one like \
+             http://127.0.0.1:4646"
+        );
+        assert_eq!(
+            err("nomad:4646"),
+            "'nomad:4646' must start with http:// or https://"
+        );
+        assert_eq!(
+            err("http://nomad:4646?region=eu"),
+            "'http://nomad:4646?region=eu' can't have a query string or fragment"
+        );
+        assert!(err("http://nomad:464600").contains("invalid port number"));
+        assert!(err("http://").contains("empty host"));
+    }
+
+    #[test]
+    fn test_ipv6_request() {
+        // Not every environment has IPv6 loopback
+        let listener = match std::net::TcpListener::bind("[::1]:0") {
+            Ok(listener) => listener,
+            Err(_) => return,
+        };
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let read = stream.read(&mut buf).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]")
+                .unwrap();
+            String::from_utf8_lossy(&buf[..read]).into_owned()
+        });
+        let mut client = get_client(Some(address));
+        let jobs = get_jobs(&mut client, "", None).unwrap();
+        assert!(jobs.is_empty());
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /v1/jobs?prefix= "), "{}", request);
+    }
+
+    #[test]
+    fn test_retries_without_leader() {
+        let (address, server) = serve_responses(vec![
+            b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 17\r\nConnection: close\r\n\r\nNo cluster leader",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]",
+        ]);
+        let mut client = get_client(Some(address));
+        let resp = client.get("jobs").unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.into_string().unwrap(), "[]");
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_other_errors_not_retried() {
+        let (address, server) = serve_responses(vec![
+            b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 14\r\nConnection: close\r\n\r\nout of memory!",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]",
+        ]);
+        let mut client = get_client(Some(address.clone()));
+        let resp = client.get("jobs").unwrap();
+        assert_eq!(resp.status(), 500);
+        assert_eq!(resp.into_string().unwrap().trim_end(), "out of memory!");
+        // Let the server finish with a request of its own
+        let _ = get_client(Some(address)).get("jobs");
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_sends_token() {
+        let (address, server) = serve_responses(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]",
+        ]);
+        let mut client = get_client(Some(address))
+            .with_token(String::from("8176afd3-772d-0b71-8f85-7fa5d903e9d4"));
+        assert_eq!(client.get("jobs").unwrap().status(), 200);
+        let requests = server.join().unwrap();
+        assert!(
+            requests[0]
+                .to_lowercase()
+                .contains("x-nomad-token: 8176afd3-772d-0b71-8f85-7fa5d903e9d4\r\n"),
+            "{}",
+            requests[0]
+        );
+    }
+
+    #[test]
+    fn test_permission_denied() {
+        let (address, server) = serve_responses(vec![
+            b"HTTP/1.1 403 Forbidden\r\nContent-Length: 17\r\nConnection: close\r\n\r\nPermission denied",
+            b"HTTP/1.1 403 Forbidden\r\nContent-Length: 19\r\nConnection: close\r\n\r\nACL token not found",
+        ]);
+        let mut client = get_client(Some(address.clone()));
+        client.token = None;
+        let err = client.get("jobs").unwrap_err();
+        assert!(err.is::<PermissionDenied>(), "{}", err);
+        assert_eq!(
+            err.to_string(),
+            "permission denied for jobs: the cluster has ACLs enabled, so set NOMAD_TOKEN or pass \
+             --token"
+        );
+        let err = get_client(Some(address))
+            .with_token(String::from("expired"))
+            .get("jobs")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied for jobs (ACL token not found): check that the ACL token is valid \
+             and its policies allow reading it"
+        );
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_max_redirects() {
+        // The original request plus two redirects
+        let (address, server) = serve_redirect_loop(3);
+        let mut client = get_client(Some(address)).with_max_redirects(2);
+        let err = client.get("jobs").unwrap_err();
+        assert!(err.to_string().contains("Too Many Redirects"), "{}", err);
+        assert_eq!(server.join().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_max_redirects_disabled() {
+        let (address, server) = serve_redirect_loop(1);
+        let mut client = get_client(Some(address)).with_max_redirects(0);
+        assert_eq!(client.get("jobs").unwrap().status(), 302);
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
 }
@@ -0,0 +1,1030 @@
+use anyhow::{anyhow, Result};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Characters escaped in file names: everything but ASCII letters, digits, `-`, `_` and `.`
+const FILE_NAME_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
+/// The document that HTML output is rendered into
+const HTML_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>nquery report</title>
+<style>
+body { font-family: sans-serif; margin: 2em; color: #222; }
+header p { color: #555; margin: 0.2em 0; }
+table { border-collapse: collapse; margin-top: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { background: #f0f0f0; }
+tr:nth-child(even) td { background: #fafafa; }
+td { font-family: monospace; white-space: pre-wrap; }
+</style>
+</head>
+<body>
+<header>
+<h1>nquery report</h1>
+<p>Query: <code>{query}</code></p>
+<p>Generated at {generated_at}, {count} jobs</p>
+</header>
+{table}
+</body>
+</html>
+"#;
+
+/// How the matched jobs are written to stdout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    /// A single JSON array of jobs
+    Json,
+    /// One JSON object per line for each job, annotated with fetch metadata
+    JsonStream,
+    /// A line describing the fields present, followed by one JSON object per line for each job
+    NdjsonWithHeader,
+    /// A standalone HTML document with a table of jobs
+    Html,
+    /// Shell variable assignments, one per field, for `eval` or `source`
+    Env,
+    /// A line of text for each job, filled in from a template
+    Template,
+    /// An aligned text table with a row for each job and a column for each field
+    Table,
+    /// A YAML document for each job, separated by `---`
+    YamlStream,
+    /// Delimiter-separated values with a header row, e.g. CSV or TSV
+    Csv,
+    /// A table counting the listed jobs by one or two fields. Reports other than the listing are
+    /// written as a table
+    CountTable,
+}
+
+impl OutputMode {
+    /// Get the format used to write output in this mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `pretty` - Whether formats that support it should pretty print their output
+    /// * `template` - The template each job is rendered with in template mode
+    /// * `delimiter` - The character separating values in CSV mode
+    /// * `script` - Whether template mode writes a shell script
+    /// * `query` - The arguments the query was run with, as shown in HTML reports. Secrets must
+    ///   already be redacted, since reports are meant to be shared
+    pub fn format(
+        self,
+        pretty: bool,
+        template: Option<&str>,
+        delimiter: char,
+        script: bool,
+        query: &str,
+    ) -> Box<dyn OutputFormat> {
+        match self {
+            OutputMode::Json => Box::new(Json { pretty }),
+            OutputMode::JsonStream => Box::new(JsonLines),
+            OutputMode::NdjsonWithHeader => Box::new(NdjsonWithHeader),
+            OutputMode::Html => Box::new(Html {
+                query: query.to_string(),
+                generated_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            }),
+            OutputMode::Env => Box::new(Env),
+            OutputMode::Template => Box::new(Template {
+                template: template.unwrap_or_default().to_string(),
+                script,
+            }),
+            OutputMode::Table | OutputMode::CountTable => Box::new(Table),
+            OutputMode::YamlStream => Box::new(YamlStream),
+            OutputMode::Csv => Box::new(Csv { delimiter }),
+        }
+    }
+}
+
+impl FromStr for OutputMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(OutputMode::Json),
+            "json-stream" => Ok(OutputMode::JsonStream),
+            "ndjson-with-header" => Ok(OutputMode::NdjsonWithHeader),
+            "html" => Ok(OutputMode::Html),
+            "env" => Ok(OutputMode::Env),
+            "template" => Ok(OutputMode::Template),
+            "table" => Ok(OutputMode::Table),
+            "yaml-stream" => Ok(OutputMode::YamlStream),
+            "csv" => Ok(OutputMode::Csv),
+            "count-table" => Ok(OutputMode::CountTable),
+            _ => Err(anyhow!("unknown output format: {}", s)),
+        }
+    }
+}
+
+impl OutputMode {
+    /// The extension given to files written in this mode.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputMode::Json => "json",
+            OutputMode::JsonStream | OutputMode::NdjsonWithHeader => "jsonl",
+            OutputMode::Html => "html",
+            OutputMode::Env => "env",
+            OutputMode::Template | OutputMode::Table | OutputMode::CountTable => "txt",
+            OutputMode::YamlStream => "yaml",
+            OutputMode::Csv => "csv",
+        }
+    }
+}
+
+/// Write each job to its own file in a directory, creating the directory if needed.
+///
+/// Returns the paths written, in order.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to write to
+/// * `jobs` - Each job's ID, along with the value to write for it
+/// * `format` - The format to write each job in
+/// * `extension` - The extension given to each file
+pub fn write_files<'a>(
+    dir: &Path,
+    jobs: impl IntoIterator<Item = (&'a str, &'a Value)>,
+    format: &dyn OutputFormat,
+    extension: &str,
+) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    for (id, value) in jobs {
+        let path = dir.join(format!("{}.{}", file_name(id), extension));
+        let mut file = io::BufWriter::new(fs::File::create(&path)?);
+        format.write(value, &mut file)?;
+        file.flush()?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Turn a job ID into a safe file name. Characters that could be a problem on some filesystems are
+/// percent-encoded, so distinct IDs always get distinct names, and a leading `.` is encoded so the
+/// file isn't hidden and can't be `.` or `..`.
+fn file_name(id: &str) -> String {
+    let name = utf8_percent_encode(id, FILE_NAME_ESCAPES).to_string();
+    match name.strip_prefix('.') {
+        Some(rest) => format!("%2E{}", rest),
+        None => name,
+    }
+}
+
+pub trait OutputFormat {
+    /// Write a value in this format.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to write, usually an array with an entry for each job
+    /// * `out` - Where to write it
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// A single JSON document
+pub struct Json {
+    pub pretty: bool,
+}
+
+impl OutputFormat for Json {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        if self.pretty {
+            serde_json::to_writer_pretty(&mut *out, value)?;
+        } else {
+            serde_json::to_writer(&mut *out, value)?;
+        }
+        writeln!(out)
+    }
+}
+
+/// What continuation lines of a wrapped string start with, so they aren't mistaken for data
+const WRAP_MARKER: &str = "\u{21aa} ";
+
+/// Pretty JSON for reading in a terminal, with string values too long for it wrapped onto
+/// continuation lines. The wrapped output is no longer valid JSON.
+pub struct WrappedJson {
+    /// The most characters a line may take up
+    pub width: usize,
+}
+
+impl OutputFormat for WrappedJson {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        for line in serde_json::to_string_pretty(value)?.lines() {
+            writeln!(out, "{}", wrap_line(line, self.width))?;
+        }
+        Ok(())
+    }
+}
+
+/// Wrap a line of pretty JSON to a width if it ends with a string value. Continuation lines keep
+/// the line's indentation and start with `WRAP_MARKER`.
+fn wrap_line(line: &str, width: usize) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let is_string = line.trim_end_matches(',').ends_with('"');
+    if !is_string || chars.len() <= width {
+        return line.to_string();
+    }
+    let indent = line.len() - line.trim_start().len();
+    let prefix = format!("{}{}", " ".repeat(indent), WRAP_MARKER);
+    let rest_width = width.saturating_sub(prefix.chars().count()).max(1);
+    let mut wrapped: String = chars[..width].iter().collect();
+    for chunk in chars[width..].chunks(rest_width) {
+        wrapped.push('\n');
+        wrapped.push_str(&prefix);
+        wrapped.extend(chunk);
+    }
+    wrapped
+}
+
+/// One JSON document per line, for each entry of an array
+pub struct JsonLines;
+
+impl OutputFormat for JsonLines {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        for row in rows(value) {
+            writeln!(out, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+/// A YAML document for each entry of an array, each starting with a `---` line
+pub struct YamlStream;
+
+impl OutputFormat for YamlStream {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        for row in rows(value) {
+            let document = serde_yaml::to_string(row)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let document = document.strip_prefix("---\n").unwrap_or(&document);
+            writeln!(out, "---\n{}", document.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// JSON lines, preceded by a line describing the fields present across all of them
+pub struct NdjsonWithHeader;
+
+impl OutputFormat for NdjsonWithHeader {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        let rows = rows(value);
+        writeln!(out, "{}", ndjson_header(rows))?;
+        JsonLines.write(value, out)
+    }
+}
+
+/// Shell variable assignments, one `KEY=value` line per field. With several jobs, each variable is
+/// prefixed with its job's ID. Only scalar fields can be written.
+pub struct Env;
+
+impl OutputFormat for Env {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        let rows = rows(value);
+        for row in rows {
+            let fields = row.as_object().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "env output needs objects")
+            })?;
+            let id = row.get("ID").and_then(Value::as_str).unwrap_or_default();
+            for (key, field) in fields {
+                let name = if rows.len() > 1 {
+                    env_name(&format!("{}_{}", id, key))
+                } else {
+                    env_name(key)
+                };
+                let value = match field {
+                    Value::Null => String::new(),
+                    Value::String(string) => string.clone(),
+                    Value::Bool(_) | Value::Number(_) => field.to_string(),
+                    Value::Array(_) | Value::Object(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "{} isn't a scalar, so can't be written as an environment variable",
+                                key
+                            ),
+                        ))
+                    }
+                };
+                writeln!(out, "{}={}", name, shell_quote(&value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Turn a field name into a valid shell variable name, replacing anything but ASCII letters,
+/// digits and `_` with `_`.
+fn env_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// Single-quote a value for the shell, so it's taken literally.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Quote a value for the shell only if it would otherwise be split or expanded, so scripts stay
+/// readable.
+fn shell_word(value: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c);
+    if !value.is_empty() && value.chars().all(is_plain) {
+        value.to_string()
+    } else {
+        shell_quote(value)
+    }
+}
+
+/// A standalone HTML document, with a table row for each job and a column for each field
+pub struct Html {
+    /// The arguments nquery was run with
+    pub query: String,
+    /// When the report was generated, as an RFC 3339 timestamp
+    pub generated_at: String,
+}
+
+impl OutputFormat for Html {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        let rows = rows(value);
+        let document = render_template(
+            HTML_TEMPLATE,
+            &[
+                ("query", &escape_html(&self.query)),
+                ("generated_at", &escape_html(&self.generated_at)),
+                ("count", &rows.len().to_string()),
+                ("table", &html_table(rows)),
+            ],
+        );
+        out.write_all(document.as_bytes())
+    }
+}
+
+/// A line of text for each job, with `{Field}` placeholders filled in from the job's fields.
+/// Nomad gives durations and timestamps in nanoseconds, so `{duration Field}` formats a duration,
+/// e.g. `5s`, and `{timestamp Field}` formats a time since the epoch as an RFC 3339 timestamp.
+/// Placeholders for fields the job doesn't have are left as they are.
+pub struct Template {
+    pub template: String,
+    /// Write a shell script: start with a `#!/bin/sh` line, and quote every value filled in so
+    /// it's taken literally by the shell
+    pub script: bool,
+}
+
+impl OutputFormat for Template {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        if self.script {
+            writeln!(out, "#!/bin/sh")?;
+        }
+        for row in rows(value) {
+            let line = fill_placeholders(&self.template, |placeholder| {
+                let (helper, field) = match placeholder.split_once(' ') {
+                    Some((helper, field)) => (Some(helper), field.trim()),
+                    None => (None, placeholder),
+                };
+                let field = row.get(field)?;
+                match helper {
+                    None => Some(match field {
+                        Value::String(string) => string.clone(),
+                        field => field.to_string(),
+                    }),
+                    Some("duration") => field
+                        .as_u64()
+                        .map(|nanos| humantime::format_duration(Duration::from_nanos(nanos)))
+                        .map(|duration| duration.to_string()),
+                    Some("timestamp") => field
+                        .as_u64()
+                        .map(|nanos| UNIX_EPOCH + Duration::from_nanos(nanos))
+                        .map(|time| humantime::format_rfc3339_seconds(time).to_string()),
+                    Some(_) => None,
+                }
+                .map(|filled| {
+                    if self.script {
+                        shell_word(&filled)
+                    } else {
+                        filled
+                    }
+                })
+            });
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// An aligned text table, with a row for each object and a column for every key present in any of
+/// them, in order of first appearance. Fields an object doesn't have are left blank.
+pub struct Table;
+
+impl OutputFormat for Table {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        let (columns, cells) = table_cells(rows(value), "table")?;
+        if columns.is_empty() {
+            return Ok(());
+        }
+        let widths: Vec<usize> = columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain(std::iter::once(column.chars().count()))
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let header: Vec<String> = columns.iter().map(|column| column.to_string()).collect();
+        for row in std::iter::once(&header).chain(&cells) {
+            let line: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect();
+            writeln!(out, "{}", line.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Values separated by a delimiter, with a header row naming the columns as in `Table`. Values
+/// containing the delimiter, a quote or a line break are quoted, with quotes doubled.
+pub struct Csv {
+    pub delimiter: char,
+}
+
+impl OutputFormat for Csv {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> io::Result<()> {
+        let (columns, cells) = table_cells(rows(value), "csv")?;
+        if columns.is_empty() {
+            return Ok(());
+        }
+        let header: Vec<String> = columns.iter().map(|column| column.to_string()).collect();
+        let separator = self.delimiter.to_string();
+        for row in std::iter::once(&header).chain(&cells) {
+            let line: Vec<String> = row
+                .iter()
+                .map(|cell| self.quote(cell).into_owned())
+                .collect();
+            writeln!(out, "{}", line.join(&separator))?;
+        }
+        Ok(())
+    }
+}
+
+impl Csv {
+    /// Quote a value if it would otherwise be split or run onto another record.
+    fn quote<'a>(&self, cell: &'a str) -> std::borrow::Cow<'a, str> {
+        let special = |c: char| c == self.delimiter || matches!(c, '"' | '\n' | '\r');
+        if cell.contains(special) {
+            format!("\"{}\"", cell.replace('"', "\"\"")).into()
+        } else {
+            cell.into()
+        }
+    }
+}
+
+/// The columns and cells of a tabular output. There's a column for every key found in the rows,
+/// in the order keys first appear, and a cell for each, left blank where a row lacks the key.
+///
+/// # Arguments
+///
+/// * `rows` - The rows, each of which must be an object
+/// * `format` - The name of the output, for the error when a row isn't an object
+fn table_cells<'a>(
+    rows: &'a [Value],
+    format: &str,
+) -> io::Result<(Vec<&'a str>, Vec<Vec<String>>)> {
+    let mut columns: Vec<&str> = Vec::new();
+    for row in rows {
+        let fields = row.as_object().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} output needs objects", format),
+            )
+        })?;
+        for key in fields.keys() {
+            if !columns.contains(&key.as_str()) {
+                columns.push(key);
+            }
+        }
+    }
+    let cells = rows
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|column| match row.get(column) {
+                    None | Some(Value::Null) => String::new(),
+                    Some(Value::String(value)) => value.clone(),
+                    Some(value) => value.to_string(),
+                })
+                .collect()
+        })
+        .collect();
+    Ok((columns, cells))
+}
+
+/// Fill in the `{name}` placeholders in a template. Substituted values aren't themselves
+/// searched for placeholders, so job data can't inject into other parts of the template.
+///
+/// # Arguments
+///
+/// * `template` - The template text
+/// * `values` - The value for each placeholder name
+fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    fill_placeholders(template, |name| {
+        values
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.to_string())
+    })
+}
+
+/// Fill in the `{...}` placeholders in a template, leaving any that can't be filled in as they
+/// are.
+///
+/// # Arguments
+///
+/// * `template` - The template text
+/// * `lookup` - Gets the value of a placeholder from the text between its braces
+fn fill_placeholders(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let placeholder = rest[1..]
+            .find('}')
+            .map(|end| &rest[1..=end])
+            .and_then(|name| lookup(name).map(|value| (name, value)));
+        match placeholder {
+            Some((name, value)) => {
+                rendered.push_str(&value);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Render rows as an HTML table, with a column for every field present in any of them, in the
+/// order they first appear.
+///
+/// # Arguments
+///
+/// * `rows` - The JSON objects to render
+fn html_table(rows: &[Value]) -> String {
+    let mut columns: Vec<&str> = Vec::new();
+    for key in rows
+        .iter()
+        .filter_map(Value::as_object)
+        .flat_map(|row| row.keys())
+    {
+        if !columns.contains(&key.as_str()) {
+            columns.push(key);
+        }
+    }
+    let mut table = String::from("<table>\n<tr>");
+    for column in &columns {
+        table.push_str(&format!("<th>{}</th>", escape_html(column)));
+    }
+    table.push_str("</tr>\n");
+    for row in rows {
+        table.push_str("<tr>");
+        for column in &columns {
+            let cell = match row.get(column) {
+                None => String::new(),
+                Some(Value::String(value)) => value.clone(),
+                Some(value) => value.to_string(),
+            };
+            table.push_str(&format!("<td>{}</td>", escape_html(&cell)));
+        }
+        table.push_str("</tr>\n");
+    }
+    table.push_str("</table>");
+    table
+}
+
+/// Escape text so it can be safely included in HTML, whether as content or an attribute value.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The messages logged to syslog for a query's output: how many jobs matched, then each job as
+/// compact JSON. Projecting the jobs with `--fields` keeps the messages short.
+///
+/// # Arguments
+///
+/// * `value` - The output, usually an array with an entry for each job
+pub fn syslog_messages(value: &Value) -> Vec<String> {
+    let rows = rows(value);
+    std::iter::once(format!("{} matching jobs", rows.len()))
+        .chain(rows.iter().map(Value::to_string))
+        .collect()
+}
+
+/// Send messages to syslog at the info level.
+///
+/// # Arguments
+///
+/// * `logger` - The connection to syslog, carrying the facility and tag
+/// * `messages` - The messages to send
+pub fn send_to_syslog<W: Write>(
+    logger: &mut syslog::Logger<W, syslog::Formatter3164>,
+    messages: &[String],
+) -> Result<()> {
+    for message in messages {
+        logger
+            .info(message)
+            .map_err(|err| anyhow!("could not write to syslog: {}", err))?;
+    }
+    Ok(())
+}
+
+/// The rows making up a value: the entries of an array, or the value itself.
+fn rows(value: &Value) -> &[Value] {
+    match value {
+        Value::Array(rows) => rows,
+        _ => std::slice::from_ref(value),
+    }
+}
+
+/// The name of a JSON value's type, as used in the NDJSON header.
+fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Describe the fields present across a set of rows, along with the types each field holds.
+///
+/// # Arguments
+///
+/// * `rows` - The JSON objects that will follow the header
+fn ndjson_header(rows: &[Value]) -> Value {
+    let mut fields: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for row in rows {
+        if let Some(row) = row.as_object() {
+            for (key, value) in row {
+                fields
+                    .entry(key.as_str())
+                    .or_default()
+                    .insert(json_type_name(value));
+            }
+        }
+    }
+    let fields: Vec<Value> = fields
+        .into_iter()
+        .map(|(name, types)| json!({"name": name, "types": types}))
+        .collect();
+    json!({ "fields": fields })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_to_string(format: &dyn OutputFormat, value: &Value) -> String {
+        let mut out = Vec::new();
+        format.write(value, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_json() {
+        let value = json!([{"ID": "example"}]);
+        assert_eq!(
+            write_to_string(&Json { pretty: false }, &value),
+            "[{\"ID\":\"example\"}]\n"
+        );
+        assert_eq!(
+            write_to_string(&Json { pretty: true }, &value),
+            "[\n  {\n    \"ID\": \"example\"\n  }\n]\n"
+        );
+    }
+
+    #[test]
+    fn test_wrapped_json() {
+        let payload = "x".repeat(40);
+        let value = json!({"ID": "example", "Payload": payload, "Count": 1});
+        let output = write_to_string(&WrappedJson { width: 20 }, &value);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[1], "  \"ID\": \"example\",");
+        assert_eq!(lines[2], "  \"Payload\": \"xxxxxx");
+        assert!(lines[3].starts_with("  \u{21aa} x"));
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|line| line.contains(WRAP_MARKER))
+                .count(),
+            3
+        );
+        assert!(lines.iter().all(|line| line.chars().count() <= 20));
+        assert_eq!(lines[lines.len() - 2], "  \"Count\": 1");
+        let unwrapped: String = lines[2..lines.len() - 2]
+            .iter()
+            .map(|line| line.trim_start().trim_start_matches(WRAP_MARKER))
+            .collect();
+        assert_eq!(unwrapped, format!("\"Payload\": \"{}\",", payload));
+    }
+
+    #[test]
+    fn test_send_to_syslog() {
+        let value = json!([{"ID": "example", "Status": "running"}]);
+        let messages = syslog_messages(&value);
+        assert_eq!(
+            messages,
+            ["1 matching jobs", r#"{"ID":"example","Status":"running"}"#]
+        );
+        let formatter = syslog::Formatter3164 {
+            facility: syslog::Facility::LOG_LOCAL0,
+            hostname: None,
+            process: String::from("nquery-monitor"),
+            pid: 42,
+        };
+        let mut logger = syslog::Logger::new(Vec::new(), formatter);
+        send_to_syslog(&mut logger, &messages[1..]).unwrap();
+        let line = String::from_utf8(logger.backend).unwrap();
+        // local0 is facility 16, and info is severity 6
+        assert!(line.starts_with("<134>"), "{}", line);
+        assert!(
+            line.ends_with(r#" nquery-monitor[42]: {"ID":"example","Status":"running"}"#),
+            "{}",
+            line
+        );
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let value = json!([{"ID": "example"}, {"ID": "other"}]);
+        assert_eq!(
+            write_to_string(&JsonLines, &value),
+            "{\"ID\":\"example\"}\n{\"ID\":\"other\"}\n"
+        );
+        assert_eq!(
+            write_to_string(&JsonLines, &json!({"ID": "example"})),
+            "{\"ID\":\"example\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_yaml_stream() {
+        let value = json!([{"ID": "example", "Priority": 50}, {"ID": "other"}]);
+        let output = write_to_string(&YamlStream, &value);
+        assert_eq!(output, "---\nID: example\nPriority: 50\n---\nID: other\n");
+        assert_eq!(output.matches("---\n").count(), 2);
+    }
+
+    #[test]
+    fn test_ndjson_header() {
+        let rows = vec![
+            json!({"ID": "example", "Meta.owner": "ops"}),
+            json!({"ID": "other", "Meta.owner": null, "Priority": 50}),
+        ];
+        let header = ndjson_header(&rows);
+        assert_eq!(
+            header,
+            json!({"fields": [
+                {"name": "ID", "types": ["string"]},
+                {"name": "Meta.owner", "types": ["null", "string"]},
+                {"name": "Priority", "types": ["number"]},
+            ]})
+        );
+    }
+
+    #[test]
+    fn test_ndjson_with_header() {
+        let value = json!([{"ID": "example"}, {"ID": "other"}]);
+        let out = write_to_string(&NdjsonWithHeader, &value);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"fields":[{"name":"ID","types":["string"]}]}"#,
+                r#"{"ID":"example"}"#,
+                r#"{"ID":"other"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_template() {
+        let job: Value = serde_json::from_str(crate::nomad::test::FULL_JOB).unwrap();
+        let value = json!([{
+            "ID": job["ID"],
+            "SubmitTime": job["SubmitTime"],
+            "KillTimeout": job["TaskGroups"][0]["Tasks"][0]["KillTimeout"],
+        }]);
+        let template = Template {
+            template: String::from(
+                "{ID} submitted {timestamp SubmitTime}, kill timeout {duration KillTimeout} {Missing}",
+            ),
+            script: false,
+        };
+        assert_eq!(
+            write_to_string(&template, &value),
+            "example submitted 2020-11-02T23:45:07Z, kill timeout 5s {Missing}\n"
+        );
+    }
+
+    #[test]
+    fn test_template_script() {
+        let value = json!([{"ID": "example"}, {"ID": "web; rm -rf /"}]);
+        let template = Template {
+            template: String::from("nomad job stop {ID}"),
+            script: true,
+        };
+        assert_eq!(
+            write_to_string(&template, &value),
+            "#!/bin/sh\nnomad job stop example\nnomad job stop 'web; rm -rf /'\n"
+        );
+        assert_eq!(shell_word("it's"), "'it'\\''s'");
+        assert_eq!(shell_word(""), "''");
+    }
+
+    #[test]
+    fn test_html() {
+        let value = json!([
+            {"ID": "example", "Priority": 50},
+            {"ID": "<script>alert('hi')</script>", "Meta": {"owner": "a&b"}},
+        ]);
+        let html = Html {
+            query: String::from("--fields ID {table}"),
+            generated_at: String::from("2020-11-03T00:00:00Z"),
+        };
+        let out = write_to_string(&html, &value);
+        assert!(out.starts_with("<!DOCTYPE html>"));
+        assert!(out.trim_end().ends_with("</html>"));
+        assert!(out.contains("<code>--fields ID {table}</code>"));
+        assert!(out.contains("Generated at 2020-11-03T00:00:00Z, 2 jobs"));
+        // Columns follow the order fields first appear, as in the other tables
+        assert!(out.contains("<tr><th>ID</th><th>Priority</th><th>Meta</th></tr>"));
+        assert!(out.contains("<tr><td>example</td><td>50</td><td></td></tr>"));
+        assert!(out.contains("<td>&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt;</td>"));
+        assert!(out.contains("<td>{&quot;owner&quot;:&quot;a&amp;b&quot;}</td>"));
+        assert!(!out.contains("<script>"));
+        // Every row has a cell for each column, and the table is only included once
+        assert_eq!(out.matches("<table>").count(), 1);
+        assert_eq!(out.matches("<tr>").count(), 3);
+        assert_eq!(out.matches("<td>").count(), 6);
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("example"), "example");
+        assert_eq!(file_name("batch-job_v1.2"), "batch-job_v1.2");
+        assert_eq!(
+            file_name("example/periodic-1604360707"),
+            "example%2Fperiodic-1604360707"
+        );
+        assert_eq!(file_name("a b:c"), "a%20b%3Ac");
+        assert_eq!(file_name(".."), "%2E.");
+    }
+
+    #[test]
+    fn test_write_files() {
+        let dir =
+            std::env::temp_dir().join(format!("nquery-test-write-files-{}", std::process::id()));
+        let jobs = [
+            ("example", json!({"ID": "example"})),
+            ("example/dispatch-1", json!({"ID": "example/dispatch-1"})),
+        ];
+        let written = write_files(
+            &dir,
+            jobs.iter().map(|(id, job)| (*id, job)),
+            &Json { pretty: false },
+            "json",
+        )
+        .unwrap();
+        assert_eq!(
+            written,
+            vec![
+                dir.join("example.json"),
+                dir.join("example%2Fdispatch-1.json")
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("example.json")).unwrap(),
+            "{\"ID\":\"example\"}\n"
+        );
+        assert!(dir.join("example%2Fdispatch-1.json").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_output_mode_format() {
+        let value = json!([{"ID": "example"}]);
+        let format = OutputMode::from_str("json-stream")
+            .unwrap()
+            .format(true, None, ',', false, "");
+        assert_eq!(
+            write_to_string(format.as_ref(), &value),
+            "{\"ID\":\"example\"}\n"
+        );
+        assert!(OutputMode::from_str("xml").is_err());
+    }
+
+    #[test]
+    fn test_table() {
+        let value = json!([
+            {"ID": "example", "Count": 1},
+            {"ID": "a-longer-id", "Region": "west", "Count": null},
+        ]);
+        assert_eq!(
+            write_to_string(&Table, &value),
+            "ID           Count  Region\nexample      1\na-longer-id         west\n"
+        );
+        assert_eq!(write_to_string(&Table, &json!([])), "");
+        assert!(Table.write(&json!(["example"]), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_csv() {
+        let value = json!([
+            {"ID": "example", "Description": "cache, shared", "Count": 1},
+            {"ID": "other", "Description": "says \"hi\""},
+        ]);
+        assert_eq!(
+            write_to_string(&Csv { delimiter: ';' }, &value),
+            "ID;Description;Count\nexample;cache, shared;1\nother;\"says \"\"hi\"\"\";\n"
+        );
+        assert_eq!(
+            write_to_string(&Csv { delimiter: ',' }, &value),
+            "ID,Description,Count\nexample,\"cache, shared\",1\nother,\"says \"\"hi\"\"\",\n"
+        );
+        let multiline = json!([{"ID": "example", "Template": "a\nb"}]);
+        assert_eq!(
+            write_to_string(&Csv { delimiter: '\t' }, &multiline),
+            "ID\tTemplate\nexample\t\"a\nb\"\n"
+        );
+        assert!(Csv { delimiter: ',' }
+            .write(&json!(["example"]), &mut Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_env() {
+        let value = json!([{"ID": "example", "Status": "running", "Priority": 50}]);
+        assert_eq!(
+            write_to_string(&Env, &value),
+            "ID='example'\nStatus='running'\nPriority='50'\n"
+        );
+        let value = json!({"ID": "example", "Meta.owner": "it's the ops team", "Stop": null});
+        assert_eq!(
+            write_to_string(&Env, &value),
+            "ID='example'\nMeta_owner='it'\\''s the ops team'\nStop=''\n"
+        );
+    }
+
+    #[test]
+    fn test_env_several_jobs() {
+        let value = json!([{"ID": "web-1", "Status": "running"}, {"ID": "2nd", "Status": "dead"}]);
+        assert_eq!(
+            write_to_string(&Env, &value),
+            "web_1_ID='web-1'\nweb_1_Status='running'\n_2nd_ID='2nd'\n_2nd_Status='dead'\n"
+        );
+    }
+
+    #[test]
+    fn test_env_rejects_non_scalars() {
+        let value = json!([{"ID": "example", "TaskGroups": [{"Name": "cache"}]}]);
+        let mut out = Vec::new();
+        let err = Env.write(&value, &mut out).unwrap_err();
+        assert!(err.to_string().contains("TaskGroups"), "{}", err);
+    }
+}
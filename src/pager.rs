@@ -0,0 +1,150 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+#[cfg(unix)]
+use std::process::{Command, Stdio};
+
+/// The pager used when neither `--pager` nor `$PAGER` name one
+const DEFAULT_PAGER: &str = "less -R";
+
+/// How many rows to assume the terminal has when `$LINES` isn't set
+const DEFAULT_ROWS: usize = 24;
+
+/// Decide which pager, if any, output should be sent through.
+///
+/// Paging is only done when stdout is a terminal, so piped output is never affected, and only on
+/// unix, where the pager can be run through the shell.
+///
+/// # Arguments
+///
+/// * `requested` - The pager command given with `--pager`
+/// * `disabled` - Whether `--no-pager` was given
+pub fn pager_command(requested: Option<String>, disabled: bool) -> Option<String> {
+    if disabled || !cfg!(unix) || !io::stdout().is_terminal() {
+        return None;
+    }
+    resolve_pager(requested, env::var("PAGER").ok())
+}
+
+/// Pick the pager command from `--pager`, then `$PAGER`, then the default. An empty command
+/// disables paging, as it does for git.
+fn resolve_pager(requested: Option<String>, env_pager: Option<String>) -> Option<String> {
+    let command = requested
+        .or(env_pager)
+        .unwrap_or_else(|| String::from(DEFAULT_PAGER));
+    if command.trim().is_empty() {
+        None
+    } else {
+        Some(command)
+    }
+}
+
+/// The number of rows in the terminal, as reported by `$LINES`.
+fn terminal_rows() -> usize {
+    env::var("LINES")
+        .ok()
+        .and_then(|lines| lines.parse().ok())
+        .unwrap_or(DEFAULT_ROWS)
+}
+
+/// Check whether output has more lines than fit in the terminal.
+fn is_large(output: &[u8], rows: usize) -> bool {
+    output.iter().filter(|&&byte| byte == b'\n').count() > rows
+}
+
+/// Write output to stdout, through the pager if one is given and the output won't fit on screen.
+/// If the pager can't be run, or fails, the output is written straight to stdout instead so it
+/// isn't lost.
+///
+/// # Arguments
+///
+/// * `output` - The fully serialized output
+/// * `pager` - The pager command chosen by `pager_command`
+pub fn write(output: &[u8], pager: Option<&str>) -> io::Result<()> {
+    if let Some(command) = pager.filter(|_| is_large(output, terminal_rows())) {
+        match page(output, command) {
+            Ok(()) => return Ok(()),
+            Err(err) => eprintln!("Could not page the output, writing it directly: {}", err),
+        }
+    }
+    io::stdout().write_all(output)
+}
+
+/// Run the pager, writing output to its stdin and waiting for the user to close it. Fails if the
+/// pager can't be started or exits unsuccessfully, e.g. because it isn't installed.
+///
+/// # Arguments
+///
+/// * `output` - The fully serialized output
+/// * `command` - The pager command, run through the shell so it can include arguments
+#[cfg(unix)]
+fn page(output: &[u8], command: &str) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let written = child.stdin.take().unwrap().write_all(output);
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "'{}' failed with {}",
+            command, status
+        )));
+    }
+    match written {
+        // The user quit the pager before reading everything
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        written => written,
+    }
+}
+
+/// Paging needs a shell to run the pager through, so isn't supported elsewhere.
+#[cfg(not(unix))]
+fn page(_output: &[u8], command: &str) -> io::Result<()> {
+    Err(io::Error::other(format!(
+        "can't run '{}' without a unix shell",
+        command
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_pager() {
+        assert_eq!(resolve_pager(None, None), Some(String::from("less -R")));
+        assert_eq!(
+            resolve_pager(None, Some(String::from("more"))),
+            Some(String::from("more"))
+        );
+        assert_eq!(
+            resolve_pager(Some(String::from("bat")), Some(String::from("more"))),
+            Some(String::from("bat"))
+        );
+        assert_eq!(resolve_pager(None, Some(String::new())), None);
+    }
+
+    #[test]
+    fn test_pager_disabled() {
+        assert_eq!(pager_command(Some(String::from("bat")), true), None);
+    }
+
+    #[test]
+    fn test_is_large() {
+        assert!(!is_large(b"[]\n", 24));
+        assert!(!is_large(b"a\nb\n", 2));
+        assert!(is_large(b"a\nb\nc\n", 2));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_page() {
+        assert!(page(b"a\nb\n", "cat > /dev/null").is_ok());
+        // Quitting before reading all of the output isn't an error
+        assert!(page(&vec![b'\n'; 1 << 20], "true").is_ok());
+        // A missing or failing pager is, so the output can be written without it
+        assert!(page(b"a\nb\n", "nquery-test-missing-pager").is_err());
+        assert!(page(&vec![b'\n'; 1 << 20], "exit 1").is_err());
+    }
+}
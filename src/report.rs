@@ -0,0 +1,717 @@
+use crate::nomad::{Allocation, AllocationMetric, Deployment, Evaluation, Job, JobListing};
+use crate::schedule;
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An upper bound on job age, labelled as it was given on the command line
+#[derive(Debug, PartialEq)]
+pub struct AgeBucket {
+    pub label: String,
+    pub max_age: Duration,
+}
+
+/// Age buckets in ascending order, parsed from a comma-separated list of durations like `1h,1d,1w`
+#[derive(Debug, PartialEq)]
+pub struct AgeBuckets(pub Vec<AgeBucket>);
+
+impl FromStr for AgeBuckets {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        parse_age_buckets(spec).map(AgeBuckets)
+    }
+}
+
+/// Parse a comma-separated list of ascending durations into age buckets.
+///
+/// # Arguments
+///
+/// * `spec` - The bucket boundaries
+fn parse_age_buckets(spec: &str) -> Result<Vec<AgeBucket>> {
+    let mut buckets: Vec<AgeBucket> = Vec::new();
+    for label in spec.split(',').map(str::trim) {
+        let max_age = humantime::parse_duration(label)
+            .map_err(|err| anyhow!("invalid bucket boundary '{}': {}", label, err))?;
+        if let Some(previous) = buckets.last() {
+            if max_age <= previous.max_age {
+                return Err(anyhow!(
+                    "bucket boundaries must be ascending, but '{}' follows '{}'",
+                    label,
+                    previous.label
+                ));
+            }
+        }
+        buckets.push(AgeBucket {
+            label: label.into(),
+            max_age,
+        });
+    }
+    Ok(buckets)
+}
+
+/// The one or two listing fields jobs are counted by in a count table, parsed from a
+/// comma-separated list like `Status,Type`
+#[derive(Debug, PartialEq)]
+pub struct CountBy(pub Vec<String>);
+
+impl FromStr for CountBy {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let fields: Vec<String> = spec.split(',').map(|field| field.trim().into()).collect();
+        if fields.len() > 2 || fields.iter().any(String::is_empty) {
+            return Err(anyhow!(
+                "expected one or two fields, e.g. Status,Type, got '{}'",
+                spec
+            ));
+        }
+        Ok(CountBy(fields))
+    }
+}
+
+/// Count the listed jobs by the values of one or two listing fields, as rows for a table.
+///
+/// By one field, there's a row for each of its values with the number of jobs having it. By two,
+/// the first field's values label the rows and the second's the columns, with each cell counting
+/// the jobs having both. Rows are followed by a `Total` column, and a `Total` row ends the table.
+///
+/// # Arguments
+///
+/// * `listing` - The jobs to count
+/// * `by` - The fields to count by, rows first
+pub fn count_table(listing: &[&JobListing], by: &[String]) -> Value {
+    let field = |job: &Value, name: &str| match job.get(name) {
+        None | Some(Value::Null) => String::from("-"),
+        Some(Value::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+    };
+    let mut counts: BTreeMap<String, BTreeMap<String, u64>> = BTreeMap::new();
+    let mut columns = BTreeSet::new();
+    for job in listing {
+        let job = serde_json::to_value(job).unwrap();
+        let row = field(&job, &by[0]);
+        let column = by.get(1).map(|name| field(&job, name)).unwrap_or_default();
+        *counts
+            .entry(row)
+            .or_default()
+            .entry(column.clone())
+            .or_default() += 1;
+        columns.insert(column);
+    }
+    let label = by.join("/");
+    let render_row = |name: &str, cells: &BTreeMap<String, u64>| {
+        let mut row = serde_json::Map::new();
+        row.insert(label.clone(), name.into());
+        if by.len() > 1 {
+            for column in &columns {
+                row.insert(
+                    column.clone(),
+                    cells.get(column).copied().unwrap_or(0).into(),
+                );
+            }
+        }
+        row.insert("Total".into(), cells.values().sum::<u64>().into());
+        Value::Object(row)
+    };
+    let mut totals: BTreeMap<String, u64> = BTreeMap::new();
+    let mut rows = Vec::new();
+    for (name, cells) in &counts {
+        for (column, count) in cells {
+            *totals.entry(column.clone()).or_default() += count;
+        }
+        rows.push(render_row(name, cells));
+    }
+    rows.push(render_row("Total", &totals));
+    Value::Array(rows)
+}
+
+/// Count the listed jobs by how long ago they were submitted.
+///
+/// Each job is counted in the first bucket it is younger than, or in a final bucket for anything
+/// older. Jobs without a submission time aren't counted.
+///
+/// # Arguments
+///
+/// * `listing` - The jobs to count
+/// * `buckets` - The upper bounds of each bucket, in ascending order
+/// * `now` - The time that job ages are measured from
+pub fn submit_histogram(listing: &[JobListing], buckets: &[AgeBucket], now: SystemTime) -> Value {
+    let mut counts = vec![0; buckets.len() + 1];
+    for job in listing {
+        let submitted = match job.SubmitTime {
+            Some(nanos) => UNIX_EPOCH + Duration::from_nanos(nanos),
+            None => continue,
+        };
+        let age = now.duration_since(submitted).unwrap_or_default();
+        let bucket = buckets
+            .iter()
+            .position(|bucket| age < bucket.max_age)
+            .unwrap_or(buckets.len());
+        counts[bucket] += 1;
+    }
+    let mut labels: Vec<String> = buckets
+        .iter()
+        .map(|bucket| format!("<{}", bucket.label))
+        .collect();
+    labels.push(match buckets.last() {
+        Some(bucket) => format!(">={}", bucket.label),
+        None => String::from("all"),
+    });
+    labels
+        .into_iter()
+        .zip(counts)
+        .map(|(bucket, count)| json!({"bucket": bucket, "count": count}))
+        .collect()
+}
+
+/// List the distinct images run across a set of jobs, sorted by name.
+///
+/// # Arguments
+///
+/// * `jobs` - The jobs whose tasks' images to collect
+/// * `with_counts` - Whether to include how many of the jobs run each image
+pub fn image_summary<'a>(jobs: impl IntoIterator<Item = &'a Job>, with_counts: bool) -> Value {
+    let mut images: BTreeMap<&str, u64> = BTreeMap::new();
+    for job in jobs {
+        for image in job.images() {
+            *images.entry(image).or_default() += 1;
+        }
+    }
+    if with_counts {
+        images
+            .into_iter()
+            .map(|(image, jobs)| json!({"image": image, "jobs": jobs}))
+            .collect()
+    } else {
+        images.into_keys().map(Value::from).collect()
+    }
+}
+
+/// Compare jobs from a previous run with the current ones, matching them up by ID. Reports the
+/// jobs that were added or removed, and for jobs in both, which of their fields changed.
+///
+/// # Arguments
+///
+/// * `previous` - The jobs from the previous run
+/// * `current` - The jobs from this run
+pub fn diff_jobs(previous: &[Value], current: &[Value]) -> Value {
+    let id = |job: &Value| job.get("ID").and_then(Value::as_str).map(String::from);
+    let before: BTreeMap<String, &Value> = previous
+        .iter()
+        .filter_map(|job| Some((id(job)?, job)))
+        .collect();
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    let mut seen = Vec::new();
+    for job in current {
+        let job_id = match id(job) {
+            Some(job_id) => job_id,
+            None => continue,
+        };
+        match before.get(&job_id) {
+            None => added.push(job.clone()),
+            Some(&old) if old != job => {
+                changed.push(json!({"ID": job_id, "Fields": changed_fields(old, job)}))
+            }
+            Some(_) => {}
+        }
+        seen.push(job_id);
+    }
+    let removed: Vec<Value> = before
+        .iter()
+        .filter(|(job_id, _)| !seen.contains(job_id))
+        .map(|(_, job)| (*job).clone())
+        .collect();
+    json!({"added": added, "removed": removed, "changed": changed})
+}
+
+/// The top-level fields that differ between two versions of a job, sorted by name.
+fn changed_fields(old: &Value, new: &Value) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+    let keys: std::collections::BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect()
+}
+
+/// Check whether a diff from `diff_jobs` found any differences.
+pub fn has_differences(diff: &Value) -> bool {
+    ["added", "removed", "changed"]
+        .iter()
+        .any(|key| diff[key].as_array().is_some_and(|jobs| !jobs.is_empty()))
+}
+
+/// Render jobs as an indented tree, with dispatched and periodic child jobs nested under their
+/// parents. Jobs whose parent isn't among them are shown at the root.
+///
+/// # Arguments
+///
+/// * `listing` - The jobs to render
+pub fn job_tree(listing: &[&JobListing]) -> String {
+    let ids: Vec<&str> = listing.iter().map(|job| job.ID.as_str()).collect();
+    let mut children: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    let mut roots = Vec::new();
+    for job in listing {
+        if job.ParentID.is_empty() || !ids.contains(&job.ParentID.as_str()) {
+            roots.push(job.ID.as_str());
+        } else {
+            children.entry(&job.ParentID).or_default().push(&job.ID);
+        }
+    }
+    let mut tree = String::new();
+    for root in roots {
+        render_subtree(root, 0, &children, &mut tree);
+    }
+    tree
+}
+
+fn render_subtree(id: &str, depth: usize, children: &BTreeMap<&str, Vec<&str>>, tree: &mut String) {
+    tree.push_str(&"  ".repeat(depth));
+    tree.push_str(id);
+    tree.push('\n');
+    for child in children.get(id).into_iter().flatten() {
+        render_subtree(child, depth + 1, children, tree);
+    }
+}
+
+/// List the services each job registers, with the port each is advertised on and its tags.
+///
+/// # Arguments
+///
+/// * `jobs` - The jobs whose services to list
+pub fn service_summary<'a>(jobs: impl IntoIterator<Item = &'a Job>) -> Value {
+    jobs.into_iter()
+        .map(|job| {
+            let services: Vec<Value> = job
+                .services()
+                .into_iter()
+                .map(|service| {
+                    json!({
+                        "Name": service.Name,
+                        "PortLabel": service.PortLabel,
+                        "Tags": service.Tags.as_deref().unwrap_or_default(),
+                    })
+                })
+                .collect();
+            json!({"ID": job.listing.ID, "Services": services})
+        })
+        .collect()
+}
+
+/// Describe how each job's tasks are started and stopped: the signal each is stopped with, how long
+/// it's given to exit before being killed, and the lifecycle hook it runs at, if any.
+///
+/// # Arguments
+///
+/// * `jobs` - The jobs whose tasks to describe
+pub fn lifecycle_summary<'a>(jobs: impl IntoIterator<Item = &'a Job>) -> Value {
+    jobs.into_iter()
+        .map(|job| {
+            let tasks: Vec<Value> = job
+                .tasks()
+                .into_iter()
+                .map(|(group, task)| {
+                    let kill_timeout = task.KillTimeout.map(|timeout| {
+                        humantime::format_duration(Duration::from_nanos(timeout)).to_string()
+                    });
+                    json!({
+                        "Group": group,
+                        "Task": task.Name,
+                        "KillSignal": task.KillSignal,
+                        "KillTimeout": kill_timeout,
+                        "Hook": task.Lifecycle.as_ref().map(|lifecycle| &lifecycle.Hook),
+                        "Sidecar": task.Lifecycle.as_ref().map(|lifecycle| lifecycle.Sidecar),
+                    })
+                })
+                .collect();
+            json!({"ID": job.listing.ID, "Tasks": tasks})
+        })
+        .collect()
+}
+
+/// Count a job's allocations by their client status, e.g. running, complete, failed or lost.
+///
+/// # Arguments
+///
+/// * `job_id` - The ID of the job the allocations belong to
+/// * `allocations` - The job's allocations
+pub fn allocation_counts(job_id: &str, allocations: &[Allocation]) -> Value {
+    let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+    for allocation in allocations {
+        *counts.entry(&allocation.ClientStatus).or_default() += 1;
+    }
+    json!({"ID": job_id, "Allocations": counts})
+}
+
+/// Describe why a job's allocations can't be placed, if its latest evaluation is blocked waiting for
+/// capacity. The placement failures are taken from the evaluation that gave up and created the
+/// blocked one.
+///
+/// # Arguments
+///
+/// * `job_id` - The ID of the job the evaluations belong to
+/// * `evaluations` - The job's evaluations
+pub fn blocked_evaluation(job_id: &str, evaluations: &[Evaluation]) -> Option<Value> {
+    let latest = evaluations
+        .iter()
+        .max_by_key(|evaluation| evaluation.CreateIndex)?;
+    if latest.Status != "blocked" {
+        return None;
+    }
+    let failed = evaluations
+        .iter()
+        .find(|evaluation| evaluation.BlockedEval == latest.ID)
+        .unwrap_or(latest);
+    let groups: BTreeMap<&str, &AllocationMetric> = failed
+        .FailedTGAllocs
+        .iter()
+        .flatten()
+        .map(|(group, metric)| (group.as_str(), metric))
+        .collect();
+    Some(json!({
+        "ID": job_id,
+        "EvalID": latest.ID,
+        "Reason": latest.StatusDescription,
+        "FailedTaskGroups": groups,
+    }))
+}
+
+/// Summarize how a job's latest deployment is rolling out. Jobs that have never been deployed,
+/// such as batch jobs, have a null rollout.
+///
+/// # Arguments
+///
+/// * `job_id` - The ID of the job the deployment belongs to
+/// * `deployment` - The job's latest deployment, if it has one
+pub fn deployment_status(job_id: &str, deployment: Option<&Deployment>) -> Value {
+    match deployment {
+        Some(deployment) => json!({
+            "ID": job_id,
+            "DeploymentID": deployment.ID,
+            "Rollout": deployment.rollout_status(),
+            "Description": deployment.StatusDescription,
+        }),
+        None => json!({"ID": job_id, "DeploymentID": null, "Rollout": null, "Description": null}),
+    }
+}
+
+/// Describe the schedule of each periodic job, leaving out jobs that aren't periodic.
+///
+/// # Arguments
+///
+/// * `jobs` - The jobs whose schedules to describe
+/// * `now` - The time to find each job's next launch after
+pub fn periodic_summary<'a>(jobs: impl IntoIterator<Item = &'a Job>, now: SystemTime) -> Value {
+    jobs.into_iter()
+        .filter_map(|job| Some((job, job.Periodic.as_ref()?)))
+        .map(|(job, periodic)| {
+            let mut summary = json!({
+                "ID": job.listing.ID,
+                "Enabled": periodic.Enabled,
+                "Spec": periodic.Spec,
+                "SpecType": periodic.SpecType,
+                "TimeZone": periodic.TimeZone,
+                "ProhibitOverlap": periodic.ProhibitOverlap,
+            });
+            match schedule::next_run(periodic, now) {
+                Ok(next) => {
+                    summary["NextRun"] =
+                        json!(next
+                            .map(|next| { humantime::format_rfc3339_seconds(next).to_string() }))
+                }
+                Err(err) => {
+                    summary["NextRun"] = Value::Null;
+                    summary["NextRunError"] = json!(err.to_string());
+                }
+            }
+            summary
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn submitted_ago(now: SystemTime, ago: Duration) -> JobListing {
+        let mut listing: Vec<JobListing> =
+            serde_json::from_str(crate::nomad::test::JOB_LISTING).unwrap();
+        let mut job = listing.remove(0);
+        job.SubmitTime = Some((now - ago).duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64);
+        job
+    }
+
+    #[test]
+    fn test_periodic_summary() {
+        let periodic: Job = crate::nomad::test::full_job_with(&[
+            ("/Periodic", crate::nomad::test::PERIODIC),
+            ("/Type", r#""batch""#),
+        ]);
+        let service: Job = serde_json::from_str(crate::nomad::test::FULL_JOB).unwrap();
+        let now = humantime::parse_rfc3339("2020-11-03T00:05:00Z").unwrap();
+        assert_eq!(
+            periodic_summary(vec![&service, &periodic], now),
+            json!([{
+                "ID": "example",
+                "Enabled": true,
+                "Spec": "0 2 * * *",
+                "SpecType": "cron",
+                "TimeZone": "America/New_York",
+                "ProhibitOverlap": true,
+                "NextRun": "2020-11-03T07:00:00Z",
+            }])
+        );
+    }
+
+    #[test]
+    fn test_image_summary() {
+        let job: Job = serde_json::from_str(crate::nomad::test::FULL_JOB).unwrap();
+        let other: Job = crate::nomad::test::full_job_with(&[(
+            "/TaskGroups/0/Tasks/0/Config/image",
+            r#""postgres:13""#,
+        )]);
+        assert_eq!(image_summary(vec![&job], false), json!(["redis:3.2"]));
+        assert_eq!(
+            image_summary(vec![&job, &other, &job], true),
+            json!([
+                {"image": "postgres:13", "jobs": 1},
+                {"image": "redis:3.2", "jobs": 2},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_age_buckets() {
+        let buckets = parse_age_buckets("1h, 1d").unwrap();
+        assert_eq!(
+            buckets,
+            vec![
+                AgeBucket {
+                    label: "1h".into(),
+                    max_age: Duration::from_secs(3600)
+                },
+                AgeBucket {
+                    label: "1d".into(),
+                    max_age: Duration::from_secs(86400)
+                },
+            ]
+        );
+        assert!(parse_age_buckets("1d,1h").is_err());
+        assert!(parse_age_buckets("soon").is_err());
+    }
+
+    #[test]
+    fn test_submit_histogram() {
+        let now = SystemTime::now();
+        let mut listing = vec![
+            submitted_ago(now, Duration::from_secs(60)),
+            submitted_ago(now, Duration::from_secs(7200)),
+            submitted_ago(now, Duration::from_secs(30 * 86400)),
+            submitted_ago(now, Duration::from_secs(120)),
+        ];
+        listing[3].SubmitTime = None;
+        let buckets = parse_age_buckets("1h,1d,1w").unwrap();
+        assert_eq!(
+            submit_histogram(&listing, &buckets, now),
+            json!([
+                {"bucket": "<1h", "count": 1},
+                {"bucket": "<1d", "count": 1},
+                {"bucket": "<1w", "count": 0},
+                {"bucket": ">=1w", "count": 1},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_diff_jobs() {
+        let previous = vec![
+            json!({"ID": "web", "Status": "running", "Version": 3}),
+            json!({"ID": "api", "Status": "running", "Version": 1}),
+            json!({"ID": "old", "Status": "dead"}),
+        ];
+        let current = vec![
+            json!({"ID": "web", "Status": "running", "Version": 3}),
+            json!({"ID": "api", "Status": "pending", "Version": 2}),
+            json!({"ID": "new", "Status": "pending"}),
+        ];
+        let diff = diff_jobs(&previous, &current);
+        assert_eq!(
+            diff,
+            json!({
+                "added": [{"ID": "new", "Status": "pending"}],
+                "removed": [{"ID": "old", "Status": "dead"}],
+                "changed": [{"ID": "api", "Fields": ["Status", "Version"]}],
+            })
+        );
+        assert!(has_differences(&diff));
+        assert!(!has_differences(&diff_jobs(&current, &current)));
+    }
+
+    #[test]
+    fn test_count_table() {
+        let now = SystemTime::now();
+        let job = |status: &str, kind: &str| {
+            let mut job = submitted_ago(now, Duration::from_secs(60));
+            job.Status = status.into();
+            job.Type = kind.into();
+            job
+        };
+        let listing = [
+            job("running", "service"),
+            job("dead", "batch"),
+            job("running", "batch"),
+            job("running", "service"),
+            job("pending", "system"),
+        ];
+        let listing: Vec<_> = listing.iter().collect();
+        let by = "Status,Type".parse::<CountBy>().unwrap().0;
+        let mut table = Vec::new();
+        crate::output::OutputFormat::write(
+            &crate::output::Table,
+            &count_table(&listing, &by),
+            &mut table,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(table).unwrap(),
+            "Status/Type  batch  service  system  Total\n\
+             dead         1      0        0       1\n\
+             pending      0      0        1       1\n\
+             running      1      2        0       3\n\
+             Total        2      2        1       5\n"
+        );
+        assert_eq!(
+            count_table(&listing, &[String::from("Type")]),
+            json!([
+                {"Type": "batch", "Total": 2},
+                {"Type": "service", "Total": 2},
+                {"Type": "system", "Total": 1},
+                {"Type": "Total", "Total": 5},
+            ])
+        );
+        assert!("Status,Type,Namespace".parse::<CountBy>().is_err());
+        assert!("Status,".parse::<CountBy>().is_err());
+    }
+
+    #[test]
+    fn test_job_tree() {
+        let now = SystemTime::now();
+        let job = |id: &str, parent: &str| {
+            let mut job = submitted_ago(now, Duration::from_secs(60));
+            job.ID = id.into();
+            job.ParentID = parent.into();
+            job
+        };
+        let listing = [
+            job("report", ""),
+            job("report/dispatch-1", "report"),
+            job("web", ""),
+            job("report/dispatch-2", "report"),
+            job("cleanup/periodic-1", "cleanup"),
+        ];
+        assert_eq!(
+            job_tree(&listing.iter().collect::<Vec<_>>()),
+            "report\n  report/dispatch-1\n  report/dispatch-2\nweb\ncleanup/periodic-1\n"
+        );
+    }
+
+    #[test]
+    fn test_service_summary() {
+        let job: Job = serde_json::from_str(crate::nomad::test::FULL_JOB).unwrap();
+        assert_eq!(
+            service_summary(vec![&job]),
+            json!([{
+                "ID": "example",
+                "Services": [{"Name": "redis-cache", "PortLabel": "db", "Tags": ["global", "cache"]}],
+            }])
+        );
+    }
+
+    #[test]
+    fn test_lifecycle_summary() {
+        let job: Job = serde_json::from_str(crate::nomad::test::FULL_JOB).unwrap();
+        let sidecar: Job = crate::nomad::test::full_job_with(&[
+            (
+                "/TaskGroups/0/Tasks/0/Lifecycle",
+                r#"{"Hook":"prestart","Sidecar":true}"#,
+            ),
+            ("/TaskGroups/0/Tasks/0/KillSignal", r#""SIGTERM""#),
+        ]);
+        assert_eq!(
+            lifecycle_summary(vec![&job, &sidecar]),
+            json!([
+                {
+                    "ID": "example",
+                    "Tasks": [{
+                        "Group": "cache",
+                        "Task": "redis",
+                        "KillSignal": "",
+                        "KillTimeout": "5s",
+                        "Hook": null,
+                        "Sidecar": null,
+                    }],
+                },
+                {
+                    "ID": "example",
+                    "Tasks": [{
+                        "Group": "cache",
+                        "Task": "redis",
+                        "KillSignal": "SIGTERM",
+                        "KillTimeout": "5s",
+                        "Hook": "prestart",
+                        "Sidecar": true,
+                    }],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_blocked_evaluation() {
+        let evaluations: Vec<Evaluation> =
+            serde_json::from_str(crate::nomad::test::BLOCKED_EVALUATIONS).unwrap();
+        assert_eq!(
+            blocked_evaluation("example", &evaluations),
+            Some(json!({
+                "ID": "example",
+                "EvalID": "0f3a9c4e-8d2b-4b1e-9c55-2a7d6e1f8b30",
+                "Reason": "created due to placement conflicts",
+                "FailedTaskGroups": {"cache": {
+                    "NodesEvaluated": 2,
+                    "NodesFiltered": 0,
+                    "NodesExhausted": 2,
+                    "ConstraintFiltered": null,
+                    "DimensionExhausted": {"memory": 2},
+                }},
+            }))
+        );
+        // Once the blocked evaluation is placed, the job is no longer blocked
+        assert_eq!(blocked_evaluation("example", &evaluations[1..]), None);
+        assert_eq!(blocked_evaluation("example", &[]), None);
+    }
+
+    #[test]
+    fn test_allocation_counts() {
+        let mut allocations: Vec<Value> =
+            serde_json::from_str(crate::nomad::test::ALLOCATIONS).unwrap();
+        let mut failed = allocations[0].clone();
+        failed["ClientStatus"] = json!("failed");
+        allocations.push(failed);
+        let allocations: Vec<Allocation> =
+            serde_json::from_value(Value::Array(allocations)).unwrap();
+        assert_eq!(
+            allocation_counts("example", &allocations),
+            json!({"ID": "example", "Allocations": {"failed": 1, "running": 2}})
+        );
+        assert_eq!(
+            allocation_counts("example", &[]),
+            json!({"ID": "example", "Allocations": {}})
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::nomad::Periodic;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use croner::Cron;
+use std::time::SystemTime;
+
+/// The only periodic spec type Nomad currently evaluates, and so the only one nquery understands
+const CRON_SPEC_TYPE: &str = "cron";
+
+/// Work out when a periodic job will next be launched.
+///
+/// Returns `None` for schedules that aren't cron specs, which nquery can't evaluate.
+///
+/// # Arguments
+///
+/// * `periodic` - The job's periodic configuration
+/// * `now` - The time to find the next launch after
+pub fn next_run(periodic: &Periodic, now: SystemTime) -> Result<Option<SystemTime>> {
+    if periodic.SpecType != CRON_SPEC_TYPE {
+        return Ok(None);
+    }
+    // Nomad evaluates the spec in the job's time zone, defaulting to UTC
+    let tz: Tz = match periodic.TimeZone.as_deref() {
+        None | Some("") => Tz::UTC,
+        Some(name) => name
+            .parse()
+            .map_err(|err| anyhow!("invalid time zone {}: {}", name, err))?,
+    };
+    let (pattern, seconds) = cron_pattern(&periodic.Spec)?;
+    let mut cron = Cron::new(&pattern);
+    if seconds {
+        cron.with_seconds_required();
+    }
+    let cron = cron
+        .parse()
+        .map_err(|err| anyhow!("invalid cron spec {}: {}", periodic.Spec, err))?;
+    let now = DateTime::<Utc>::from(now).with_timezone(&tz);
+    let next = cron
+        .find_next_occurrence(&now, false)
+        .map_err(|err| anyhow!("no next run for {}: {}", periodic.Spec, err))?;
+    Ok(Some(next.with_timezone(&Utc).into()))
+}
+
+/// Convert a spec laid out the way Nomad reads it into a pattern for `Cron`, along with whether it
+/// has a seconds field. Nomad takes 5 fields as minute to day of week, 6 as those followed by the
+/// year, and 7 as the second, those and the year. Only specs that run in any year can be
+/// evaluated, as the year isn't supported.
+///
+/// # Arguments
+///
+/// * `spec` - The job's cron spec
+fn cron_pattern(spec: &str) -> Result<(String, bool)> {
+    let fields: Vec<&str> = spec.split_whitespace().collect();
+    let (fields, seconds) = match fields.as_slice() {
+        [fields @ .., year] if fields.len() == 5 || fields.len() == 6 => {
+            if *year != "*" {
+                return Err(anyhow!(
+                    "can't evaluate cron spec {}: only specs running in every year are supported",
+                    spec
+                ));
+            }
+            (fields, fields.len() == 6)
+        }
+        fields => (fields, false),
+    };
+    Ok((fields.join(" "), seconds))
+}
+
+/// Check whether a periodic job runs on the given cron expression. Fields are compared one by one,
+/// so differences in spacing don't matter, and specs of any other type never match.
+///
+/// # Arguments
+///
+/// * `periodic` - The job's periodic configuration
+/// * `expr` - The cron expression to compare the job's spec with
+pub fn cron_matches(periodic: &Periodic, expr: &str) -> bool {
+    periodic.SpecType == CRON_SPEC_TYPE
+        && periodic.Spec.split_whitespace().eq(expr.split_whitespace())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn periodic(spec: &str, time_zone: Option<&str>) -> Periodic {
+        Periodic {
+            Enabled: true,
+            Spec: spec.into(),
+            SpecType: "cron".into(),
+            ProhibitOverlap: false,
+            TimeZone: time_zone.map(String::from),
+        }
+    }
+
+    fn at(timestamp: &str) -> SystemTime {
+        humantime::parse_rfc3339(timestamp).unwrap()
+    }
+
+    #[test]
+    fn test_next_run() {
+        let now = at("2020-11-03T00:05:00Z");
+        let hourly = periodic("0 * * * *", None);
+        assert_eq!(
+            next_run(&hourly, now).unwrap(),
+            Some(at("2020-11-03T01:00:00Z"))
+        );
+        // Six fields end with the year, as in Nomad, rather than starting with the second
+        let hourly_any_year = periodic("0 * * * * *", None);
+        assert_eq!(
+            next_run(&hourly_any_year, now).unwrap(),
+            Some(at("2020-11-03T01:00:00Z"))
+        );
+        let every_thirty_seconds = periodic("*/30 * * * * * *", None);
+        assert_eq!(
+            next_run(&every_thirty_seconds, now).unwrap(),
+            Some(at("2020-11-03T00:05:30Z"))
+        );
+        assert!(next_run(&periodic("0 * * * * 2021", None), now).is_err());
+        assert!(next_run(&periodic("*/30 * * * * * 2021", None), now).is_err());
+    }
+
+    #[test]
+    fn test_next_run_time_zone() {
+        // 2am in New York is 7am UTC in November
+        let nightly = periodic("0 2 * * *", Some("America/New_York"));
+        assert_eq!(
+            next_run(&nightly, at("2020-11-03T00:05:00Z")).unwrap(),
+            Some(at("2020-11-03T07:00:00Z"))
+        );
+        let invalid = periodic("0 2 * * *", Some("Mars/Olympus_Mons"));
+        assert!(next_run(&invalid, SystemTime::now()).is_err());
+    }
+
+    #[test]
+    fn test_next_run_unsupported() {
+        let mut spec = periodic("0 * * * *", None);
+        spec.SpecType = String::from("test");
+        assert_eq!(next_run(&spec, SystemTime::now()).unwrap(), None);
+        assert!(next_run(&periodic("not a spec", None), SystemTime::now()).is_err());
+    }
+
+    #[test]
+    fn test_cron_matches() {
+        let nightly = periodic("0 2 * * *", None);
+        assert!(cron_matches(&nightly, "0 2 * * *"));
+        assert!(cron_matches(&nightly, " 0  2 * * * "));
+        assert!(!cron_matches(&nightly, "0 3 * * *"));
+        assert!(!cron_matches(&nightly, "0 2 * *"));
+    }
+
+    #[test]
+    fn test_cron_matches_unsupported() {
+        let mut spec = periodic("0 2 * * *", None);
+        spec.SpecType = String::from("test");
+        assert!(!cron_matches(&spec, "0 2 * * *"));
+    }
+}
@@ -0,0 +1,152 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// The number of single-character insertions, deletions or substitutions needed to turn one
+/// string into another.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            let insertion = current[j] + 1;
+            let deletion = previous[j + 1] + 1;
+            current.push(substitution.min(insertion).min(deletion));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Find the candidate closest to a name, if any is close enough to plausibly be what was meant.
+///
+/// # Arguments
+///
+/// * `name` - The name that wasn't recognized
+/// * `candidates` - The valid names
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(2);
+    candidates
+        .into_iter()
+        .map(|candidate| (levenshtein(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Every field path in a value, written the way `--fields` accepts them, e.g.
+/// `TaskGroups[*].Name`.
+///
+/// # Arguments
+///
+/// * `value` - The value whose fields to list
+pub fn field_paths(value: &Value) -> BTreeSet<String> {
+    field_paths_to_depth(value, None)
+}
+
+/// The field paths in a value, down to a limited depth, where top-level fields are at depth 1.
+///
+/// # Arguments
+///
+/// * `value` - The value whose fields to list
+/// * `max_depth` - If specified, the deepest paths to include
+pub fn field_paths_to_depth(value: &Value, max_depth: Option<usize>) -> BTreeSet<String> {
+    let mut paths = BTreeSet::new();
+    collect_paths(value, "", 1, max_depth, &mut paths);
+    paths
+}
+
+fn collect_paths(
+    value: &Value,
+    prefix: &str,
+    depth: usize,
+    max_depth: Option<usize>,
+    paths: &mut BTreeSet<String>,
+) {
+    if max_depth.is_some_and(|max_depth| depth > max_depth) {
+        return;
+    }
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                collect_paths(child, &path, depth + 1, max_depth, paths);
+                paths.insert(path);
+            }
+        }
+        Value::Array(items) => {
+            // Array elements sit at the same depth as the array itself
+            let path = format!("{}[*]", prefix);
+            for item in items {
+                collect_paths(item, &path, depth, max_depth, paths);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("Priority", "Priority"), 0);
+        assert_eq!(levenshtein("Priorty", "Priority"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "ID"), 2);
+    }
+
+    #[test]
+    fn test_closest() {
+        let candidates = vec!["ID", "Name", "Priority", "Type"];
+        assert_eq!(closest("Priorty", candidates.clone()), Some("Priority"));
+        assert_eq!(closest("name", candidates.clone()), Some("Name"));
+        assert_eq!(closest("Datacenters", candidates), None);
+    }
+
+    #[test]
+    fn test_field_paths() {
+        let value = json!({
+            "ID": "example",
+            "TaskGroups": [{"Name": "cache", "EphemeralDisk": {"SizeMB": 300}}],
+        });
+        let paths: Vec<String> = field_paths(&value).into_iter().collect();
+        assert_eq!(
+            paths,
+            vec![
+                "ID",
+                "TaskGroups",
+                "TaskGroups[*].EphemeralDisk",
+                "TaskGroups[*].EphemeralDisk.SizeMB",
+                "TaskGroups[*].Name",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_field_paths_to_depth() {
+        let value = json!({
+            "ID": "example",
+            "TaskGroups": [{"Name": "cache", "EphemeralDisk": {"SizeMB": 300}}],
+        });
+        let paths: Vec<String> = field_paths_to_depth(&value, Some(1)).into_iter().collect();
+        assert_eq!(paths, vec!["ID", "TaskGroups"]);
+        let paths: Vec<String> = field_paths_to_depth(&value, Some(2)).into_iter().collect();
+        assert_eq!(
+            paths,
+            vec![
+                "ID",
+                "TaskGroups",
+                "TaskGroups[*].EphemeralDisk",
+                "TaskGroups[*].Name",
+            ]
+        );
+    }
+}